@@ -1,6 +1,6 @@
 //! Local LLM agent implementation using Ollama
 
-use super::{LocalAgentError, LocalTask, LocalTaskResult, PreprocessingAgent};
+use super::{LocalAgentError, LocalTask, LocalTaskResult, PreprocessingAgent, TaskComplexity};
 use crate::api::{ApiRequest, ContextItem};
 use async_trait::async_trait;
 use reqwest::Client;
@@ -202,6 +202,30 @@ impl LocalAgent {
 
         self.query(&prompt, Some(system)).await
     }
+
+    async fn classify_complexity(
+        &self,
+        task: &str,
+        context_summary: &str,
+    ) -> Result<TaskComplexity, LocalAgentError> {
+        let prompt = format!(
+            "Classify how capable a coding model this task needs.\n\
+            trivial: mechanical edits such as renames, typos or formatting\n\
+            routine: everyday coding such as small features, tests or simple fixes\n\
+            hard: design, subtle bugs, concurrency, security or large contexts\n\n\
+            Context: {}\n\n\
+            Task: {}\n\n\
+            Answer with one word (trivial, routine or hard):",
+            context_summary, task
+        );
+
+        let system = "You are a task triage assistant. Output only one word.";
+
+        let answer = self.query(&prompt, Some(system)).await?;
+        TaskComplexity::parse(&answer).ok_or_else(|| {
+            LocalAgentError::Inference(format!("Unexpected complexity answer: {}", answer.trim()))
+        })
+    }
 }
 
 #[async_trait]
@@ -231,6 +255,13 @@ impl PreprocessingAgent for LocalAgent {
                 let minimal = self.minimalize_task(&task, &context_summary).await?;
                 Ok(LocalTaskResult::MinimalTask(minimal))
             }
+            LocalTask::ClassifyComplexity {
+                task,
+                context_summary,
+            } => {
+                let complexity = self.classify_complexity(&task, &context_summary).await?;
+                Ok(LocalTaskResult::Complexity(complexity))
+            }
         }
     }
 
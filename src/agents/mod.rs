@@ -11,6 +11,7 @@ use async_trait::async_trait;
 use thiserror::Error;
 
 use crate::api::{ApiRequest, ContextItem};
+use serde::{Deserialize, Serialize};
 
 #[derive(Error, Debug)]
 pub enum LocalAgentError {
@@ -24,6 +25,37 @@ pub enum LocalAgentError {
     Config(String),
 }
 
+/// How capable a model a task needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskComplexity {
+    Trivial,
+    Routine,
+    Hard,
+}
+
+impl TaskComplexity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trivial => "trivial",
+            Self::Routine => "routine",
+            Self::Hard => "hard",
+        }
+    }
+
+    /// Parse a classifier's answer, e.g. "Hard." or "routine", by its first
+    /// word that names a complexity
+    pub fn parse(answer: &str) -> Option<Self> {
+        answer
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .find_map(|word| {
+                [Self::Trivial, Self::Routine, Self::Hard]
+                    .into_iter()
+                    .find(|complexity| word.eq_ignore_ascii_case(complexity.as_str()))
+            })
+    }
+}
+
 /// Tasks that the local agent can perform
 #[derive(Debug, Clone)]
 pub enum LocalTask {
@@ -44,6 +76,9 @@ pub enum LocalTask {
 
     /// Generate a minimal prompt that captures the task requirements
     MinimalizeTask { task: String, context_summary: String },
+
+    /// Judge how capable a model the task needs
+    ClassifyComplexity { task: String, context_summary: String },
 }
 
 /// Result of local agent processing
@@ -54,6 +89,7 @@ pub enum LocalTaskResult {
     OptimizedPrompt(String),
     ExtractedInfo(String),
     MinimalTask(String),
+    Complexity(TaskComplexity),
 }
 
 /// Trait for local preprocessing agents
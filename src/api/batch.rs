@@ -0,0 +1,447 @@
+//! Anthropic Message Batches: deferred requests at half price
+//!
+//! [`ApiAgent::submit_batch`] sends many requests as one batch,
+//! [`ApiAgent::batch_status`] polls it and [`ApiAgent::batch_results`]
+//! downloads the results once processing has ended; [`ApiAgent::run_batch`]
+//! does all three. Most batches finish within an hour, all within 24 hours.
+//! Usage in the results is priced at [`BATCH_DISCOUNT`](super::BATCH_DISCOUNT).
+
+use super::classify::error_from_response;
+use super::format::apply_response_format;
+use super::pricing::ModelPricing;
+use super::{ApiAgent, ApiError, ApiRequest, ApiResponse, ProviderType};
+use crate::metrics::TokenMetrics;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Maximum requests the API accepts in one batch
+pub const MAX_BATCH_REQUESTS: usize = 100_000;
+
+/// A request in a batch, identified by a caller-chosen id
+#[derive(Debug, Clone)]
+pub struct BatchRequest {
+    /// 1-64 characters from `[A-Za-z0-9_-]`, unique within the batch
+    pub custom_id: String,
+    pub request: ApiRequest,
+}
+
+impl BatchRequest {
+    pub fn new(custom_id: impl Into<String>, request: ApiRequest) -> Self {
+        Self {
+            custom_id: custom_id.into(),
+            request,
+        }
+    }
+}
+
+/// A submitted batch, as reported by the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageBatch {
+    pub id: String,
+    pub processing_status: BatchStatus,
+    #[serde(default)]
+    pub request_counts: BatchRequestCounts,
+    /// Where results can be downloaded once the batch has ended
+    #[serde(default)]
+    pub results_url: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+impl MessageBatch {
+    pub fn is_ended(&self) -> bool {
+        self.processing_status == BatchStatus::Ended
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    InProgress,
+    Canceling,
+    Ended,
+}
+
+/// How many requests of a batch are in each state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchRequestCounts {
+    pub processing: u32,
+    pub succeeded: u32,
+    pub errored: u32,
+    pub canceled: u32,
+    pub expired: u32,
+}
+
+impl BatchRequestCounts {
+    pub fn total(&self) -> u32 {
+        self.processing + self.succeeded + self.errored + self.canceled + self.expired
+    }
+}
+
+/// Outcome of one batch request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub custom_id: String,
+    pub status: BatchResultStatus,
+    /// The response, for succeeded requests; usage is priced at batch rates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<ApiResponse>,
+    /// Why the request failed, for errored requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResultStatus {
+    Succeeded,
+    Errored,
+    Canceled,
+    Expired,
+}
+
+impl ApiAgent {
+    /// Submit `requests` as one batch
+    pub async fn submit_batch(&self, requests: &[BatchRequest]) -> Result<MessageBatch, ApiError> {
+        let url = self.batches_url()?;
+        let body = self.batch_body(requests)?;
+
+        let json: Value = self
+            .retry_policy
+            .run(|| async {
+                let response = self.anthropic(self.client.post(&url)).json(&body).send().await?;
+                if response.status().is_success() {
+                    Ok(response.json().await?)
+                } else {
+                    Err(error_from_response(response).await)
+                }
+            })
+            .await?;
+        Ok(serde_json::from_value(json)?)
+    }
+
+    /// Current state of a batch
+    pub async fn batch_status(&self, batch_id: &str) -> Result<MessageBatch, ApiError> {
+        let url = format!("{}/{}", self.batches_url()?, batch_id);
+        let body = self.get_text(&url).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Download the results of an ended batch
+    pub async fn batch_results(&self, batch: &MessageBatch) -> Result<Vec<BatchResult>, ApiError> {
+        let url = batch.results_url.as_deref().ok_or_else(|| {
+            ApiError::InvalidRequest(format!("batch {} has no results yet", batch.id))
+        })?;
+        let body = self.get_text(url).await?;
+        self.parse_batch_results(&body)
+    }
+
+    /// Poll a batch every `poll_interval` until processing has ended
+    pub async fn wait_for_batch(
+        &self,
+        batch_id: &str,
+        poll_interval: Duration,
+        mut on_poll: impl FnMut(&MessageBatch),
+    ) -> Result<MessageBatch, ApiError> {
+        loop {
+            let batch = self.batch_status(batch_id).await?;
+            on_poll(&batch);
+            if batch.is_ended() {
+                return Ok(batch);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Submit a batch, wait for it to end and return its results in the
+    /// order of `requests`. `on_poll` sees the batch after every status check.
+    pub async fn run_batch(
+        &self,
+        requests: &[BatchRequest],
+        poll_interval: Duration,
+        mut on_poll: impl FnMut(&MessageBatch),
+    ) -> Result<Vec<BatchResult>, ApiError> {
+        let batch = self.submit_batch(requests).await?;
+        on_poll(&batch);
+        // Give the batch a head start before the first status check
+        tokio::time::sleep(poll_interval).await;
+        let batch = self.wait_for_batch(&batch.id, poll_interval, on_poll).await?;
+        self.collect_batch(&batch, requests).await
+    }
+
+    /// Results of an ended batch submitted as `requests`, in their order and
+    /// checked against their response formats
+    pub async fn collect_batch(
+        &self,
+        batch: &MessageBatch,
+        requests: &[BatchRequest],
+    ) -> Result<Vec<BatchResult>, ApiError> {
+        let results = self.batch_results(batch).await?;
+        Ok(finish_results(results, requests))
+    }
+
+    /// `<api root>/v1/messages/batches`, whether `base_url` is the messages
+    /// URL or the API root
+    fn batches_url(&self) -> Result<String, ApiError> {
+        if !matches!(self.config.provider, ProviderType::Claude) {
+            return Err(ApiError::InvalidRequest(
+                "message batches require the Anthropic API".to_string(),
+            ));
+        }
+        let base = self.config.base_url.as_deref().unwrap_or("https://api.anthropic.com");
+        let base = base.trim_end_matches('/');
+        let base = base.strip_suffix("/messages").unwrap_or(base);
+        let root = base.strip_suffix("/v1").unwrap_or(base);
+        Ok(format!("{}/v1/messages/batches", root))
+    }
+
+    fn batch_body(&self, requests: &[BatchRequest]) -> Result<Value, ApiError> {
+        if requests.is_empty() || requests.len() > MAX_BATCH_REQUESTS {
+            return Err(ApiError::InvalidRequest(format!(
+                "a batch holds 1 to {} requests, got {}",
+                MAX_BATCH_REQUESTS,
+                requests.len()
+            )));
+        }
+        let mut seen = HashSet::new();
+        for r in requests {
+            let valid = (1..=64).contains(&r.custom_id.len())
+                && r
+                    .custom_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(ApiError::InvalidRequest(format!(
+                    "invalid batch custom_id {:?}: use 1-64 letters, digits, '_' or '-'",
+                    r.custom_id
+                )));
+            }
+            if !seen.insert(r.custom_id.as_str()) {
+                return Err(ApiError::InvalidRequest(format!(
+                    "duplicate batch custom_id {:?}",
+                    r.custom_id
+                )));
+            }
+        }
+
+        let entries: Vec<Value> = requests
+            .iter()
+            .map(|r| {
+                json!({
+                    "custom_id": r.custom_id,
+                    "params": self.build_claude_request(&r.request),
+                })
+            })
+            .collect();
+        Ok(json!({ "requests": entries }))
+    }
+
+    /// Parse the JSONL results file
+    fn parse_batch_results(&self, body: &str) -> Result<Vec<BatchResult>, ApiError> {
+        let mut results = Vec::new();
+        for line in body.lines().filter(|l| !l.trim().is_empty()) {
+            let entry: Value = serde_json::from_str(line)?;
+            let custom_id = entry["custom_id"].as_str().unwrap_or("").to_string();
+            let result = &entry["result"];
+
+            let (status, response, error) = match result["type"].as_str() {
+                Some("succeeded") => {
+                    let mut response = self.parse_claude_response(result["message"].clone())?;
+                    let pricing = ModelPricing::for_model(&response.model)
+                        .or_else(|| ModelPricing::for_model(&self.config.model));
+                    if let Some(pricing) = pricing {
+                        response.usage.estimated_cost_usd =
+                            Some(pricing.batch_cost(&response.usage));
+                    }
+                    (BatchResultStatus::Succeeded, Some(response), None)
+                }
+                Some("errored") => {
+                    let error = &result["error"];
+                    let message = error["error"]["message"]
+                        .as_str()
+                        .or_else(|| error["message"].as_str())
+                        .unwrap_or("unknown error");
+                    (BatchResultStatus::Errored, None, Some(message.to_string()))
+                }
+                Some("canceled") => (BatchResultStatus::Canceled, None, None),
+                Some("expired") => (BatchResultStatus::Expired, None, None),
+                other => {
+                    return Err(ApiError::Provider(format!(
+                        "unknown batch result type {:?}",
+                        other
+                    )))
+                }
+            };
+            results.push(BatchResult {
+                custom_id,
+                status,
+                response,
+                error,
+            });
+        }
+        Ok(results)
+    }
+
+    async fn get_text(&self, url: &str) -> Result<String, ApiError> {
+        self.retry_policy
+            .run(|| async {
+                let response = self.anthropic(self.client.get(url)).send().await?;
+                if response.status().is_success() {
+                    Ok(response.text().await?)
+                } else {
+                    Err(error_from_response(response).await)
+                }
+            })
+            .await
+    }
+
+    fn anthropic(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .header("x-api-key", &self.config.api_key)
+            .header("anthropic-version", "2023-06-01")
+    }
+}
+
+/// Sort `results` into the order of `requests` and check structured output
+/// the way `send_request` does
+fn finish_results(mut results: Vec<BatchResult>, requests: &[BatchRequest]) -> Vec<BatchResult> {
+    let order: HashMap<&str, usize> = requests
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.custom_id.as_str(), i))
+        .collect();
+    results.sort_by_key(|r| order.get(r.custom_id.as_str()).copied().unwrap_or(usize::MAX));
+
+    for result in &mut results {
+        let Some(&i) = order.get(result.custom_id.as_str()) else {
+            continue;
+        };
+        let Some(response) = result.response.take() else {
+            continue;
+        };
+        match apply_response_format(response, requests[i].request.response_format()) {
+            Ok(response) => result.response = Some(response),
+            Err(e) => {
+                result.status = BatchResultStatus::Errored;
+                result.error = Some(e.to_string());
+            }
+        }
+    }
+    results
+}
+
+/// Add succeeded results to `metrics` at their (batch) cost
+pub fn record_batch_metrics(results: &[BatchResult], metrics: &mut TokenMetrics) {
+    for response in results.iter().filter_map(|r| r.response.as_ref()) {
+        metrics.record_request(
+            response.usage.prompt_tokens,
+            response.usage.completion_tokens,
+            0,
+            response.usage.estimated_cost_usd,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiConfig;
+
+    fn claude() -> ApiAgent {
+        ApiAgent::new(ApiConfig {
+            provider: ProviderType::Claude,
+            api_key: "test".to_string(),
+            base_url: Some("https://example.test/v1/messages".to_string()),
+            model: "claude-sonnet-4-20250514".to_string(),
+            max_tokens: Some(1024),
+            temperature: None,
+            num_ctx: None,
+        })
+    }
+
+    #[test]
+    fn test_batch_body_and_validation() {
+        let agent = claude();
+        assert_eq!(agent.batches_url().unwrap(), "https://example.test/v1/messages/batches");
+        let mut proxied = claude();
+        proxied.config.base_url = Some("https://proxy.test/anthropic/".to_string());
+        let url = proxied.batches_url().unwrap();
+        assert_eq!(url, "https://proxy.test/anthropic/v1/messages/batches");
+
+        let requests = vec![
+            BatchRequest::new("doc-1", ApiRequest::new("Document lib.rs".to_string())),
+            BatchRequest::new("doc-2", ApiRequest::new("Document main.rs".to_string())),
+        ];
+        let body = agent.batch_body(&requests).unwrap();
+        assert_eq!(body["requests"][1]["custom_id"], "doc-2");
+        assert_eq!(body["requests"][0]["params"]["max_tokens"], 1024);
+        assert_eq!(
+            body["requests"][0]["params"]["messages"][0]["content"],
+            "Document lib.rs"
+        );
+
+        let bad = vec![BatchRequest::new("has space", ApiRequest::new("x".to_string()))];
+        assert!(agent.batch_body(&bad).is_err());
+        let dup = vec![requests[0].clone(), requests[0].clone()];
+        assert!(agent.batch_body(&dup).is_err());
+    }
+
+    #[test]
+    fn test_parse_results_at_batch_prices() {
+        let body = [
+            json!({
+                "custom_id": "doc-1",
+                "result": {
+                    "type": "succeeded",
+                    "message": {
+                        "model": "claude-sonnet-4-20250514",
+                        "stop_reason": "end_turn",
+                        "content": [{ "type": "text", "text": "Docs." }],
+                        "usage": { "input_tokens": 1_000_000, "output_tokens": 0 }
+                    }
+                }
+            }),
+            json!({
+                "custom_id": "doc-2",
+                "result": {
+                    "type": "errored",
+                    "error": { "type": "error", "error": { "type": "invalid_request_error", "message": "bad" } }
+                }
+            }),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let results = claude().parse_batch_results(&body).unwrap();
+        assert_eq!(results[0].status, BatchResultStatus::Succeeded);
+        let response = results[0].response.as_ref().unwrap();
+        assert_eq!(response.content, "Docs.");
+        // $3 per MTok input, halved
+        assert!((response.usage.estimated_cost_usd.unwrap() - 1.5).abs() < 1e-9);
+        assert_eq!(results[1].error.as_deref(), Some("bad"));
+
+        let mut metrics = TokenMetrics::new();
+        record_batch_metrics(&results, &mut metrics);
+        assert_eq!(metrics.request_count, 1);
+        assert!((metrics.estimated_cost - 1.5).abs() < 1e-9);
+
+        // Results come back in request order, with formats applied
+        let json_task = ApiRequest::new("x".to_string())
+            .with_response_format(crate::api::ResponseFormat::JsonObject);
+        let requests = vec![
+            BatchRequest::new("doc-2", ApiRequest::new("y".to_string())),
+            BatchRequest::new("doc-1", json_task),
+        ];
+        let results = finish_results(results, &requests);
+        assert_eq!(results[0].custom_id, "doc-2");
+        assert_eq!(results[1].status, BatchResultStatus::Errored);
+        assert!(results[1].error.as_deref().unwrap().contains("expected JSON"));
+    }
+}
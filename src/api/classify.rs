@@ -0,0 +1,466 @@
+//! Classification of non-2xx provider responses into [`ApiError`]
+//!
+//! Error bodies are parsed in the shapes used by Anthropic
+//! (`{"type":"error","error":{"type":..,"message":..}}`), OpenAI and Venice
+//! (`{"error":{"type":..,"code":..,"message":..}}`), Gemini
+//! (`{"error":{"code":400,"status":..,"message":..}}`) and Ollama
+//! (`{"error":"..."}`), falling back to the status code alone.
+//!
+//! Rate-limit hints are read from, in order of preference:
+//! - `retry-after-ms` / `retry-after` (seconds)
+//! - `anthropic-ratelimit-{requests,tokens,input-tokens,output-tokens}-reset`
+//!   (RFC 3339 timestamps) for limits whose `-remaining` count is zero
+//! - `x-ratelimit-reset-{requests,tokens}` (OpenAI-style durations such as
+//!   `1s`, `6m0s` or `20ms`) for limits whose `-remaining` count is zero
+
+use super::ApiError;
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Consume an unsuccessful response and turn it into an error
+pub(crate) async fn error_from_response(response: reqwest::Response) -> ApiError {
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let body = response.text().await.unwrap_or_default();
+    classify_error(status, &headers, &body)
+}
+
+/// Map a status code, headers and body to the matching error variant
+pub(crate) fn classify_error(status: u16, headers: &HeaderMap, body: &str) -> ApiError {
+    let retry_after_secs = retry_after(headers).map(ceil_secs);
+    let details = ErrorDetails::parse(body);
+    let message = details.message.clone().unwrap_or_else(|| body.to_string());
+
+    if details.is_quota_exceeded() || status == 402 {
+        return ApiError::QuotaExceeded(message);
+    }
+    // Before the context check: a 429 may complain of "too many tokens" per
+    // minute, which no amount of shrinking fixes
+    match (status, details.kind.as_deref()) {
+        (_, Some("overloaded_error")) | (529, _) => {
+            return ApiError::Overloaded { retry_after_secs }
+        }
+        (_, Some("rate_limit_error")) | (429, _) => {
+            return ApiError::RateLimited { retry_after_secs }
+        }
+        _ => {}
+    }
+    if status == 413 || (status == 400 && details.is_context_too_long()) {
+        let (limit, requested) = context_numbers(&message);
+        return ApiError::ContextTooLong {
+            limit,
+            requested,
+            message,
+        };
+    }
+    if details.is_content_filtered() {
+        return ApiError::ContentFiltered(message);
+    }
+
+    match (status, details.kind.as_deref()) {
+        (401 | 403, _) | (_, Some("authentication_error" | "permission_error")) => {
+            ApiError::Auth(format!("{}: {}", status, message))
+        }
+        (503, _) if retry_after_secs.is_some() => ApiError::Overloaded { retry_after_secs },
+        (500..=599, _) => ApiError::ServerError { status, message },
+        (400 | 404 | 422, _) | (_, Some("invalid_request_error" | "not_found_error")) => {
+            ApiError::InvalidRequest(message)
+        }
+        _ => ApiError::Provider(format!("{}: {}", status, message)),
+    }
+}
+
+/// The interesting fields of a provider error body
+#[derive(Debug, Default)]
+struct ErrorDetails {
+    /// Error type (`invalid_request_error`, `overloaded_error`, ...)
+    kind: Option<String>,
+    /// Machine-readable code (`context_length_exceeded`, ...)
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl ErrorDetails {
+    fn parse(body: &str) -> Self {
+        let Ok(json) = serde_json::from_str::<Value>(body) else {
+            return Self::default();
+        };
+        let text = |v: &Value| v.as_str().map(|s| s.to_string());
+
+        match &json["error"] {
+            Value::Object(_) => Self {
+                kind: text(&json["error"]["type"]).or_else(|| text(&json["error"]["status"])),
+                code: text(&json["error"]["code"]),
+                message: text(&json["error"]["message"]),
+            },
+            Value::String(message) => Self {
+                message: Some(message.clone()),
+                ..Self::default()
+            },
+            _ => Self {
+                kind: text(&json["type"]),
+                code: text(&json["code"]),
+                message: text(&json["message"]).or_else(|| text(&json["detail"])),
+            },
+        }
+    }
+
+    fn code_is(&self, codes: &[&str]) -> bool {
+        self.code.as_deref().is_some_and(|c| codes.contains(&c))
+            || self.kind.as_deref().is_some_and(|k| codes.contains(&k))
+    }
+
+    fn message_lower(&self) -> String {
+        self.message.as_deref().unwrap_or("").to_lowercase()
+    }
+
+    fn is_context_too_long(&self) -> bool {
+        let msg = self.message_lower();
+        self.code_is(&["context_length_exceeded", "request_too_large"])
+            || msg.contains("prompt is too long")
+            || msg.contains("context length")
+            || msg.contains("context window")
+            || msg.contains("maximum context")
+            || msg.contains("too many tokens")
+            || msg.contains("exceeds the maximum number of tokens")
+    }
+
+    fn is_quota_exceeded(&self) -> bool {
+        let msg = self.message_lower();
+        self.code_is(&["insufficient_quota", "billing_error"])
+            || (msg.contains("insufficient")
+                && (msg.contains("balance")
+                    || msg.contains("credit")
+                    || msg.contains("funds")
+                    || msg.contains("quota")))
+            || msg.contains("quota exceeded")
+            || msg.contains("exceeded your current quota")
+    }
+
+    fn is_content_filtered(&self) -> bool {
+        let msg = self.message_lower();
+        self.code_is(&["content_filter", "content_policy_violation"])
+            || msg.contains("content filter")
+            || msg.contains("content policy")
+            || msg.contains("content management policy")
+    }
+}
+
+/// Pull `(limit, requested)` token counts out of a context-length message.
+///
+/// Understands Anthropic's `prompt is too long: 210000 tokens > 200000
+/// maximum`, OpenAI's `maximum context length is 8192 tokens. However,
+/// your messages resulted in 9000 tokens` and Gemini's `The input token count
+/// (1200000) exceeds the maximum number of tokens allowed (1048576)`.
+fn context_numbers(message: &str) -> (Option<u32>, Option<u32>) {
+    let numbers: Vec<u32> = message
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| s.parse().ok())
+        .collect();
+
+    if numbers.len() < 2 {
+        return (None, None);
+    }
+    if message.contains(" > ") || message.contains("exceeds the maximum") {
+        // "<requested> tokens > <limit> maximum"
+        (Some(numbers[1]), Some(numbers[0]))
+    } else {
+        // "maximum context length is <limit> ... resulted in <requested>"
+        (Some(numbers[0]), Some(numbers[1]))
+    }
+}
+
+/// How long the server asked us to wait, if it said so
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(ms) = header_str(headers, "retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(secs) = header_str(headers, "retry-after").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+
+    let now = SystemTime::now();
+    let anthropic = ["requests", "tokens", "input-tokens", "output-tokens"]
+        .iter()
+        .filter(|kind| is_exhausted(headers, &format!("anthropic-ratelimit-{}-remaining", kind)))
+        .filter_map(|kind| header_str(headers, &format!("anthropic-ratelimit-{}-reset", kind)))
+        .filter_map(parse_rfc3339)
+        .map(|reset| reset.duration_since(now).unwrap_or(Duration::ZERO))
+        .max();
+    if anthropic.is_some() {
+        return anthropic;
+    }
+
+    ["requests", "tokens"]
+        .iter()
+        .filter(|kind| is_exhausted(headers, &format!("x-ratelimit-remaining-{}", kind)))
+        .filter_map(|kind| header_str(headers, &format!("x-ratelimit-reset-{}", kind)))
+        .filter_map(parse_go_duration)
+        .max()
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+fn is_exhausted(headers: &HeaderMap, remaining_header: &str) -> bool {
+    header_str(headers, remaining_header).and_then(|v| v.parse::<u64>().ok()) == Some(0)
+}
+
+fn ceil_secs(d: Duration) -> u64 {
+    d.as_secs() + u64::from(d.subsec_nanos() > 0)
+}
+
+/// Parse an RFC 3339 timestamp such as `2024-05-01T12:00:30.5Z` or
+/// `2024-05-01T14:00:30+02:00`
+fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let (date, time) = s.split_once(['T', 't', ' '])?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+
+    // Split the UTC offset off the time of day
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let pos = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(pos);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (h, m) = offset[1..].split_once(':')?;
+        let secs = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+        (clock, sign * secs)
+    };
+
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour: i64 = clock_parts.next()?.parse().ok()?;
+    let minute: i64 = clock_parts.next()?.parse().ok()?;
+    let seconds: f64 = clock_parts.next()?.parse().ok()?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let whole = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60
+        - offset_secs;
+    let total = whole as f64 + seconds;
+    if total < 0.0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs_f64(total))
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse a Go-style duration such as `1s`, `6m0s`, `1h2m3.5s` or `20ms`
+fn parse_go_duration(s: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = s;
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let num_end = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let value: f64 = rest[..num_end].parse().ok()?;
+        rest = &rest[num_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += value * scale;
+        rest = &rest[unit_end..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn test_classify_statuses() {
+        let h = headers(&[("retry-after", "7")]);
+        assert!(matches!(
+            classify_error(429, &h, ""),
+            ApiError::RateLimited {
+                retry_after_secs: Some(7)
+            }
+        ));
+        // A token rate limit is not a context overflow
+        let per_minute = r#"{"error":{"message":"Too many tokens per minute","type":"tokens"}}"#;
+        assert!(matches!(
+            classify_error(429, &HeaderMap::new(), per_minute),
+            ApiError::RateLimited { .. }
+        ));
+        assert!(matches!(
+            classify_error(529, &HeaderMap::new(), "overloaded"),
+            ApiError::Overloaded {
+                retry_after_secs: None
+            }
+        ));
+        assert!(matches!(
+            classify_error(502, &HeaderMap::new(), "bad gateway"),
+            ApiError::ServerError { status: 502, .. }
+        ));
+        assert!(matches!(
+            classify_error(401, &HeaderMap::new(), ""),
+            ApiError::Auth(_)
+        ));
+        assert!(matches!(
+            classify_error(400, &HeaderMap::new(), ""),
+            ApiError::InvalidRequest(_)
+        ));
+        assert!(matches!(
+            classify_error(418, &HeaderMap::new(), ""),
+            ApiError::Provider(_)
+        ));
+    }
+
+    #[test]
+    fn test_anthropic_prompt_too_long() {
+        let body = r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 210000 tokens > 200000 maximum"}}"#;
+        match classify_error(400, &HeaderMap::new(), body) {
+            ApiError::ContextTooLong {
+                limit, requested, ..
+            } => {
+                assert_eq!(limit, Some(200_000));
+                assert_eq!(requested, Some(210_000));
+            }
+            other => panic!("Expected ContextTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_openai_context_length_exceeded() {
+        let body = r#"{"error":{"message":"This model's maximum context length is 8192 tokens. However, your messages resulted in 9000 tokens.","type":"invalid_request_error","param":"messages","code":"context_length_exceeded"}}"#;
+        match classify_error(400, &HeaderMap::new(), body) {
+            ApiError::ContextTooLong {
+                limit, requested, ..
+            } => {
+                assert_eq!(limit, Some(8192));
+                assert_eq!(requested, Some(9000));
+            }
+            other => panic!("Expected ContextTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_gemini_input_too_long() {
+        let body = r#"{"error":{"code":400,"message":"The input token count (1200000) exceeds the maximum number of tokens allowed (1048576).","status":"INVALID_ARGUMENT"}}"#;
+        match classify_error(400, &HeaderMap::new(), body) {
+            ApiError::ContextTooLong {
+                limit, requested, ..
+            } => {
+                assert_eq!(limit, Some(1_048_576));
+                assert_eq!(requested, Some(1_200_000));
+            }
+            other => panic!("Expected ContextTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_typed_error_bodies() {
+        let overloaded = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(matches!(
+            classify_error(500, &HeaderMap::new(), overloaded),
+            ApiError::Overloaded { .. }
+        ));
+
+        let quota = r#"{"error":{"message":"You exceeded your current quota","type":"insufficient_quota","code":"insufficient_quota"}}"#;
+        assert!(matches!(
+            classify_error(429, &HeaderMap::new(), quota),
+            ApiError::QuotaExceeded(_)
+        ));
+
+        let venice = r#"{"error":"Insufficient USD or Diem balance to complete request"}"#;
+        assert!(matches!(
+            classify_error(429, &HeaderMap::new(), venice),
+            ApiError::QuotaExceeded(_)
+        ));
+
+        let filtered = r#"{"error":{"message":"blocked","type":"invalid_request_error","code":"content_filter"}}"#;
+        assert!(matches!(
+            classify_error(400, &HeaderMap::new(), filtered),
+            ApiError::ContentFiltered(_)
+        ));
+
+        let invalid = r#"{"type":"error","error":{"type":"invalid_request_error","message":"max_tokens: must be positive"}}"#;
+        match classify_error(400, &HeaderMap::new(), invalid) {
+            ApiError::InvalidRequest(msg) => assert_eq!(msg, "max_tokens: must be positive"),
+            other => panic!("Expected InvalidRequest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_retry_after_ms_takes_precedence() {
+        let h = headers(&[("retry-after-ms", "1500"), ("retry-after", "9")]);
+        assert_eq!(retry_after(&h), Some(Duration::from_millis(1500)));
+        assert_eq!(ceil_secs(Duration::from_millis(1500)), 2);
+    }
+
+    #[test]
+    fn test_anthropic_reset_headers() {
+        let h = headers(&[
+            ("anthropic-ratelimit-requests-remaining", "10"),
+            ("anthropic-ratelimit-requests-reset", "2999-01-01T00:00:00Z"),
+            ("anthropic-ratelimit-tokens-remaining", "0"),
+            ("anthropic-ratelimit-tokens-reset", "1970-01-01T00:00:00Z"),
+        ]);
+        // Only the exhausted limit counts, and a reset in the past means "now"
+        assert_eq!(retry_after(&h), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_openai_reset_headers() {
+        let h = headers(&[
+            ("x-ratelimit-remaining-requests", "0"),
+            ("x-ratelimit-reset-requests", "6m0s"),
+            ("x-ratelimit-remaining-tokens", "0"),
+            ("x-ratelimit-reset-tokens", "20ms"),
+        ]);
+        assert_eq!(retry_after(&h), Some(Duration::from_secs(360)));
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let t = parse_rfc3339("2024-03-01T12:30:15Z").unwrap();
+        assert_eq!(t.duration_since(UNIX_EPOCH).unwrap().as_secs(), 1_709_296_215);
+        let offset = parse_rfc3339("2024-03-01T14:30:15+02:00").unwrap();
+        assert_eq!(offset, t);
+        assert!(parse_rfc3339("not a date").is_none());
+    }
+
+    #[test]
+    fn test_parse_go_duration() {
+        assert_eq!(parse_go_duration("1h2m3.5s"), Some(Duration::from_secs_f64(3723.5)));
+        assert_eq!(parse_go_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_go_duration("abc"), None);
+    }
+}
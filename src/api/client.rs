@@ -1,18 +1,38 @@
 //! Generic API client for coding agents
 
-use super::sse::{parse_sse_line, SseFormat};
-use super::streaming::{StreamChunk, StreamingProvider};
-use super::{ApiConfig, ApiError, ApiProvider, ApiRequest, ApiResponse, ProviderType, TokenUsage};
+use super::classify::error_from_response;
+use super::format::{
+    anthropic_format_tool, apply_response_format, gemini_response_format, ollama_format,
+    openai_response_format,
+};
+use super::retry::RetryPolicy;
+use super::sampling::{
+    anthropic_sampling, gemini_sampling, ollama_options, openai_sampling, openai_stop_sequence,
+};
+use super::sse::SseFormat;
+use super::streaming::{spawn_sse_reader, StreamChunk, StreamingProvider};
+use super::tools::{
+    anthropic_message_content, anthropic_tool_choice, anthropic_tools, gemini_contents,
+    gemini_tool_config, gemini_tools, ollama_messages, openai_messages, openai_tool_choice,
+    openai_tools, parse_anthropic_content, parse_gemini_parts, parse_ollama_tool_calls,
+    parse_openai_tool_calls,
+};
+use super::{
+    ApiConfig, ApiError, ApiProvider, ApiRequest, ApiResponse, ProviderType, SamplingParams,
+    StopReason, TokenUsage,
+};
+use crate::cache::CacheControl;
+use crate::tokenizer::tokenizer_for_model;
 use async_trait::async_trait;
-use futures_util::StreamExt;
 use reqwest::Client;
 use serde_json::{json, Value};
 use tokio::sync::mpsc;
 
 /// Generic API agent that can work with multiple providers
 pub struct ApiAgent {
-    config: ApiConfig,
-    client: Client,
+    pub(super) config: ApiConfig,
+    pub(super) client: Client,
+    pub(super) retry_policy: RetryPolicy,
 }
 
 impl ApiAgent {
@@ -20,10 +40,22 @@ impl ApiAgent {
         Self {
             config,
             client: Client::new(),
+            retry_policy: RetryPolicy::default(),
         }
     }
 
-    fn build_claude_request(&self, request: &ApiRequest) -> Value {
+    /// Set how rate-limited and transiently failing requests are retried
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// The request's sampling parameters over the configured defaults
+    fn sampling(&self, request: &ApiRequest) -> SamplingParams {
+        request.sampling_or(self.config.temperature, self.config.max_tokens)
+    }
+
+    pub(super) fn build_claude_request(&self, request: &ApiRequest) -> Value {
         let mut messages = Vec::new();
 
         // Build context with cache control support
@@ -35,16 +67,21 @@ impl ApiAgent {
             for (idx, ctx) in request.context.iter().enumerate() {
                 let block_text = format!("### {}\n```\n{}\n```", ctx.name, ctx.content);
 
-                // Check if this item has cache control or is at a breakpoint
-                let has_breakpoint = request.cache_breakpoints.contains(&idx)
-                    || ctx.cache_control.is_some();
+                // Use the item's own cache control, or the default 5m TTL at
+                // a bare breakpoint index
+                let cache_control = ctx.cache_control.clone().or_else(|| {
+                    request
+                        .cache_breakpoints
+                        .contains(&idx)
+                        .then(CacheControl::default)
+                });
 
-                if has_breakpoint {
+                if let Some(control) = cache_control {
                     // Add with cache_control
                     content_blocks.push(json!({
                         "type": "text",
                         "text": block_text,
-                        "cache_control": { "type": "ephemeral" }
+                        "cache_control": control.to_anthropic()
                     }));
                 } else {
                     content_blocks.push(json!({
@@ -60,40 +97,63 @@ impl ApiAgent {
             }));
         }
 
-        // Insert conversation history between context and current task
-        for msg in &request.messages {
+        // Insert conversation history between context and current task, with
+        // rolling cache breakpoints on the latest turns
+        let marked = request.history_breakpoints();
+        for (idx, msg) in request.messages.iter().enumerate() {
             let role = match msg.role {
                 super::Role::User => "user",
                 super::Role::Assistant => "assistant",
                 super::Role::System => continue, // System messages handled separately
             };
+            let mut content = anthropic_message_content(msg);
+            if marked.contains(&idx) {
+                mark_cache_breakpoint(&mut content);
+            }
             messages.push(json!({
                 "role": role,
-                "content": msg.content
+                "content": content
             }));
         }
 
-        // Add the task (always dynamic, no caching)
-        messages.push(json!({
-            "role": "user",
-            "content": request.task
-        }));
+        // Add the task (always dynamic, no caching). An empty task is allowed
+        // when the request only answers tool calls; a non-empty one following
+        // tool results joins that user turn so roles keep alternating.
+        let last_is_tool_results = request
+            .messages
+            .last()
+            .is_some_and(|m| !m.tool_results.is_empty());
+        if last_is_tool_results && !request.task.is_empty() {
+            if let Some(blocks) = messages
+                .last_mut()
+                .and_then(|m| m["content"].as_array_mut())
+            {
+                blocks.push(json!({ "type": "text", "text": request.task }));
+            }
+        } else if !request.task.is_empty() || messages.is_empty() {
+            messages.push(json!({
+                "role": "user",
+                "content": request.task
+            }));
+        }
 
+        let sampling = self.sampling(request);
         let mut body = json!({
             "model": self.config.model,
             "messages": messages,
-            "max_tokens": self.config.max_tokens.unwrap_or(4096),
+            "max_tokens": sampling.max_tokens.unwrap_or(4096),
         });
+        anthropic_sampling(&sampling, &mut body);
 
         // Handle system prompt with optional caching
         if let Some(system) = &request.system {
-            if request.system_cache_control.is_some() {
+            if let Some(control) = &request.system_cache_control {
                 // Use array format with cache_control for cacheable system prompt
                 body["system"] = json!([
                     {
                         "type": "text",
                         "text": system,
-                        "cache_control": { "type": "ephemeral" }
+                        "cache_control": control.to_anthropic()
                     }
                 ]);
             } else {
@@ -101,8 +161,20 @@ impl ApiAgent {
             }
         }
 
-        if let Some(temp) = self.config.temperature {
-            body["temperature"] = json!(temp);
+        // JSON output is requested by forcing a tool whose input is the
+        // response; with other tools present the model may still pick those
+        let format_tool = anthropic_format_tool(request.response_format());
+        let mut tools = request.tools.clone();
+        tools.extend(format_tool.clone());
+        if !tools.is_empty() {
+            body["tools"] = anthropic_tools(&tools);
+        }
+        match (&request.tool_choice, format_tool) {
+            (Some(choice), _) => body["tool_choice"] = anthropic_tool_choice(choice),
+            (None, Some(tool)) if request.tools.is_empty() => {
+                body["tool_choice"] = json!({ "type": "tool", "name": tool.name });
+            }
+            (None, _) => {}
         }
 
         body
@@ -135,90 +207,285 @@ impl ApiAgent {
 
         // Insert conversation history between context and current task
         for msg in &request.messages {
-            let role = match msg.role {
-                super::Role::User => "user",
-                super::Role::Assistant => "assistant",
-                super::Role::System => "system",
-            };
+            messages.extend(openai_messages(msg));
+        }
+
+        // Add the task
+        if !request.task.is_empty() || messages.is_empty() {
             messages.push(json!({
-                "role": role,
-                "content": msg.content
+                "role": "user",
+                "content": request.task
             }));
         }
 
-        // Add the task
-        messages.push(json!({
-            "role": "user",
-            "content": request.task
-        }));
+        let mut body = json!({
+            "model": self.config.model,
+            "messages": messages,
+        });
+
+        // OpenAI itself rejects top_k; compatible servers generally take it
+        let top_k = !matches!(self.config.provider, ProviderType::OpenAI);
+        openai_sampling(&self.sampling(request), &mut body, top_k);
+
+        if !request.tools.is_empty() {
+            body["tools"] = openai_tools(&request.tools);
+        }
+        if let Some(choice) = &request.tool_choice {
+            body["tool_choice"] = openai_tool_choice(choice);
+        }
+        if let Some(format) = openai_response_format(request.response_format()) {
+            body["response_format"] = format;
+        }
+
+        body
+    }
+
+    /// Build a native Ollama `/api/chat` body.
+    ///
+    /// Generation settings go in `options`: `num_ctx` (context window),
+    /// `num_predict` (max output tokens) and the sampling parameters.
+    fn build_ollama_request(&self, request: &ApiRequest) -> Value {
+        let mut messages = Vec::new();
+
+        if let Some(system) = &request.system {
+            messages.push(json!({
+                "role": "system",
+                "content": system
+            }));
+        }
+
+        if !request.context.is_empty() {
+            let context_text = request
+                .context
+                .iter()
+                .map(|c| format!("### {}\n```\n{}\n```", c.name, c.content))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+
+            messages.push(json!({
+                "role": "user",
+                "content": format!("Context:\n{}", context_text)
+            }));
+        }
+
+        messages.extend(ollama_messages(&request.messages));
+
+        if !request.task.is_empty() || messages.is_empty() {
+            messages.push(json!({
+                "role": "user",
+                "content": request.task
+            }));
+        }
+
+        let mut options = serde_json::Map::new();
+        if let Some(num_ctx) = self.config.num_ctx {
+            options.insert("num_ctx".to_string(), json!(num_ctx));
+        }
+        ollama_options(&self.sampling(request), &mut options);
 
         let mut body = json!({
             "model": self.config.model,
             "messages": messages,
+            "stream": false,
         });
 
-        if let Some(max_tokens) = self.config.max_tokens {
-            body["max_tokens"] = json!(max_tokens);
+        if !options.is_empty() {
+            body["options"] = Value::Object(options);
         }
 
-        if let Some(temp) = self.config.temperature {
-            body["temperature"] = json!(temp);
+        // Ollama has no tool_choice; the model always decides
+        if !request.tools.is_empty() {
+            body["tools"] = openai_tools(&request.tools);
+        }
+        if let Some(format) = ollama_format(request.response_format()) {
+            body["format"] = format;
         }
 
         body
     }
 
-    fn parse_claude_response(&self, response: Value) -> Result<ApiResponse, ApiError> {
-        let content = response["content"][0]["text"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
-
-        // Extract cache-related token counts
-        let cache_creation = response["usage"]["cache_creation_input_tokens"]
-            .as_u64()
-            .map(|t| t as u32);
-        let cache_read = response["usage"]["cache_read_input_tokens"]
-            .as_u64()
-            .map(|t| t as u32);
-
-        let usage = TokenUsage::with_cache(
-            response["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32,
-            response["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32,
-            cache_creation,
-            cache_read,
-        );
+    /// Gemini endpoint for the configured model.
+    ///
+    /// For Gemini, `base_url` is the API root (e.g.
+    /// `https://generativelanguage.googleapis.com/v1beta`), not a full URL.
+    fn gemini_url(&self, streaming: bool) -> String {
+        let base = self
+            .config
+            .base_url
+            .as_deref()
+            .unwrap_or("https://generativelanguage.googleapis.com/v1beta")
+            .trim_end_matches('/');
+        if streaming {
+            format!("{}/models/{}:streamGenerateContent?alt=sse", base, self.config.model)
+        } else {
+            format!("{}/models/{}:generateContent", base, self.config.model)
+        }
+    }
+
+    fn build_gemini_request(&self, request: &ApiRequest) -> Value {
+        let mut contents = Vec::new();
+
+        if !request.context.is_empty() {
+            let context_text = request
+                .context
+                .iter()
+                .map(|c| format!("### {}\n```\n{}\n```", c.name, c.content))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+
+            contents.push(json!({
+                "role": "user",
+                "parts": [{ "text": format!("Context:\n{}", context_text) }]
+            }));
+        }
+
+        contents.extend(gemini_contents(&request.messages));
+
+        if !request.task.is_empty() || contents.is_empty() {
+            contents.push(json!({
+                "role": "user",
+                "parts": [{ "text": request.task }]
+            }));
+        }
+
+        let mut body = json!({ "contents": contents });
+
+        if let Some(system) = &request.system {
+            body["systemInstruction"] = json!({ "parts": [{ "text": system }] });
+        }
+
+        let mut generation_config = serde_json::Map::new();
+        gemini_sampling(&self.sampling(request), &mut generation_config);
+        gemini_response_format(request.response_format(), &mut generation_config);
+        if !generation_config.is_empty() {
+            body["generationConfig"] = Value::Object(generation_config);
+        }
+
+        if !request.tools.is_empty() {
+            body["tools"] = gemini_tools(&request.tools);
+        }
+        if let Some(choice) = &request.tool_choice {
+            body["toolConfig"] = gemini_tool_config(choice);
+        }
+
+        body
+    }
+
+    fn parse_gemini_response(&self, response: Value) -> Result<ApiResponse, ApiError> {
+        let candidate = &response["candidates"][0];
+        let (content, _thinking, tool_calls) = parse_gemini_parts(&candidate["content"]["parts"]);
+        let finish_reason = candidate["finishReason"].as_str();
+
+        let stop_reason = if tool_calls.is_empty() {
+            finish_reason.and_then(StopReason::from_gemini)
+        } else {
+            Some(StopReason::ToolUse)
+        };
+
+        Ok(ApiResponse {
+            content,
+            usage: TokenUsage::from_gemini(&response["usageMetadata"]),
+            model: response["modelVersion"]
+                .as_str()
+                .unwrap_or(&self.config.model)
+                .to_string(),
+            truncated: finish_reason == Some("MAX_TOKENS"),
+            stop_reason,
+            stop_sequence: None,
+            tool_calls,
+        })
+    }
+
+    pub(super) fn parse_claude_response(
+        &self,
+        response: Value,
+    ) -> Result<ApiResponse, ApiError> {
+        let (content, tool_calls) = parse_anthropic_content(&response["content"]);
+
+        // Token counts, cache writes and reads included
+        let usage = TokenUsage::from_anthropic(&response["usage"]);
 
         Ok(ApiResponse {
             content,
             usage,
             model: response["model"].as_str().unwrap_or("").to_string(),
             truncated: response["stop_reason"].as_str() == Some("max_tokens"),
-            stop_reason: None,
+            stop_reason: response["stop_reason"]
+                .as_str()
+                .and_then(StopReason::from_anthropic),
+            stop_sequence: response["stop_sequence"].as_str().map(str::to_string),
+            tool_calls,
         })
     }
 
-    fn parse_openai_response(&self, response: Value) -> Result<ApiResponse, ApiError> {
-        let content = response["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
+    fn parse_ollama_response(&self, response: Value) -> Result<ApiResponse, ApiError> {
+        let message = &response["message"];
+        let content = message["content"].as_str().unwrap_or("").to_string();
+        let tool_calls = parse_ollama_tool_calls(message);
 
         let usage = TokenUsage::new(
-            response["usage"]["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-            response["usage"]["completion_tokens"].as_u64().unwrap_or(0) as u32,
+            response["prompt_eval_count"].as_u64().unwrap_or(0) as u32,
+            response["eval_count"].as_u64().unwrap_or(0) as u32,
         );
 
+        let stop_reason = if tool_calls.is_empty() {
+            response["done_reason"]
+                .as_str()
+                .and_then(StopReason::from_openai)
+        } else {
+            Some(StopReason::ToolUse)
+        };
+
+        Ok(ApiResponse {
+            content,
+            usage,
+            model: response["model"]
+                .as_str()
+                .unwrap_or(&self.config.model)
+                .to_string(),
+            truncated: response["done_reason"].as_str() == Some("length"),
+            stop_reason,
+            stop_sequence: None,
+            tool_calls,
+        })
+    }
+
+    fn parse_openai_response(&self, response: Value) -> Result<ApiResponse, ApiError> {
+        let choice = &response["choices"][0];
+        let message = &choice["message"];
+        let content = message["content"].as_str().unwrap_or("").to_string();
+        let tool_calls = parse_openai_tool_calls(message);
+
+        let usage = TokenUsage::from_openai(&response["usage"]);
+
         Ok(ApiResponse {
             content,
             usage,
             model: response["model"].as_str().unwrap_or("").to_string(),
-            truncated: response["choices"][0]["finish_reason"].as_str() == Some("length"),
-            stop_reason: None,
+            truncated: choice["finish_reason"].as_str() == Some("length"),
+            stop_reason: match openai_stop_sequence(choice) {
+                Some(_) => Some(StopReason::StopSequence),
+                None => choice["finish_reason"].as_str().and_then(StopReason::from_openai),
+            },
+            stop_sequence: openai_stop_sequence(choice),
+            tool_calls,
         })
     }
 }
 
+/// Put a 5-minute cache breakpoint on the last block of a message's content
+fn mark_cache_breakpoint(content: &mut Value) {
+    if let Some(text) = content.as_str() {
+        if text.is_empty() {
+            return;
+        }
+        *content = json!([{ "type": "text", "text": text }]);
+    }
+    if let Some(block) = content.as_array_mut().and_then(|blocks| blocks.last_mut()) {
+        block["cache_control"] = CacheControl::default().to_anthropic();
+    }
+}
+
 #[async_trait]
 impl ApiProvider for ApiAgent {
     async fn send_request(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
@@ -251,9 +518,17 @@ impl ApiProvider for ApiAgent {
                     .base_url
                     .clone()
                     .unwrap_or_else(|| "http://localhost:11434/api/chat".to_string());
-                let body = self.build_openai_request(&request); // Ollama uses OpenAI-compatible format
+                let body = self.build_ollama_request(&request);
                 (url, body, ("Authorization", String::new()))
             }
+            ProviderType::Gemini => {
+                let body = self.build_gemini_request(&request);
+                (
+                    self.gemini_url(false),
+                    body,
+                    ("x-goog-api-key", self.config.api_key.clone()),
+                )
+            }
             ProviderType::Custom => {
                 let url = self
                     .config
@@ -269,33 +544,38 @@ impl ApiProvider for ApiAgent {
             }
         };
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("anthropic-version", "2023-06-01") // For Claude
-            .header(auth_header.0, auth_header.1)
-            .json(&body)
-            .send()
+        let json: Value = self
+            .retry_policy
+            .run(|| async {
+                let response = self
+                    .client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .header("anthropic-version", "2023-06-01") // For Claude
+                    .header(auth_header.0, &auth_header.1)
+                    .json(&body)
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    Ok(response.json().await?)
+                } else {
+                    Err(error_from_response(response).await)
+                }
+            })
             .await?;
 
-        if response.status().is_success() {
-            let json: Value = response.json().await?;
-            match self.config.provider {
-                ProviderType::Claude => self.parse_claude_response(json),
-                _ => self.parse_openai_response(json),
-            }
-        } else {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            Err(ApiError::Provider(format!("{}: {}", status, error_text)))
-        }
+        let response = match self.config.provider {
+            ProviderType::Claude => self.parse_claude_response(json),
+            ProviderType::Ollama => self.parse_ollama_response(json),
+            ProviderType::Gemini => self.parse_gemini_response(json),
+            _ => self.parse_openai_response(json),
+        }?;
+        apply_response_format(response, request.response_format())
     }
 
     fn estimate_tokens(&self, text: &str) -> usize {
-        // Simple estimation: ~4 characters per token on average
-        // For accurate counts, use tiktoken
-        text.len() / 4
+        tokenizer_for_model(&self.config.model).count(text)
     }
 
     fn provider_type(&self) -> ProviderType {
@@ -347,7 +627,7 @@ impl StreamingProvider for ApiAgent {
                     .base_url
                     .clone()
                     .unwrap_or_else(|| "http://localhost:11434/api/chat".to_string());
-                let body = self.build_openai_request(&request);
+                let body = self.build_ollama_request(&request);
                 (
                     SseFormat::Ollama,
                     url,
@@ -355,6 +635,15 @@ impl StreamingProvider for ApiAgent {
                     ("Authorization".to_string(), String::new()),
                 )
             }
+            ProviderType::Gemini => {
+                let body = self.build_gemini_request(&request);
+                (
+                    SseFormat::Gemini,
+                    self.gemini_url(true),
+                    body,
+                    ("x-goog-api-key".to_string(), self.config.api_key.clone()),
+                )
+            }
             ProviderType::Custom => {
                 let url = self
                     .config
@@ -374,66 +663,355 @@ impl StreamingProvider for ApiAgent {
             }
         };
 
-        // Enable streaming in the request body
-        body["stream"] = json!(true);
+        // Enable streaming in the request body (Gemini streams by endpoint)
+        if !matches!(self.config.provider, ProviderType::Gemini) {
+            body["stream"] = json!(true);
+        }
+        // OpenAI only reports usage, cached tokens included, when asked to
+        if matches!(self.config.provider, ProviderType::OpenAI) {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
 
+        // Only establishing the stream is retried; a stream that fails
+        // midway surfaces as a StreamChunk::Error
         let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("anthropic-version", "2023-06-01")
-            .header(&auth_header.0, &auth_header.1)
-            .json(&body)
-            .send()
+            .retry_policy
+            .run(|| async {
+                let response = self
+                    .client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .header("anthropic-version", "2023-06-01")
+                    .header(&auth_header.0, &auth_header.1)
+                    .json(&body)
+                    .send()
+                    .await?;
+
+                if response.status().is_success() {
+                    Ok(response)
+                } else {
+                    Err(error_from_response(response).await)
+                }
+            })
             .await?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(ApiError::Provider(format!("{}: {}", status, error_text)));
-        }
+        Ok(spawn_sse_reader(response, sse_format))
+    }
+}
 
-        let (tx, rx) = mpsc::channel(64);
-
-        tokio::spawn(async move {
-            let mut stream = response.bytes_stream();
-            let mut buffer = String::new();
-
-            while let Some(chunk_result) = stream.next().await {
-                match chunk_result {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-                        // Process complete lines
-                        while let Some(newline_pos) = buffer.find('\n') {
-                            let line = buffer[..newline_pos].to_string();
-                            buffer = buffer[newline_pos + 1..].to_string();
-
-                            if let Some(chunk) = parse_sse_line(&line, sse_format) {
-                                let is_done = matches!(chunk, StreamChunk::Done(_));
-                                let is_error = matches!(chunk, StreamChunk::Error(_));
-                                if tx.send(chunk).await.is_err() {
-                                    return; // Receiver dropped
-                                }
-                                if is_done || is_error {
-                                    return;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        let _ = tx
-                            .send(StreamChunk::Error(format!("Stream error: {}", e)))
-                            .await;
-                        return;
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        ContextItem, ContextType, Message, ToolCall, ToolChoice, ToolDefinition, ToolResult,
+    };
+
+    fn agent(provider: ProviderType) -> ApiAgent {
+        ApiAgent::new(ApiConfig {
+            provider,
+            api_key: "test".to_string(),
+            base_url: None,
+            model: "test-model".to_string(),
+            max_tokens: Some(1024),
+            temperature: None,
+            num_ctx: None,
+        })
+    }
+
+    fn read_file_tool() -> ToolDefinition {
+        ToolDefinition::new(
+            "read_file",
+            "Read a file from disk",
+            json!({ "type": "object", "properties": { "path": { "type": "string" } } }),
+        )
+    }
+
+    #[test]
+    fn test_claude_request_serializes_tools_and_results() {
+        let call = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "read_file".to_string(),
+            input: json!({ "path": "src/lib.rs" }),
+        };
+        let mut request = ApiRequest::new(String::new()).with_tools(vec![read_file_tool()]);
+        request.messages = vec![
+            Message::user("Show me lib.rs"),
+            Message::assistant_with_tool_calls("", vec![call]),
+        ];
+        let request = request.with_tool_results(vec![ToolResult::success("toolu_1", "pub mod api;")]);
+
+        let body = agent(ProviderType::Claude).build_claude_request(&request);
+
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[2]["content"][0]["type"], "tool_result");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "toolu_1");
+    }
+
+    #[test]
+    fn test_claude_history_gets_rolling_breakpoints() {
+        let mut request = ApiRequest::new("And now?".to_string()).with_cached_system("sys".into());
+        request.messages = vec![
+            Message::user("one"),
+            Message::assistant("two"),
+            Message::user("three"),
+            Message::assistant("four"),
+        ];
+        let body = agent(ProviderType::Claude).build_claude_request(&request);
+        let messages = body["messages"].as_array().unwrap();
+        let marked: Vec<_> = messages
+            .iter()
+            .map(|m| m["content"][0]["cache_control"].is_object())
+            .collect();
+        assert_eq!(marked, vec![false, true, false, true, false]);
+        assert_eq!(messages[3]["content"][0]["text"], "four");
+        assert_eq!(messages[4]["content"], "And now?");
+
+        // Context breakpoints leave room for only one history breakpoint
+        let context = (0..2)
+            .map(|i| ContextItem {
+                name: format!("doc{}", i),
+                content: "docs".to_string(),
+                item_type: ContextType::Documentation,
+                relevance: None,
+                cache_control: Some(CacheControl::default()),
+                is_static: true,
+            })
+            .collect();
+        let request = request.with_context(context);
+        let body = agent(ProviderType::Claude).build_claude_request(&request);
+        let marked = body["messages"].as_array().unwrap()[1..]
+            .iter()
+            .filter(|m| m["content"][0]["cache_control"].is_object())
+            .count();
+        assert_eq!(marked, 1);
+    }
+
+    #[test]
+    fn test_openai_request_serializes_tools() {
+        let request = ApiRequest::new("List files".to_string())
+            .with_tools(vec![read_file_tool()])
+            .with_tool_choice(crate::api::ToolChoice::Any);
+
+        let body = agent(ProviderType::OpenAI).build_openai_request(&request);
+
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "read_file");
+        assert_eq!(body["tool_choice"], "required");
+    }
+
+    #[test]
+    fn test_json_schema_format_per_provider() {
+        let format = crate::api::ResponseFormat::json_schema(
+            "answer",
+            json!({ "type": "object", "required": ["ok"] }),
+        );
+        let request = ApiRequest::new("Reply in JSON".to_string()).with_response_format(format);
+
+        let claude = agent(ProviderType::Claude).build_claude_request(&request);
+        assert_eq!(claude["tools"][0]["name"], "answer");
+        assert_eq!(claude["tool_choice"]["name"], "answer");
+
+        let openai = agent(ProviderType::OpenAI).build_openai_request(&request);
+        assert_eq!(openai["response_format"]["json_schema"]["schema"]["required"][0], "ok");
+
+        let ollama = agent(ProviderType::Ollama).build_ollama_request(&request);
+        assert_eq!(ollama["format"]["type"], "object");
+
+        let plain = agent(ProviderType::OpenAI).build_openai_request(&ApiRequest::new("hi".into()));
+        assert!(plain.get("response_format").is_none());
+    }
+
+    #[test]
+    fn test_request_sampling_overrides_config_per_provider() {
+        let sampling = crate::api::SamplingParams::new()
+            .with_temperature(0.1)
+            .with_top_p(0.9)
+            .with_top_k(40)
+            .with_stop(["</answer>"])
+            .with_seed(7)
+            .with_frequency_penalty(0.5);
+        let request = ApiRequest::new("hi".to_string()).with_sampling(sampling);
+
+        let claude = agent(ProviderType::Claude).build_claude_request(&request);
+        assert_eq!(claude["max_tokens"], 1024);
+        assert_eq!(claude["top_k"], 40);
+        assert_eq!(claude["stop_sequences"][0], "</answer>");
+        assert!(claude.get("seed").is_none());
+
+        let openai = agent(ProviderType::OpenAI).build_openai_request(&request);
+        assert_eq!(openai["stop"][0], "</answer>");
+        assert_eq!(openai["seed"], 7);
+        assert_eq!(openai["frequency_penalty"], 0.5);
+        assert!(openai.get("top_k").is_none());
+        let custom = agent(ProviderType::Custom).build_openai_request(&request);
+        assert_eq!(custom["top_k"], 40);
+
+        let ollama = agent(ProviderType::Ollama).build_ollama_request(&request);
+        assert_eq!(ollama["options"]["stop"][0], "</answer>");
+        assert_eq!(ollama["options"]["seed"], 7);
+
+        let gemini = agent(ProviderType::Gemini).build_gemini_request(&request);
+        assert_eq!(gemini["generationConfig"]["topK"], 40);
+        assert_eq!(gemini["generationConfig"]["stopSequences"][0], "</answer>");
+    }
+
+    #[test]
+    fn test_parse_stop_sequence() {
+        let claude = json!({
+            "model": "claude",
+            "stop_reason": "stop_sequence",
+            "stop_sequence": "</answer>",
+            "content": [{ "type": "text", "text": "42" }],
+            "usage": { "input_tokens": 1, "output_tokens": 1 }
+        });
+        let parsed = agent(ProviderType::Claude).parse_claude_response(claude).unwrap();
+        assert_eq!(parsed.stop_reason, Some(StopReason::StopSequence));
+        assert_eq!(parsed.stop_sequence.as_deref(), Some("</answer>"));
+
+        // vLLM-style servers name the matched sequence in `stop_reason`
+        let vllm = json!({
+            "choices": [{
+                "finish_reason": "stop",
+                "stop_reason": "</answer>",
+                "message": { "content": "42" }
+            }]
+        });
+        let parsed = agent(ProviderType::Custom).parse_openai_response(vllm).unwrap();
+        assert_eq!(parsed.stop_reason, Some(StopReason::StopSequence));
+        assert_eq!(parsed.stop_sequence.as_deref(), Some("</answer>"));
+    }
+
+    #[test]
+    fn test_parse_claude_tool_use_response() {
+        let response = json!({
+            "model": "claude",
+            "stop_reason": "tool_use",
+            "content": [
+                { "type": "text", "text": "Reading." },
+                { "type": "tool_use", "id": "toolu_9", "name": "read_file", "input": { "path": "a" } }
+            ],
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        });
+
+        let parsed = agent(ProviderType::Claude).parse_claude_response(response).unwrap();
+
+        assert_eq!(parsed.content, "Reading.");
+        assert_eq!(parsed.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(parsed.tool_calls[0].id, "toolu_9");
+    }
+
+    #[test]
+    fn test_parse_openai_tool_call_response() {
+        let response = json!({
+            "model": "gpt",
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "read_file", "arguments": "{\"path\":\"b\"}" }
+                    }]
                 }
-            }
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 4 }
+        });
+
+        let parsed = agent(ProviderType::OpenAI).parse_openai_response(response).unwrap();
 
-            // If stream ends without a Done chunk, send one
-            let _ = tx.send(StreamChunk::Done(TokenUsage::default())).await;
+        assert!(parsed.has_tool_calls());
+        assert_eq!(parsed.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(parsed.tool_calls[0].input["path"], "b");
+    }
+
+    #[test]
+    fn test_ollama_request_uses_native_options() {
+        let mut ollama = agent(ProviderType::Ollama);
+        ollama.config.num_ctx = Some(32_768);
+        ollama.config.temperature = Some(0.2);
+        let request = ApiRequest::new("hi".to_string()).with_tools(vec![read_file_tool()]);
+
+        let body = ollama.build_ollama_request(&request);
+
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["options"]["num_ctx"], 32_768);
+        assert_eq!(body["options"]["num_predict"], 1024);
+        assert!(body.get("max_tokens").is_none());
+        assert_eq!(body["tools"][0]["function"]["name"], "read_file");
+        assert_eq!(body["messages"][0]["content"], "hi");
+    }
+
+    #[test]
+    fn test_parse_ollama_response_reports_usage() {
+        let response = json!({
+            "model": "llama3.2",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{ "function": { "name": "read_file", "arguments": { "path": "c" } } }]
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 26,
+            "eval_count": 12
+        });
+
+        let parsed = agent(ProviderType::Ollama).parse_ollama_response(response).unwrap();
+
+        assert_eq!(parsed.usage.prompt_tokens, 26);
+        assert_eq!(parsed.usage.completion_tokens, 12);
+        assert_eq!(parsed.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(parsed.tool_calls[0].id, "call_0");
+        assert_eq!(parsed.tool_calls[0].input["path"], "c");
+    }
+
+    #[test]
+    fn test_gemini_request_shape() {
+        let mut gemini = agent(ProviderType::Gemini);
+        gemini.config.base_url = Some("https://example.test/v1beta/".to_string());
+        let mut request = ApiRequest::new("hi".to_string())
+            .with_tools(vec![read_file_tool()])
+            .with_tool_choice(ToolChoice::Any);
+        request.system = Some("be brief".to_string());
+
+        let body = gemini.build_gemini_request(&request);
+
+        assert_eq!(
+            gemini.gemini_url(true),
+            "https://example.test/v1beta/models/test-model:streamGenerateContent?alt=sse"
+        );
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+        assert_eq!(body["contents"][0]["parts"][0]["text"], "hi");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 1024);
+        assert_eq!(body["tools"][0]["functionDeclarations"][0]["name"], "read_file");
+        assert_eq!(body["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+    }
+
+    #[test]
+    fn test_parse_gemini_response_with_cached_tokens() {
+        let response = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Done." }] },
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 5000,
+                "candidatesTokenCount": 20,
+                "cachedContentTokenCount": 4096
+            },
+            "modelVersion": "gemini-2.5-flash"
         });
 
-        Ok(rx)
+        let parsed = agent(ProviderType::Gemini).parse_gemini_response(response).unwrap();
+
+        assert_eq!(parsed.content, "Done.");
+        assert_eq!(parsed.stop_reason, Some(StopReason::EndTurn));
+        assert_eq!(parsed.usage.prompt_tokens, 904);
+        assert_eq!(parsed.usage.cache_read_tokens, Some(4096));
+        assert_eq!(parsed.model, "gemini-2.5-flash");
     }
 }
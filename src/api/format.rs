@@ -0,0 +1,420 @@
+//! Structured (JSON) output
+//!
+//! A [`ResponseFormat`] on the request's constraints asks the provider for
+//! JSON: OpenAI-compatible APIs get `response_format`, Ollama `format` and
+//! Gemini `responseMimeType`/`responseJsonSchema`. Anthropic has no JSON
+//! mode, so the request forces a call to a synthetic tool whose input schema
+//! is the requested one, and that tool input becomes the response content.
+//!
+//! Whatever the provider, the returned text is checked before it is handed
+//! back: surrounding prose and code fences are stripped, and the JSON must
+//! parse and match the schema, or the request fails with
+//! [`ApiError::InvalidResponse`]. Streamed responses are passed through as
+//! they arrive and are not validated.
+
+use super::tools::ToolDefinition;
+use super::{ApiError, ApiResponse, StopReason};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Tool name used for Anthropic when the format has no name of its own
+pub const JSON_TOOL_NAME: &str = "json_response";
+
+/// What the response content must look like
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Free-form text
+    #[default]
+    Text,
+    /// Any JSON object
+    JsonObject,
+    /// JSON matching a JSON Schema
+    JsonSchema {
+        /// Schema name, sent to providers that label schemas
+        name: String,
+        schema: Value,
+        /// Ask the provider to enforce the schema exactly (OpenAI `strict`)
+        #[serde(default)]
+        strict: bool,
+    },
+}
+
+impl ResponseFormat {
+    /// JSON matching `schema`, enforced strictly where the provider can.
+    /// `name` is reduced to the letters, digits, `_` and `-` providers
+    /// accept in schema and tool names.
+    pub fn json_schema(name: impl Into<String>, schema: Value) -> Self {
+        ResponseFormat::JsonSchema {
+            name: schema_name(&name.into()),
+            schema,
+            strict: true,
+        }
+    }
+
+    /// Whether the provider should enforce the schema exactly. OpenAI's
+    /// strict mode rejects schemas with optional properties or without
+    /// `additionalProperties: false`.
+    pub fn with_strict(mut self, enforce: bool) -> Self {
+        if let ResponseFormat::JsonSchema { strict, .. } = &mut self {
+            *strict = enforce;
+        }
+        self
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, ResponseFormat::Text)
+    }
+
+    /// Parse `content` as this format's JSON and check it against the schema.
+    ///
+    /// Tolerates a markdown code fence or prose around the JSON value.
+    pub fn validate(&self, content: &str) -> Result<Value, ApiError> {
+        let value = extract_json(content).ok_or_else(|| {
+            ApiError::InvalidResponse(format!("expected JSON, got: {}", preview(content)))
+        })?;
+        match self {
+            ResponseFormat::Text => {}
+            ResponseFormat::JsonObject => {
+                if !value.is_object() {
+                    return Err(ApiError::InvalidResponse(format!(
+                        "expected a JSON object, got: {}",
+                        preview(content)
+                    )));
+                }
+            }
+            ResponseFormat::JsonSchema { schema, .. } => {
+                let mut errors = Vec::new();
+                check_schema(&value, schema, "$", &mut errors);
+                if !errors.is_empty() {
+                    return Err(ApiError::InvalidResponse(format!(
+                        "response does not match schema: {}",
+                        errors.join("; ")
+                    )));
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Schema the output must match; `JsonObject` accepts any object
+    fn schema(&self) -> Option<Value> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(json!({ "type": "object" })),
+            ResponseFormat::JsonSchema { schema, .. } => Some(schema.clone()),
+        }
+    }
+
+    /// Name of the synthetic Anthropic tool
+    fn tool_name(&self) -> &str {
+        match self {
+            ResponseFormat::JsonSchema { name, .. } if !name.is_empty() => name,
+            _ => JSON_TOOL_NAME,
+        }
+    }
+}
+
+/// `name` as a valid schema name: at most 64 of `[a-zA-Z0-9_-]`
+fn schema_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .take(64)
+        .collect();
+    if name.is_empty() {
+        JSON_TOOL_NAME.to_string()
+    } else {
+        name
+    }
+}
+
+// ─── Provider wire formats ──────────────────────────────────────────────────
+
+/// OpenAI `response_format`
+pub(crate) fn openai_response_format(format: &ResponseFormat) -> Option<Value> {
+    match format {
+        ResponseFormat::Text => None,
+        ResponseFormat::JsonObject => Some(json!({ "type": "json_object" })),
+        ResponseFormat::JsonSchema {
+            name,
+            schema,
+            strict,
+        } => Some(json!({
+            "type": "json_schema",
+            "json_schema": { "name": name, "schema": schema, "strict": strict },
+        })),
+    }
+}
+
+/// Ollama `format`: `"json"` or the schema itself
+pub(crate) fn ollama_format(format: &ResponseFormat) -> Option<Value> {
+    match format {
+        ResponseFormat::Text => None,
+        ResponseFormat::JsonObject => Some(json!("json")),
+        ResponseFormat::JsonSchema { schema, .. } => Some(schema.clone()),
+    }
+}
+
+/// Add Gemini's JSON settings to a `generationConfig`
+pub(crate) fn gemini_response_format(format: &ResponseFormat, config: &mut Map<String, Value>) {
+    if format.is_text() {
+        return;
+    }
+    config.insert("responseMimeType".to_string(), json!("application/json"));
+    if let ResponseFormat::JsonSchema { schema, .. } = format {
+        config.insert("responseJsonSchema".to_string(), schema.clone());
+    }
+}
+
+/// Synthetic tool whose input is the structured response (Anthropic)
+pub(crate) fn anthropic_format_tool(format: &ResponseFormat) -> Option<ToolDefinition> {
+    format.schema().map(|schema| {
+        ToolDefinition::new(
+            format.tool_name(),
+            "Respond by calling this tool; its input is the complete response.",
+            schema,
+        )
+    })
+}
+
+/// Check a parsed response against the requested format.
+///
+/// Input of the synthetic Anthropic tool becomes the content, and JSON text
+/// is replaced by the bare JSON value. A response that only calls the
+/// request's own tools is returned unchanged.
+pub(crate) fn apply_response_format(
+    mut response: ApiResponse,
+    format: &ResponseFormat,
+) -> Result<ApiResponse, ApiError> {
+    if format.is_text() {
+        return Ok(response);
+    }
+
+    let tool_name = format.tool_name();
+    if let Some(pos) = response.tool_calls.iter().position(|c| c.name == tool_name) {
+        let call = response.tool_calls.remove(pos);
+        response.content = call.input.to_string();
+        if response.tool_calls.is_empty() && response.stop_reason == Some(StopReason::ToolUse) {
+            response.stop_reason = Some(StopReason::EndTurn);
+        }
+    } else if response.has_tool_calls() && response.content.trim().is_empty() {
+        return Ok(response);
+    }
+
+    let value = format.validate(&response.content)?;
+    response.content = value.to_string();
+    Ok(response)
+}
+
+// ─── Validation ─────────────────────────────────────────────────────────────
+
+/// The JSON value in `content`: the whole text, a fenced block, or the
+/// outermost `{...}` / `[...]` span
+fn extract_json(content: &str) -> Option<Value> {
+    let trimmed = content.trim();
+    if let Ok(value) = serde_json::from_str(trimmed) {
+        return Some(value);
+    }
+
+    if let Some(start) = trimmed.find("```") {
+        let fenced = &trimmed[start + 3..];
+        // Skip the info string (e.g. "json")
+        let body = fenced.split_once('\n').map(|(_, rest)| rest).unwrap_or(fenced);
+        if let Some(end) = body.find("```") {
+            if let Ok(value) = serde_json::from_str(body[..end].trim()) {
+                return Some(value);
+            }
+        }
+    }
+
+    let start = trimmed.find(['{', '['])?;
+    let close = if trimmed[start..].starts_with('{') { '}' } else { ']' };
+    let end = trimmed.rfind(close)?;
+    if end <= start {
+        return None;
+    }
+    serde_json::from_str(&trimmed[start..=end]).ok()
+}
+
+/// Check the JSON Schema keywords models are asked to follow: `type`,
+/// `enum`, `const`, `properties`, `required`, `additionalProperties` and
+/// `items`. Unknown keywords are ignored.
+fn check_schema(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{}: expected {}", path, types.join(" or ")));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} is not one of the allowed values", path, value));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{}: expected {}", path, expected));
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for key in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(key) {
+                errors.push(format!("{}: missing required property \"{}\"", path, key));
+            }
+        }
+        for (key, item) in object {
+            let child = format!("{}.{}", path, key);
+            match properties.and_then(|p| p.get(key)) {
+                Some(property) => check_schema(item, property, &child, errors),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        errors.push(format!("{}: unexpected property", child))
+                    }
+                    Some(extra @ Value::Object(_)) => check_schema(item, extra, &child, errors),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            check_schema(item, item_schema, &format!("{}[{}]", path, i), errors);
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Start of a response, for error messages
+fn preview(content: &str) -> String {
+    const MAX_CHARS: usize = 80;
+    let trimmed = content.trim();
+    if trimmed.chars().count() > MAX_CHARS {
+        format!("{}...", trimmed.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{TokenUsage, ToolCall};
+
+    fn review_schema() -> ResponseFormat {
+        ResponseFormat::json_schema(
+            "review",
+            json!({
+                "type": "object",
+                "properties": {
+                    "verdict": { "type": "string", "enum": ["approve", "reject"] },
+                    "issues": { "type": "array", "items": { "type": "string" } }
+                },
+                "required": ["verdict", "issues"],
+                "additionalProperties": false
+            }),
+        )
+    }
+
+    fn response(content: &str, tool_calls: Vec<ToolCall>) -> ApiResponse {
+        ApiResponse {
+            content: content.to_string(),
+            usage: TokenUsage::default(),
+            model: "test".to_string(),
+            truncated: false,
+            stop_reason: Some(StopReason::EndTurn),
+            stop_sequence: None,
+            tool_calls,
+        }
+    }
+
+    #[test]
+    fn test_validate_strips_prose_and_checks_schema() {
+        let format = review_schema();
+        let chatty = "Sure! Here is the review:\n```json\n{\"verdict\": \"approve\", \"issues\": []}\n```\nLet me know.";
+        assert_eq!(format.validate(chatty).unwrap()["verdict"], "approve");
+
+        let err = format
+            .validate(r#"{"verdict": "maybe", "issues": [1], "extra": true}"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("$.verdict"), "{}", err);
+        assert!(err.contains("$.issues[0]: expected string"), "{}", err);
+        assert!(err.contains("$.extra: unexpected property"), "{}", err);
+
+        assert!(ResponseFormat::JsonObject.validate("[1, 2]").is_err());
+        assert!(ResponseFormat::JsonObject.validate("no json here").is_err());
+    }
+
+    #[test]
+    fn test_wire_formats() {
+        let format = review_schema();
+        let openai = openai_response_format(&format).unwrap();
+        assert_eq!(openai["type"], "json_schema");
+        assert_eq!(openai["json_schema"]["name"], "review");
+        assert_eq!(openai["json_schema"]["strict"], true);
+        let lenient = ResponseFormat::json_schema("code review.v2", json!({})).with_strict(false);
+        let openai = openai_response_format(&lenient).unwrap();
+        assert_eq!(openai["json_schema"]["name"], "code_review_v2");
+        assert_eq!(openai["json_schema"]["strict"], false);
+        assert_eq!(ollama_format(&ResponseFormat::JsonObject), Some(json!("json")));
+        assert_eq!(ollama_format(&format).unwrap()["type"], "object");
+        assert!(openai_response_format(&ResponseFormat::Text).is_none());
+
+        let mut config = Map::new();
+        gemini_response_format(&format, &mut config);
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert_eq!(config["responseJsonSchema"]["required"][0], "verdict");
+    }
+
+    #[test]
+    fn test_anthropic_tool_input_becomes_content() {
+        let format = review_schema();
+        assert_eq!(anthropic_format_tool(&format).unwrap().name, "review");
+
+        let call = ToolCall {
+            id: "toolu_1".to_string(),
+            name: "review".to_string(),
+            input: json!({ "verdict": "reject", "issues": ["unsafe unwrap"] }),
+        };
+        let mut forced = response("", vec![call]);
+        forced.stop_reason = Some(StopReason::ToolUse);
+
+        let applied = apply_response_format(forced, &format).unwrap();
+        assert!(applied.tool_calls.is_empty());
+        assert_eq!(applied.stop_reason, Some(StopReason::EndTurn));
+        let value: Value = serde_json::from_str(&applied.content).unwrap();
+        assert_eq!(value["issues"][0], "unsafe unwrap");
+
+        // Plain text is left alone
+        let text = apply_response_format(response("hi", Vec::new()), &ResponseFormat::Text);
+        assert_eq!(text.unwrap().content, "hi");
+    }
+}
@@ -1,17 +1,41 @@
 //! API abstraction layer for various coding agent providers
 
+mod batch;
+mod classify;
 mod client;
+mod format;
+mod pricing;
+mod registry;
+mod replay;
 mod request;
 mod response;
+mod retry;
+mod sampling;
 pub mod sse;
 pub mod streaming;
+mod tools;
 mod venice;
 
+pub use batch::{
+    record_batch_metrics, BatchRequest, BatchRequestCounts, BatchResult, BatchResultStatus,
+    BatchStatus, MessageBatch, MAX_BATCH_REQUESTS,
+};
 pub use client::ApiAgent;
+pub use format::{ResponseFormat, JSON_TOOL_NAME};
+pub use registry::{ChatProvider, ProviderFactory, ProviderRegistry, ProviderSpec};
+pub use replay::{
+    request_hash, Cassette, CassetteEntry, RecordedError, RecordedOutcome, RecordingProvider,
+    ReplayProvider,
+};
 pub use request::{ApiRequest, ContextItem, ContextType, Message, RequestConstraints, Role};
 pub use response::{ApiResponse, StopReason, TokenUsage};
+pub use pricing::{ModelPricing, BATCH_DISCOUNT};
+pub use retry::RetryPolicy;
+pub use sampling::SamplingParams;
+pub(crate) use replay::fnv1a;
 pub use sse::SseFormat;
 pub use streaming::{StreamChunk, StreamingProvider};
+pub use tools::{ToolCall, ToolChoice, ToolDefinition, ToolResult};
 pub use venice::{VeniceBalance, VeniceConfig, VeniceModel, VeniceProvider};
 
 use async_trait::async_trait;
@@ -26,8 +50,40 @@ pub enum ApiError {
     #[error("Authentication failed: {0}")]
     Auth(String),
 
-    #[error("Rate limited: retry after {retry_after_secs} seconds")]
-    RateLimited { retry_after_secs: u64 },
+    #[error("Rate limited{}", fmt_retry_after(.retry_after_secs))]
+    RateLimited { retry_after_secs: Option<u64> },
+
+    #[error("Provider overloaded{}", fmt_retry_after(.retry_after_secs))]
+    Overloaded { retry_after_secs: Option<u64> },
+
+    #[error("Server error {status}: {message}")]
+    ServerError { status: u16, message: String },
+
+    #[error("Context too long: {message}")]
+    ContextTooLong {
+        /// Context window reported by the provider, if stated
+        limit: Option<u32>,
+        /// Tokens the rejected request needed, if stated
+        requested: Option<u32>,
+        message: String,
+    },
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// A configured spending cap was reached
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("Content filtered: {0}")]
+    ContentFiltered(String),
+
+    /// The response did not match the requested [`ResponseFormat`]
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
 
     #[error("Provider error: {0}")]
     Provider(String),
@@ -36,6 +92,37 @@ pub enum ApiError {
     Serialization(#[from] serde_json::Error),
 }
 
+impl ApiError {
+    /// Whether the request may succeed if sent again unchanged
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::RateLimited { .. }
+            | ApiError::Overloaded { .. }
+            | ApiError::ServerError { .. } => true,
+            ApiError::Http(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Server-requested wait before retrying, if any
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            ApiError::RateLimited { retry_after_secs }
+            | ApiError::Overloaded { retry_after_secs } => {
+                retry_after_secs.map(std::time::Duration::from_secs)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn fmt_retry_after(retry_after_secs: &Option<u64>) -> String {
+    match retry_after_secs {
+        Some(secs) => format!(": retry after {} seconds", secs),
+        None => String::new(),
+    }
+}
+
 /// Configuration for API providers
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiConfig {
@@ -45,6 +132,10 @@ pub struct ApiConfig {
     pub model: String,
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    /// Context window to request from Ollama (`options.num_ctx`); ignored by
+    /// other providers
+    #[serde(default)]
+    pub num_ctx: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +144,7 @@ pub enum ProviderType {
     Claude,
     OpenAI,
     Ollama,
+    Gemini,
     Custom,
 }
 
@@ -0,0 +1,131 @@
+//! List prices for cost estimates
+//!
+//! Prices are USD per million tokens at standard (non-batch) rates. Cache
+//! writes and reads are priced by [`TokenUsage::with_cache_cost`], and the
+//! Message Batches API bills everything at [`BATCH_DISCOUNT`].
+
+use super::{TokenUsage, VeniceModel};
+use crate::cache::CACHE_READ_MULTIPLIER;
+
+/// Fraction of the standard price charged for batch requests
+pub const BATCH_DISCOUNT: f64 = 0.5;
+
+/// Per-token prices of a model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    /// USD per million input tokens
+    pub input_per_mtok: f64,
+    /// USD per million output tokens
+    pub output_per_mtok: f64,
+    /// Fraction of the input price charged for a cached token
+    pub cache_read_multiplier: f64,
+}
+
+impl ModelPricing {
+    pub const fn new(input_per_mtok: f64, output_per_mtok: f64) -> Self {
+        Self {
+            input_per_mtok,
+            output_per_mtok,
+            cache_read_multiplier: CACHE_READ_MULTIPLIER,
+        }
+    }
+
+    /// Charge cached reads at `multiplier` times the input price, as the
+    /// provider bills them; Anthropic's rate is the default
+    pub fn with_cache_read_multiplier(mut self, multiplier: f64) -> Self {
+        self.cache_read_multiplier = multiplier;
+        self
+    }
+
+    /// Prices of a Claude model, matched by family and version, or of a
+    /// Venice model by its id
+    pub fn for_model(model: &str) -> Option<Self> {
+        let model = model.to_lowercase();
+        if !model.contains("claude") {
+            let (input, output) = VeniceModel::from_id(&model)?.pricing();
+            return Some(Self::new(input, output));
+        }
+        let version_4_5 = ["4-5", "4.5"].iter().any(|v| model.contains(v));
+        let pricing = if model.contains("opus") {
+            if version_4_5 {
+                Self::new(5.0, 25.0)
+            } else {
+                Self::new(15.0, 75.0)
+            }
+        } else if model.contains("sonnet") {
+            Self::new(3.0, 15.0)
+        } else if model.contains("haiku") {
+            if model.contains("haiku-4") {
+                Self::new(1.0, 5.0)
+            } else if model.contains("3-5-haiku") {
+                Self::new(0.8, 4.0)
+            } else {
+                Self::new(0.25, 1.25)
+            }
+        } else {
+            return None;
+        };
+        Some(pricing)
+    }
+
+    /// Cost of `usage` at standard rates, cache writes and reads included
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        usage
+            .clone()
+            .with_cache_cost(
+                self.input_per_mtok / 1000.0,
+                self.output_per_mtok / 1000.0,
+                self.cache_read_multiplier,
+            )
+            .estimated_cost_usd
+            .unwrap_or(0.0)
+    }
+
+    /// Cost of `usage` when sent through the Message Batches API
+    pub fn batch_cost(&self, usage: &TokenUsage) -> f64 {
+        self.cost(usage) * BATCH_DISCOUNT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claude_prices_and_batch_discount() {
+        let sonnet = ModelPricing::for_model("claude-sonnet-4-20250514").unwrap();
+        assert_eq!(sonnet, ModelPricing::new(3.0, 15.0));
+        assert_eq!(
+            ModelPricing::for_model("claude-opus-4-5"),
+            Some(ModelPricing::new(5.0, 25.0))
+        );
+        assert_eq!(
+            ModelPricing::for_model("claude-3-5-haiku-20241022"),
+            Some(ModelPricing::new(0.8, 4.0))
+        );
+        assert!(ModelPricing::for_model("gpt-4o").is_none());
+        assert_eq!(ModelPricing::for_model("venice-small"), Some(ModelPricing::new(0.05, 0.15)));
+
+        let usage = TokenUsage::new(1_000_000, 100_000);
+        assert!((sonnet.cost(&usage) - 4.5).abs() < 1e-9);
+        assert!((sonnet.batch_cost(&usage) - 2.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_one_hour_cache_writes_cost_double() {
+        let usage = TokenUsage::from_anthropic(&serde_json::json!({
+            "input_tokens": 0,
+            "output_tokens": 0,
+            "cache_creation_input_tokens": 3_000_000,
+            "cache_creation": {
+                "ephemeral_5m_input_tokens": 2_000_000,
+                "ephemeral_1h_input_tokens": 1_000_000
+            }
+        }));
+        assert_eq!(usage.cache_creation_1h_tokens, Some(1_000_000));
+
+        // 2M at 1.25x plus 1M at 2x, on $3/MTok input
+        let sonnet = ModelPricing::new(3.0, 15.0);
+        assert!((sonnet.cost(&usage) - 13.5).abs() < 1e-9);
+    }
+}
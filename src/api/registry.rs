@@ -0,0 +1,603 @@
+//! Provider registry: maps provider names to factories
+//!
+//! Every CLI path resolves providers by name through a [`ProviderRegistry`]
+//! instead of matching on [`ProviderType`]. The built-in providers (`venice`,
+//! `claude`, `openai`, `ollama`, `gemini`, `custom`, `replay`) are registered
+//! by [`ProviderRegistry::with_builtins`]; library users can add their own
+//! with [`ProviderRegistry::register`].
+//!
+//! Two spec options apply to every provider: `record = "<path>"` records
+//! its traffic to a cassette, and the `replay` provider with `cassette =
+//! "<path>"` serves one back offline (see [`super::replay`]).
+//!
+//! A provider is described by a [`ProviderSpec`], which is usually read from
+//! the config: the `[primary]`/`[fallback]`/`[local]` sections or a
+//! `[providers.<name>]` table such as
+//!
+//! ```toml
+//! [providers.deepseek]
+//! provider = "openai"
+//! base_url = "https://api.deepseek.com/v1"
+//! model = "deepseek-chat"
+//! api_key_env = "DEEPSEEK_API_KEY"
+//! ```
+
+use super::{
+    ApiAgent, ApiConfig, ApiError, ApiProvider, ApiRequest, ApiResponse, ProviderType,
+    RecordingProvider, ReplayProvider, RetryPolicy, StreamChunk, StreamingProvider, VeniceConfig,
+    VeniceProvider,
+};
+use crate::config::Config;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// A provider that supports both blocking and streaming requests
+pub trait ChatProvider: ApiProvider + StreamingProvider {
+    /// Access the concrete provider, e.g. to read a Venice balance
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: ApiProvider + StreamingProvider + 'static> ChatProvider for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A built provider, wrapped again by `RecordingProvider`. A newtype rather
+/// than impls on `Box<dyn ChatProvider>`, which would make the box itself a
+/// `ChatProvider` and break `as_any` downcasts on it.
+struct Boxed(Box<dyn ChatProvider>);
+
+#[async_trait]
+impl ApiProvider for Boxed {
+    async fn send_request(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+        self.0.send_request(request).await
+    }
+
+    fn estimate_tokens(&self, text: &str) -> usize {
+        self.0.estimate_tokens(text)
+    }
+
+    fn provider_type(&self) -> ProviderType {
+        self.0.provider_type()
+    }
+}
+
+#[async_trait]
+impl StreamingProvider for Boxed {
+    async fn send_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<tokio::sync::mpsc::Receiver<StreamChunk>, ApiError> {
+        self.0.send_streaming(request).await
+    }
+}
+
+/// Everything a factory needs to build a provider
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderSpec {
+    /// Registry name of the factory (e.g. "claude"). A `[providers.<name>]`
+    /// table may leave this out to use its own name.
+    pub provider: String,
+
+    /// API key; falls back to `api_key_env`, then the provider's usual
+    /// environment variable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+
+    /// Environment variable holding the API key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+
+    /// API root (e.g. "https://api.anthropic.com/v1"); the provider's
+    /// default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// Model; the provider's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Context window to request (Ollama only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+
+    /// Retries of transient failures; the provider default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+
+    /// Provider-specific settings for custom factories
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl ProviderSpec {
+    pub fn new(provider: impl Into<String>) -> Self {
+        Self {
+            provider: provider.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = Some(api_key);
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Spec for the `[primary]` section
+    pub fn primary(config: &Config) -> Self {
+        let p = &config.primary;
+        Self {
+            provider: p.provider.clone(),
+            api_key: config.primary_api_key(),
+            base_url: Some(p.base_url.clone()),
+            model: Some(p.model.clone()),
+            max_tokens: Some(p.max_tokens),
+            temperature: Some(p.temperature),
+            max_retries: Some(config.orchestrator.max_retries),
+            ..Self::default()
+        }
+        .with_extra("min_balance_usd", p.min_balance_usd)
+        .with_extra("min_balance_diem", p.min_balance_diem)
+    }
+
+    /// Spec for the `[fallback]` section
+    pub fn fallback(config: &Config) -> Self {
+        let f = &config.fallback;
+        Self {
+            provider: f.provider.clone(),
+            api_key: config.fallback_api_key(),
+            base_url: Some(f.base_url.clone()),
+            model: Some(f.model.clone()),
+            max_tokens: Some(f.max_tokens),
+            temperature: Some(f.temperature),
+            max_retries: Some(config.orchestrator.max_retries),
+            ..Self::default()
+        }
+    }
+
+    /// Spec for serving requests directly from the `[local]` Ollama server
+    pub fn local(config: &Config) -> Self {
+        let l = &config.local;
+        Self {
+            provider: "ollama".to_string(),
+            base_url: Some(l.url.clone()),
+            model: Some(l.model.clone()),
+            temperature: Some(0.7),
+            num_ctx: l.num_ctx,
+            max_retries: Some(config.orchestrator.max_retries),
+            ..Self::default()
+        }
+    }
+
+    /// Spec for the provider called `name`: a `[providers.<name>]` table,
+    /// else the primary/fallback/local section using that provider, else a
+    /// bare spec relying on the provider's defaults and environment
+    pub fn from_config(config: &Config, name: &str) -> Self {
+        if let Some(table) = config.providers.get(name) {
+            let mut spec = table.clone();
+            if spec.provider.is_empty() {
+                spec.provider = name.to_string();
+            }
+            if spec.max_retries.is_none() {
+                spec.max_retries = Some(config.orchestrator.max_retries);
+            }
+            return spec;
+        }
+        if config.primary.provider == name {
+            return Self::primary(config);
+        }
+        if config.fallback.provider == name {
+            return Self::fallback(config);
+        }
+        if name == "ollama" {
+            return Self::local(config);
+        }
+        Self::new(name).with_max_retries(config.orchestrator.max_retries)
+    }
+
+    /// API key from the spec, `api_key_env`, or the first of `env_vars` set
+    pub fn resolve_api_key(&self, env_vars: &[&str]) -> Option<String> {
+        self.api_key
+            .clone()
+            .or_else(|| {
+                self.api_key_env
+                    .as_deref()
+                    .and_then(|var| std::env::var(var).ok())
+            })
+            .or_else(|| env_vars.iter().find_map(|var| std::env::var(var).ok()))
+            .filter(|key| !key.is_empty())
+    }
+
+    /// Retry policy from `max_retries`, else the default
+    pub fn retry_policy(&self) -> RetryPolicy {
+        match self.max_retries {
+            Some(n) => RetryPolicy::default().with_max_retries(n),
+            None => RetryPolicy::default(),
+        }
+    }
+
+    fn with_extra(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.extra.insert(key.to_string(), value.into());
+        self
+    }
+
+    fn extra_f64(&self, key: &str) -> Option<f64> {
+        self.extra.get(key).and_then(|v| v.as_f64())
+    }
+
+    fn extra_str(&self, key: &str) -> Option<&str> {
+        self.extra.get(key).and_then(|v| v.as_str())
+    }
+
+    fn require_api_key(&self, env_vars: &[&str]) -> Result<String, ApiError> {
+        self.resolve_api_key(env_vars).ok_or_else(|| {
+            ApiError::Auth(format!(
+                "No API key for provider '{}' (set {})",
+                self.provider,
+                self.api_key_env
+                    .as_deref()
+                    .or(env_vars.first().copied())
+                    .unwrap_or("api_key")
+            ))
+        })
+    }
+}
+
+/// Builds a provider from its spec
+pub type ProviderFactory =
+    Arc<dyn Fn(&ProviderSpec) -> Result<Box<dyn ChatProvider>, ApiError> + Send + Sync>;
+
+/// Name → factory map used to construct providers
+#[derive(Clone, Default)]
+pub struct ProviderRegistry {
+    factories: HashMap<String, ProviderFactory>,
+    aliases: HashMap<String, String>,
+}
+
+impl ProviderRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with every built-in provider
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("venice", build_venice);
+        registry.register("claude", |spec| {
+            let key = spec.require_api_key(&["ANTHROPIC_API_KEY"])?;
+            api_agent(
+                spec,
+                ProviderType::Claude,
+                key,
+                "https://api.anthropic.com/v1",
+                "/messages",
+                "claude-sonnet-4-20250514",
+            )
+        });
+        registry.register("openai", |spec| {
+            let key = spec.require_api_key(&["OPENAI_API_KEY"])?;
+            api_agent(
+                spec,
+                ProviderType::OpenAI,
+                key,
+                "https://api.openai.com/v1",
+                "/chat/completions",
+                "gpt-4",
+            )
+        });
+        registry.register("ollama", |spec| {
+            api_agent(
+                spec,
+                ProviderType::Ollama,
+                String::new(),
+                "http://localhost:11434",
+                "/api/chat",
+                "llama3.2",
+            )
+        });
+        registry.register("gemini", |spec| {
+            let key = spec.require_api_key(&["GEMINI_API_KEY", "GOOGLE_API_KEY"])?;
+            // Gemini builds model-specific URLs from the API root
+            api_agent(
+                spec,
+                ProviderType::Gemini,
+                key,
+                crate::config::GEMINI_BASE_URL,
+                "",
+                "gemini-2.5-flash",
+            )
+        });
+        registry.register("custom", |spec| {
+            if spec.base_url.is_none() {
+                return Err(ApiError::InvalidRequest(
+                    "Custom provider requires base_url".to_string(),
+                ));
+            }
+            let key = spec.resolve_api_key(&[]).unwrap_or_default();
+            api_agent(
+                spec,
+                ProviderType::Custom,
+                key,
+                "",
+                "/chat/completions",
+                "default",
+            )
+        });
+        registry.register("replay", |spec| {
+            let path = spec.extra_str("cassette").ok_or_else(|| {
+                ApiError::InvalidRequest("Replay provider requires cassette".to_string())
+            })?;
+            let mut replay = ReplayProvider::from_file(path)?;
+            if let Some(model) = &spec.model {
+                replay = replay.with_model(model.clone());
+            }
+            Ok(Box::new(replay))
+        });
+        registry.alias("anthropic", "claude");
+        registry.alias("google", "gemini");
+        registry.alias("local", "ollama");
+        registry
+    }
+
+    /// Register (or replace) the factory for `name`
+    pub fn register<F>(&mut self, name: &str, factory: F) -> &mut Self
+    where
+        F: Fn(&ProviderSpec) -> Result<Box<dyn ChatProvider>, ApiError> + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(name.to_lowercase(), Arc::new(factory));
+        self
+    }
+
+    /// Make `alias` resolve to the provider registered as `target`
+    pub fn alias(&mut self, alias: &str, target: &str) -> &mut Self {
+        self.aliases
+            .insert(alias.to_lowercase(), target.to_lowercase());
+        self
+    }
+
+    /// Canonical registered name for `name`, following aliases
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        let name = self.aliases.get(&name).cloned().unwrap_or(name);
+        self.factories.contains_key(&name).then_some(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.resolve(name).is_some()
+    }
+
+    /// Registered provider names, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Build the provider described by `spec`, recording its traffic if the
+    /// spec has a `record` path
+    pub fn build(&self, spec: &ProviderSpec) -> Result<Box<dyn ChatProvider>, ApiError> {
+        let factory = self
+            .resolve(&spec.provider)
+            .and_then(|name| self.factories.get(&name))
+            .ok_or_else(|| {
+                ApiError::InvalidRequest(format!(
+                    "Unknown provider '{}'. Available: {}",
+                    spec.provider,
+                    self.names().join(", ")
+                ))
+            })?;
+        let provider = factory(spec)?;
+        match spec.extra_str("record") {
+            Some(path) => Ok(Box::new(RecordingProvider::new(Boxed(provider), path)?)),
+            None => Ok(provider),
+        }
+    }
+
+    /// Build the provider called `name` as configured in `config`
+    pub fn build_from_config(
+        &self,
+        config: &Config,
+        name: &str,
+    ) -> Result<(ProviderSpec, Box<dyn ChatProvider>), ApiError> {
+        let name = name.to_lowercase();
+        // A [providers.<name>] table takes precedence over aliases
+        let name = if config.providers.contains_key(&name) {
+            name
+        } else {
+            self.resolve(&name).unwrap_or(name)
+        };
+        let spec = ProviderSpec::from_config(config, &name);
+        let provider = self.build(&spec)?;
+        Ok((spec, provider))
+    }
+}
+
+impl std::fmt::Debug for ProviderRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderRegistry")
+            .field("providers", &self.names())
+            .finish()
+    }
+}
+
+fn build_venice(spec: &ProviderSpec) -> Result<Box<dyn ChatProvider>, ApiError> {
+    let defaults = VeniceConfig::default();
+    let config = VeniceConfig {
+        api_key: spec.require_api_key(&["VENICE_API_KEY"])?,
+        model: spec.model.clone().unwrap_or(defaults.model),
+        base_url: spec.base_url.clone().or(defaults.base_url),
+        min_balance_usd: spec
+            .extra_f64("min_balance_usd")
+            .unwrap_or(defaults.min_balance_usd),
+        min_balance_diem: spec
+            .extra_f64("min_balance_diem")
+            .unwrap_or(defaults.min_balance_diem),
+        max_tokens: spec.max_tokens.or(defaults.max_tokens),
+        temperature: spec.temperature.or(defaults.temperature),
+    };
+    Ok(Box::new(
+        VeniceProvider::new(config).with_retry_policy(spec.retry_policy()),
+    ))
+}
+
+/// Build an [`ApiAgent`], appending `endpoint` to the API root
+fn api_agent(
+    spec: &ProviderSpec,
+    provider: ProviderType,
+    api_key: String,
+    default_root: &str,
+    endpoint: &str,
+    default_model: &str,
+) -> Result<Box<dyn ChatProvider>, ApiError> {
+    let root = spec.base_url.as_deref().unwrap_or(default_root);
+    let config = ApiConfig {
+        provider,
+        api_key,
+        base_url: Some(format!("{}{}", root.trim_end_matches('/'), endpoint)),
+        model: spec
+            .model
+            .clone()
+            .unwrap_or_else(|| default_model.to_string()),
+        max_tokens: spec.max_tokens.or(Some(4096)),
+        temperature: spec.temperature.or(Some(0.7)),
+        num_ctx: spec.num_ctx,
+    };
+    Ok(Box::new(
+        ApiAgent::new(config).with_retry_policy(spec.retry_policy()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins_and_aliases() {
+        let registry = ProviderRegistry::with_builtins();
+        assert_eq!(registry.resolve("Anthropic").as_deref(), Some("claude"));
+        assert_eq!(registry.resolve("local").as_deref(), Some("ollama"));
+        assert!(registry.resolve("nope").is_none());
+
+        let ollama = registry
+            .build(&ProviderSpec::new("ollama").with_model("qwen2.5-coder".into()))
+            .unwrap();
+        assert!(matches!(ollama.provider_type(), ProviderType::Ollama));
+
+        let venice = registry
+            .build(&ProviderSpec::new("venice").with_api_key("k".into()))
+            .unwrap();
+        assert!(venice.as_any().downcast_ref::<VeniceProvider>().is_some());
+
+        match registry.build(&ProviderSpec::new("nope")) {
+            Err(ApiError::InvalidRequest(msg)) => assert!(msg.contains("gemini")),
+            other => panic!("Expected InvalidRequest, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_register_custom_factory() {
+        let mut registry = ProviderRegistry::new();
+        registry.register("mock", |spec| {
+            let config = ApiConfig {
+                provider: ProviderType::Custom,
+                api_key: String::new(),
+                base_url: spec.base_url.clone(),
+                model: spec.model.clone().unwrap_or_default(),
+                max_tokens: None,
+                temperature: None,
+                num_ctx: None,
+            };
+            Ok(Box::new(ApiAgent::new(config)))
+        });
+        assert!(registry.contains("MOCK"));
+        assert!(registry.build(&ProviderSpec::new("mock")).is_ok());
+        assert!(registry.build(&ProviderSpec::new("claude")).is_err());
+    }
+
+    #[test]
+    fn test_spec_from_providers_table() {
+        let config: Config = toml::from_str(
+            r#"
+            [providers.deepseek]
+            provider = "openai"
+            base_url = "https://api.deepseek.com/v1"
+            model = "deepseek-chat"
+            api_key = "sk-test"
+            region = "eu"
+            "#,
+        )
+        .unwrap();
+
+        let registry = ProviderRegistry::with_builtins();
+        let (spec, provider) = registry.build_from_config(&config, "deepseek").unwrap();
+        assert_eq!(spec.provider, "openai");
+        assert_eq!(spec.model.as_deref(), Some("deepseek-chat"));
+        assert_eq!(spec.max_retries, Some(config.orchestrator.max_retries));
+        assert_eq!(spec.extra.get("region"), Some(&serde_json::json!("eu")));
+        assert!(matches!(provider.provider_type(), ProviderType::OpenAI));
+    }
+
+    #[tokio::test]
+    async fn test_replay_provider_from_config() {
+        use crate::api::{ApiRequest, ApiResponse, Cassette, TokenUsage};
+
+        let path = std::env::temp_dir().join(format!(
+            "token-optimizer-registry-{}.json",
+            std::process::id()
+        ));
+        let request = ApiRequest::new("ping".to_string());
+        let response = ApiResponse {
+            content: "pong".to_string(),
+            usage: TokenUsage::new(1, 1),
+            model: "recorded".to_string(),
+            truncated: false,
+            stop_reason: None,
+            stop_sequence: None,
+            tool_calls: Vec::new(),
+        };
+        Cassette::new()
+            .with_response(request.clone(), response)
+            .save(&path)
+            .unwrap();
+
+        let config: Config = toml::from_str(&format!(
+            "[providers.offline]\nprovider = \"replay\"\ncassette = {:?}\n",
+            path.display().to_string()
+        ))
+        .unwrap();
+        let (_, provider) = ProviderRegistry::with_builtins()
+            .build_from_config(&config, "offline")
+            .unwrap();
+        let reply = provider.send_request(request).await;
+        std::fs::remove_file(&path).ok();
+        assert_eq!(reply.unwrap().content, "pong");
+    }
+}
@@ -1,6 +1,9 @@
 //! API request structures
 
-use crate::cache::CacheControl;
+use super::format::ResponseFormat;
+use super::sampling::SamplingParams;
+use super::tools::{ToolCall, ToolChoice, ToolDefinition, ToolResult};
+use crate::cache::{CacheControl, MAX_CACHE_BREAKPOINTS};
 use serde::{Deserialize, Serialize};
 
 /// A message in a conversation
@@ -8,6 +11,47 @@ use serde::{Deserialize, Serialize};
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// Tool invocations made by the assistant in this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Results of earlier tool invocations (sent as a user message)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<ToolResult>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_results: Vec::new(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// Assistant message that requested tool calls
+    pub fn assistant_with_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(Role::Assistant, content)
+        }
+    }
+
+    /// User message answering earlier tool calls
+    pub fn tool_results(results: Vec<ToolResult>) -> Self {
+        Self {
+            tool_results: results,
+            ..Self::new(Role::User, String::new())
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -40,6 +84,18 @@ pub struct ApiRequest {
     /// Optional constraints for the response
     pub constraints: Option<RequestConstraints>,
 
+    /// Tools the model may call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+
+    /// How the model should choose between tools (provider default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+
+    /// Sampling parameters overriding the provider config
+    #[serde(default, skip_serializing_if = "SamplingParams::is_empty")]
+    pub sampling: SamplingParams,
+
     /// Positions where cache breakpoints should be inserted
     #[serde(skip)]
     pub cache_breakpoints: Vec<usize>,
@@ -71,7 +127,7 @@ pub enum ContextType {
     Output,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RequestConstraints {
     /// Maximum tokens for context
     pub max_context_tokens: Option<u32>,
@@ -79,6 +135,9 @@ pub struct RequestConstraints {
     pub max_response_tokens: Option<u32>,
     /// Prefer concise responses
     pub prefer_concise: bool,
+    /// Required shape of the response content (text, JSON, JSON schema)
+    #[serde(default, skip_serializing_if = "ResponseFormat::is_text")]
+    pub response_format: ResponseFormat,
 }
 
 impl ApiRequest {
@@ -90,6 +149,9 @@ impl ApiRequest {
             context: Vec::new(),
             task,
             constraints: None,
+            tools: Vec::new(),
+            tool_choice: None,
+            sampling: SamplingParams::default(),
             cache_breakpoints: Vec::new(),
         }
     }
@@ -116,6 +178,57 @@ impl ApiRequest {
         self
     }
 
+    pub fn with_sampling(mut self, sampling: SamplingParams) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Sampling to send: this request's parameters, then the
+    /// `max_response_tokens` constraint, then the provider defaults
+    pub(crate) fn sampling_or(
+        &self,
+        temperature: Option<f32>,
+        max_tokens: Option<u32>,
+    ) -> SamplingParams {
+        let constrained = self.constraints.as_ref().and_then(|c| c.max_response_tokens);
+        self.sampling.or_defaults(temperature, constrained.or(max_tokens))
+    }
+
+    /// Require JSON output; the response is validated before it is returned
+    pub fn with_response_format(mut self, format: ResponseFormat) -> Self {
+        self.constraints
+            .get_or_insert_with(RequestConstraints::default)
+            .response_format = format;
+        self
+    }
+
+    /// The requested response format (text unless constrained)
+    pub fn response_format(&self) -> &ResponseFormat {
+        static TEXT: ResponseFormat = ResponseFormat::Text;
+        self.constraints
+            .as_ref()
+            .map_or(&TEXT, |c| &c.response_format)
+    }
+
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn with_tool_choice(mut self, choice: ToolChoice) -> Self {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    /// Answer the tool calls of the last assistant message.
+    ///
+    /// The results are appended to the history; the task may be left empty
+    /// when there is nothing to add besides the results.
+    pub fn with_tool_results(mut self, results: Vec<ToolResult>) -> Self {
+        self.messages.push(Message::tool_results(results));
+        self
+    }
+
     /// Add cache breakpoints at specified context indices
     pub fn with_cache_breakpoints(mut self, breakpoints: Vec<usize>) -> Self {
         self.cache_breakpoints = breakpoints;
@@ -135,6 +248,35 @@ impl ApiRequest {
         }
     }
 
+    /// Cache breakpoints Claude requests mark ahead of the history: a cached
+    /// system prompt, and context items that are cached or sit at a bare
+    /// breakpoint index
+    pub fn prefix_breakpoints(&self) -> usize {
+        let system = self.system.is_some() && self.system_cache_control.is_some();
+        let context = (0..self.context.len())
+            .filter(|idx| {
+                self.context[*idx].cache_control.is_some() || self.cache_breakpoints.contains(idx)
+            })
+            .count();
+        usize::from(system) + context
+    }
+
+    /// Indices of the history messages Claude requests mark with rolling
+    /// cache breakpoints: the last one, and the one that ended the previous
+    /// turn's history, so each turn reads the prefix the turn before it
+    /// wrote. System messages are not sent as history, and the breakpoints
+    /// share the limit with [`prefix_breakpoints`](Self::prefix_breakpoints).
+    pub fn history_breakpoints(&self) -> Vec<usize> {
+        let history: Vec<usize> = (0..self.messages.len())
+            .filter(|idx| self.messages[*idx].role != Role::System)
+            .collect();
+        [1, 3]
+            .into_iter()
+            .take(MAX_CACHE_BREAKPOINTS.saturating_sub(self.prefix_breakpoints()))
+            .filter_map(|offset| history.len().checked_sub(offset).map(|idx| history[idx]))
+            .collect()
+    }
+
     /// Reorder context to put static items first (for optimal caching)
     pub fn optimize_for_caching(&mut self) {
         // Sort by is_static (true first) while preserving relative order
@@ -1,6 +1,9 @@
 //! API response structures
 
+use super::tools::ToolCall;
+use crate::cache::{CACHE_WRITE_MULTIPLIER_1H, CACHE_WRITE_MULTIPLIER_5M};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 /// Response from an API coding agent
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +22,21 @@ pub struct ApiResponse {
 
     /// Stop reason
     pub stop_reason: Option<StopReason>,
+
+    /// The stop sequence that ended generation, when the provider reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequence: Option<String>,
+
+    /// Tool invocations requested by the model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ApiResponse {
+    /// Whether the model is waiting for tool results
+    pub fn has_tool_calls(&self) -> bool {
+        !self.tool_calls.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -33,11 +51,14 @@ pub struct TokenUsage {
     pub estimated_cost_usd: Option<f64>,
     /// Tokens written to cache (Anthropic)
     pub cache_creation_tokens: Option<u32>,
+    /// Part of `cache_creation_tokens` written with the 1h TTL
+    #[serde(default)]
+    pub cache_creation_1h_tokens: Option<u32>,
     /// Tokens read from cache (Anthropic)
     pub cache_read_tokens: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum StopReason {
     EndTurn,
@@ -46,6 +67,38 @@ pub enum StopReason {
     ToolUse,
 }
 
+impl StopReason {
+    /// Map an Anthropic `stop_reason`
+    pub fn from_anthropic(reason: &str) -> Option<Self> {
+        match reason {
+            "end_turn" => Some(StopReason::EndTurn),
+            "max_tokens" => Some(StopReason::MaxTokens),
+            "stop_sequence" => Some(StopReason::StopSequence),
+            "tool_use" => Some(StopReason::ToolUse),
+            _ => None,
+        }
+    }
+
+    /// Map an OpenAI-compatible `finish_reason`
+    pub fn from_openai(reason: &str) -> Option<Self> {
+        match reason {
+            "stop" => Some(StopReason::EndTurn),
+            "length" => Some(StopReason::MaxTokens),
+            "tool_calls" | "function_call" => Some(StopReason::ToolUse),
+            _ => None,
+        }
+    }
+
+    /// Map a Gemini `finishReason`
+    pub fn from_gemini(reason: &str) -> Option<Self> {
+        match reason {
+            "STOP" => Some(StopReason::EndTurn),
+            "MAX_TOKENS" => Some(StopReason::MaxTokens),
+            _ => None,
+        }
+    }
+}
+
 impl TokenUsage {
     pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
         Self {
@@ -54,6 +107,7 @@ impl TokenUsage {
             total_tokens: prompt_tokens + completion_tokens,
             estimated_cost_usd: None,
             cache_creation_tokens: None,
+            cache_creation_1h_tokens: None,
             cache_read_tokens: None,
         }
     }
@@ -71,10 +125,67 @@ impl TokenUsage {
             total_tokens: prompt_tokens + completion_tokens,
             estimated_cost_usd: None,
             cache_creation_tokens: cache_creation,
+            cache_creation_1h_tokens: None,
             cache_read_tokens: cache_read,
         }
     }
 
+    /// Parse an Anthropic `usage` object, including the 1h share of cache
+    /// writes from its `cache_creation` breakdown
+    pub(crate) fn from_anthropic(usage: &Value) -> Self {
+        let tokens = |v: &Value| v.as_u64().map(|t| t as u32);
+        let mut parsed = Self::with_cache(
+            usage["input_tokens"].as_u64().unwrap_or(0) as u32,
+            usage["output_tokens"].as_u64().unwrap_or(0) as u32,
+            tokens(&usage["cache_creation_input_tokens"]),
+            tokens(&usage["cache_read_input_tokens"]),
+        );
+        parsed.cache_creation_1h_tokens =
+            tokens(&usage["cache_creation"]["ephemeral_1h_input_tokens"]);
+        parsed
+    }
+
+    /// Parse an OpenAI-compatible `usage` object.
+    ///
+    /// `prompt_tokens` includes `prompt_tokens_details.cached_tokens`; as for
+    /// Gemini, the cached part is moved to `cache_read_tokens`. Servers that
+    /// leave out the details report no cache activity at all.
+    pub(crate) fn from_openai(usage: &Value) -> Self {
+        let prompt = usage["prompt_tokens"].as_u64().unwrap_or(0) as u32;
+        let cached = usage["prompt_tokens_details"]["cached_tokens"]
+            .as_u64()
+            .map(|t| t as u32);
+
+        Self::with_cache(
+            prompt.saturating_sub(cached.unwrap_or(0)),
+            usage["completion_tokens"].as_u64().unwrap_or(0) as u32,
+            None,
+            cached,
+        )
+    }
+
+    /// Parse Gemini `usageMetadata`.
+    ///
+    /// Gemini's `promptTokenCount` includes `cachedContentTokenCount`; the
+    /// cached part is moved to `cache_read_tokens` so `prompt_tokens` means
+    /// uncached input, as it does for Anthropic. Thinking tokens are billed
+    /// as output and counted as completion tokens.
+    pub(crate) fn from_gemini(metadata: &Value) -> Self {
+        let prompt = metadata["promptTokenCount"].as_u64().unwrap_or(0) as u32;
+        let cached = metadata["cachedContentTokenCount"]
+            .as_u64()
+            .map(|t| t as u32);
+        let completion = metadata["candidatesTokenCount"].as_u64().unwrap_or(0)
+            + metadata["thoughtsTokenCount"].as_u64().unwrap_or(0);
+
+        Self::with_cache(
+            prompt.saturating_sub(cached.unwrap_or(0)),
+            completion as u32,
+            None,
+            cached,
+        )
+    }
+
     pub fn with_cost(mut self, cost_per_1k_input: f64, cost_per_1k_output: f64) -> Self {
         let input_cost = (self.prompt_tokens as f64 / 1000.0) * cost_per_1k_input;
         let output_cost = (self.completion_tokens as f64 / 1000.0) * cost_per_1k_output;
@@ -82,31 +193,64 @@ impl TokenUsage {
         self
     }
 
-    /// Calculate cost with cache pricing (Anthropic)
-    /// - cache_creation: 25% more than base input price
-    /// - cache_read: 90% less than base input price
+    /// Calculate cost with cache pricing
+    /// - cache_creation: 25% more than base input price (5m TTL), or
+    ///   double it for the `cache_creation_1h_tokens` share (Anthropic)
+    /// - cache_read: `cache_read_multiplier` times the base input price,
+    ///   e.g. [`CACHE_READ_MULTIPLIER`](crate::cache::CACHE_READ_MULTIPLIER)
+    ///   on Anthropic and 0.5 on OpenAI
     pub fn with_cache_cost(
         mut self,
         cost_per_1k_input: f64,
         cost_per_1k_output: f64,
+        cache_read_multiplier: f64,
     ) -> Self {
         let base_input_cost = (self.prompt_tokens as f64 / 1000.0) * cost_per_1k_input;
         let output_cost = (self.completion_tokens as f64 / 1000.0) * cost_per_1k_output;
 
-        // Cache creation costs 25% more
-        let cache_write_cost = self.cache_creation_tokens
-            .map(|t| (t as f64 / 1000.0) * cost_per_1k_input * 1.25)
-            .unwrap_or(0.0);
+        // Cache creation costs 25% more, or 100% more for the 1h TTL
+        let written = self.cache_creation_tokens.unwrap_or(0);
+        let written_1h = self.cache_creation_1h_tokens.unwrap_or(0).min(written);
+        let cache_write_cost = ((written - written_1h) as f64 * CACHE_WRITE_MULTIPLIER_5M
+            + written_1h as f64 * CACHE_WRITE_MULTIPLIER_1H)
+            / 1000.0
+            * cost_per_1k_input;
 
-        // Cache read costs 90% less
         let cache_read_cost = self.cache_read_tokens
-            .map(|t| (t as f64 / 1000.0) * cost_per_1k_input * 0.10)
+            .map(|t| (t as f64 / 1000.0) * cost_per_1k_input * cache_read_multiplier)
             .unwrap_or(0.0);
 
         self.estimated_cost_usd = Some(base_input_cost + output_cost + cache_write_cost + cache_read_cost);
         self
     }
 
+    /// Fold in usage reported later in a stream.
+    ///
+    /// Providers split usage across events (Anthropic sends input tokens in
+    /// `message_start` and output tokens in `message_delta`), so non-zero
+    /// counts and present cache fields from `other` win.
+    pub fn merge(&mut self, other: &TokenUsage) {
+        if other.prompt_tokens > 0 {
+            self.prompt_tokens = other.prompt_tokens;
+        }
+        if other.completion_tokens > 0 {
+            self.completion_tokens = other.completion_tokens;
+        }
+        if other.cache_creation_tokens.is_some() {
+            self.cache_creation_tokens = other.cache_creation_tokens;
+        }
+        if other.cache_creation_1h_tokens.is_some() {
+            self.cache_creation_1h_tokens = other.cache_creation_1h_tokens;
+        }
+        if other.cache_read_tokens.is_some() {
+            self.cache_read_tokens = other.cache_read_tokens;
+        }
+        if other.estimated_cost_usd.is_some() {
+            self.estimated_cost_usd = other.estimated_cost_usd;
+        }
+        self.total_tokens = self.prompt_tokens + self.completion_tokens;
+    }
+
     /// Calculate tokens saved from cache
     pub fn cache_savings(&self) -> u32 {
         self.cache_read_tokens.unwrap_or(0)
@@ -114,6 +258,6 @@ impl TokenUsage {
 
     /// Check if any caching occurred
     pub fn has_cache_activity(&self) -> bool {
-        self.cache_creation_tokens.is_some() || self.cache_read_tokens.is_some()
+        self.cache_creation_tokens.unwrap_or(0) > 0 || self.cache_read_tokens.unwrap_or(0) > 0
     }
 }
@@ -0,0 +1,199 @@
+//! Retry with jittered exponential backoff, shared by every provider
+//!
+//! Providers wrap each HTTP attempt in [`RetryPolicy::run`]. Only errors for
+//! which [`ApiError::is_retryable`] holds are retried; a server-supplied
+//! retry delay (from `retry-after` or rate-limit reset headers) takes
+//! precedence over the computed backoff.
+
+use super::ApiError;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+/// How failed requests are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt (0 disables retrying)
+    pub max_retries: u32,
+    /// Backoff before the first retry; doubles on each further retry
+    pub base_delay: Duration,
+    /// Upper bound on a single wait. A server asking for a longer wait is
+    /// not retried, so callers can fall back instead of stalling.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Backoff before retry number `attempt` (1-based), with "equal jitter":
+    /// half the exponential delay is fixed, the other half random.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        let half = exp / 2;
+        half + half.mul_f64(jitter())
+    }
+
+    /// Delay before retrying after `err`, or `None` if it should not be retried
+    pub fn delay_for(&self, attempt: u32, err: &ApiError) -> Option<Duration> {
+        if attempt > self.max_retries || !err.is_retryable() {
+            return None;
+        }
+        match err.retry_after() {
+            Some(wait) if wait > self.max_delay => None,
+            Some(wait) => Some(wait),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// Run `op`, retrying retryable failures according to this policy
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    attempt += 1;
+                    let Some(delay) = self.delay_for(attempt, &e) else {
+                        return Err(e);
+                    };
+                    warn!(
+                        "Request failed ({}), retry {}/{} in {:.1}s",
+                        e,
+                        attempt,
+                        self.max_retries,
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+/// A random fraction in [0, 1).
+///
+/// `RandomState` is seeded from OS randomness, which is plenty for spreading
+/// out retries without pulling in an RNG crate.
+fn jitter() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy::default()
+            .with_base_delay(Duration::from_millis(1))
+            .with_max_delay(Duration::from_millis(10))
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::default();
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_millis(250) && first <= Duration::from_millis(500));
+        let third = policy.backoff(3);
+        assert!(third >= Duration::from_millis(1000) && third <= Duration::from_millis(2000));
+        assert!(policy.backoff(30) <= policy.max_delay);
+    }
+
+    #[test]
+    fn test_delay_for_respects_retry_after_and_limits() {
+        let policy = RetryPolicy::default();
+        let limited = ApiError::RateLimited {
+            retry_after_secs: Some(3),
+        };
+        assert_eq!(policy.delay_for(1, &limited), Some(Duration::from_secs(3)));
+        assert_eq!(policy.delay_for(3, &limited), None);
+
+        let too_long = ApiError::RateLimited {
+            retry_after_secs: Some(3600),
+        };
+        assert_eq!(policy.delay_for(1, &too_long), None);
+
+        let auth = ApiError::Auth("bad key".into());
+        assert_eq!(policy.delay_for(1, &auth), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_until_success() {
+        let mut calls = 0;
+        let result = fast_policy()
+            .run(|| {
+                calls += 1;
+                let attempt = calls;
+                async move {
+                    if attempt < 3 {
+                        Err(ApiError::Overloaded {
+                            retry_after_secs: None,
+                        })
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_gives_up_after_max_retries() {
+        let mut calls = 0;
+        let result: Result<(), ApiError> = fast_policy()
+            .with_max_retries(1)
+            .run(|| {
+                calls += 1;
+                async {
+                    Err(ApiError::ServerError {
+                        status: 502,
+                        message: "bad gateway".into(),
+                    })
+                }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+    }
+}
@@ -0,0 +1,217 @@
+//! Per-request sampling parameters
+//!
+//! Values set on a request override the provider config's `temperature` and
+//! `max_tokens`. Parameters a provider does not accept are left out of its
+//! request: Anthropic has no seed or penalties, and OpenAI has no `top_k`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Sampling controls for a single request; unset fields use provider defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling probability mass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Sample only from the k most likely tokens (not supported by OpenAI)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// Maximum tokens to generate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Sequences that end generation when produced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Seed for best-effort deterministic sampling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+}
+
+impl SamplingParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_stop<S: Into<String>>(mut self, stop: impl IntoIterator<Item = S>) -> Self {
+        self.stop = stop.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, penalty: f32) -> Self {
+        self.presence_penalty = Some(penalty);
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, penalty: f32) -> Self {
+        self.frequency_penalty = Some(penalty);
+        self
+    }
+
+    /// Whether no parameter is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fill unset `temperature` and `max_tokens` from provider defaults
+    pub(crate) fn or_defaults(&self, temperature: Option<f32>, max_tokens: Option<u32>) -> Self {
+        Self {
+            temperature: self.temperature.or(temperature),
+            max_tokens: self.max_tokens.or(max_tokens),
+            ..self.clone()
+        }
+    }
+}
+
+// ─── Provider wire formats ──────────────────────────────────────────────────
+
+/// Anthropic Messages fields; `max_tokens` is required and set by the caller
+pub(crate) fn anthropic_sampling(params: &SamplingParams, body: &mut Value) {
+    if let Some(temp) = params.temperature {
+        body["temperature"] = json!(temp);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(top_k) = params.top_k {
+        body["top_k"] = json!(top_k);
+    }
+    if !params.stop.is_empty() {
+        body["stop_sequences"] = json!(params.stop);
+    }
+}
+
+/// OpenAI-compatible fields; `top_k` is only sent to servers that take it
+pub(crate) fn openai_sampling(params: &SamplingParams, body: &mut Value, top_k: bool) {
+    if let Some(max_tokens) = params.max_tokens {
+        body["max_tokens"] = json!(max_tokens);
+    }
+    if let Some(temp) = params.temperature {
+        body["temperature"] = json!(temp);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(k) = params.top_k.filter(|_| top_k) {
+        body["top_k"] = json!(k);
+    }
+    if !params.stop.is_empty() {
+        body["stop"] = json!(params.stop);
+    }
+    if let Some(seed) = params.seed {
+        body["seed"] = json!(seed);
+    }
+    if let Some(penalty) = params.presence_penalty {
+        body["presence_penalty"] = json!(penalty);
+    }
+    if let Some(penalty) = params.frequency_penalty {
+        body["frequency_penalty"] = json!(penalty);
+    }
+}
+
+/// Ollama `options`
+pub(crate) fn ollama_options(params: &SamplingParams, options: &mut Map<String, Value>) {
+    let mut set = |key: &str, value: Value| {
+        options.insert(key.to_string(), value);
+    };
+    if let Some(temp) = params.temperature {
+        set("temperature", json!(temp));
+    }
+    if let Some(top_p) = params.top_p {
+        set("top_p", json!(top_p));
+    }
+    if let Some(top_k) = params.top_k {
+        set("top_k", json!(top_k));
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        set("num_predict", json!(max_tokens));
+    }
+    if !params.stop.is_empty() {
+        set("stop", json!(params.stop));
+    }
+    if let Some(seed) = params.seed {
+        set("seed", json!(seed));
+    }
+    if let Some(penalty) = params.presence_penalty {
+        set("presence_penalty", json!(penalty));
+    }
+    if let Some(penalty) = params.frequency_penalty {
+        set("frequency_penalty", json!(penalty));
+    }
+}
+
+/// Gemini `generationConfig`
+pub(crate) fn gemini_sampling(params: &SamplingParams, config: &mut Map<String, Value>) {
+    let mut set = |key: &str, value: Value| {
+        config.insert(key.to_string(), value);
+    };
+    if let Some(max_tokens) = params.max_tokens {
+        set("maxOutputTokens", json!(max_tokens));
+    }
+    if let Some(temp) = params.temperature {
+        set("temperature", json!(temp));
+    }
+    if let Some(top_p) = params.top_p {
+        set("topP", json!(top_p));
+    }
+    if let Some(top_k) = params.top_k {
+        set("topK", json!(top_k));
+    }
+    if !params.stop.is_empty() {
+        set("stopSequences", json!(params.stop));
+    }
+    if let Some(seed) = params.seed {
+        set("seed", json!(seed));
+    }
+    if let Some(penalty) = params.presence_penalty {
+        set("presencePenalty", json!(penalty));
+    }
+    if let Some(penalty) = params.frequency_penalty {
+        set("frequencyPenalty", json!(penalty));
+    }
+}
+
+/// Stop sequence matched by an OpenAI-compatible server.
+///
+/// OpenAI reports `finish_reason: "stop"` for both a natural end and a stop
+/// sequence; vLLM (`stop_reason`) and SGLang (`matched_stop`) also say which
+/// sequence matched.
+pub(crate) fn openai_stop_sequence(choice: &Value) -> Option<String> {
+    if choice["finish_reason"].as_str() != Some("stop") {
+        return None;
+    }
+    choice["stop_reason"]
+        .as_str()
+        .or_else(|| choice["matched_stop"].as_str())
+        .map(str::to_string)
+}
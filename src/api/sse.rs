@@ -1,12 +1,17 @@
 //! Server-Sent Events (SSE) parser for streaming API responses
 //!
-//! Handles three formats:
+//! Handles four formats:
 //! - OpenAI/Venice: `data: {"choices":[{"delta":{"content":"..."}}]}`
 //! - Anthropic: `event: content_block_delta` / `data: {"delta":{"text":"..."}}`
 //! - Ollama: line-delimited JSON `{"response":"..."}`
+//! - Gemini: `data: {"candidates":[{"content":{"parts":[{"text":"..."}]}}]}`
+//!
+//! Besides text, the parsers surface tool-call starts and input-JSON deltas,
+//! thinking/reasoning deltas and usage reported mid-stream.
 
 use super::streaming::StreamChunk;
-use super::TokenUsage;
+use super::tools::parse_gemini_parts;
+use super::{StopReason, TokenUsage};
 use serde_json::Value;
 
 /// The format of SSE events from the provider
@@ -18,133 +23,279 @@ pub enum SseFormat {
     Anthropic,
     /// Ollama line-delimited JSON
     Ollama,
+    /// Gemini `streamGenerateContent?alt=sse`
+    Gemini,
 }
 
 /// Parse a single SSE line or data payload into a StreamChunk.
 /// Returns None if the line should be skipped (comments, empty lines, event types).
+///
+/// A single payload can carry several chunks (e.g. an OpenAI delta that both
+/// starts a tool call and carries its first arguments); this returns the
+/// first of them. Use [`parse_sse_events`] to get all of them.
 pub fn parse_sse_line(line: &str, format: SseFormat) -> Option<StreamChunk> {
-    let line = line.trim();
+    parse_sse_events(line, format).into_iter().next()
+}
+
+/// Parse a single SSE line or data payload into all the chunks it carries.
+///
+/// Each call starts a fresh stream; use [`SseParser`] to parse the lines of
+/// one stream in turn.
+pub fn parse_sse_events(line: &str, format: SseFormat) -> Vec<StreamChunk> {
+    SseParser::new(format).parse(line)
+}
 
-    // Skip empty lines and SSE comments
-    if line.is_empty() || line.starts_with(':') {
-        return None;
+/// Parses the lines of one stream, keeping what must carry across lines
+pub struct SseParser {
+    format: SseFormat,
+    /// Tool calls seen so far; Gemini does not number its calls, so they
+    /// are numbered here across the whole stream
+    tool_calls: usize,
+}
+
+impl SseParser {
+    pub fn new(format: SseFormat) -> Self {
+        Self {
+            format,
+            tool_calls: 0,
+        }
     }
 
-    match format {
-        SseFormat::OpenAI => parse_openai_sse(line),
-        SseFormat::Anthropic => parse_anthropic_sse(line),
-        SseFormat::Ollama => parse_ollama_line(line),
+    /// Parse the next line of the stream into all the chunks it carries
+    pub fn parse(&mut self, line: &str) -> Vec<StreamChunk> {
+        let line = line.trim();
+
+        // Skip empty lines and SSE comments
+        if line.is_empty() || line.starts_with(':') {
+            return Vec::new();
+        }
+
+        match self.format {
+            SseFormat::OpenAI => parse_openai_sse(line),
+            SseFormat::Anthropic => parse_anthropic_sse(line),
+            SseFormat::Ollama => parse_ollama_line(line),
+            SseFormat::Gemini => parse_gemini_sse(line, &mut self.tool_calls),
+        }
     }
 }
 
-fn parse_openai_sse(line: &str) -> Option<StreamChunk> {
+fn parse_openai_sse(line: &str) -> Vec<StreamChunk> {
     // Only process data lines
-    let data = line.strip_prefix("data: ")?;
+    let Some(data) = line.strip_prefix("data: ") else {
+        return Vec::new();
+    };
 
     // Check for stream end
     if data.trim() == "[DONE]" {
-        return Some(StreamChunk::Done(TokenUsage::default()));
+        return vec![StreamChunk::Done(TokenUsage::default())];
     }
 
     // Parse JSON
     let json: Value = match serde_json::from_str(data) {
         Ok(v) => v,
-        Err(e) => return Some(StreamChunk::Error(format!("JSON parse error: {}", e))),
+        Err(e) => return vec![StreamChunk::Error(format!("JSON parse error: {}", e))],
     };
 
+    let mut chunks = Vec::new();
+    let delta = &json["choices"][0]["delta"];
+
+    // Reasoning models (DeepSeek R1 and compatible APIs) stream their
+    // thinking separately from the answer
+    for key in ["reasoning_content", "reasoning"] {
+        if let Some(thinking) = delta[key].as_str() {
+            if !thinking.is_empty() {
+                chunks.push(StreamChunk::ThinkingDelta(thinking.to_string()));
+            }
+        }
+    }
+
     // Check for content delta
-    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
+    if let Some(content) = delta["content"].as_str() {
         if !content.is_empty() {
-            return Some(StreamChunk::TextDelta(content.to_string()));
+            chunks.push(StreamChunk::TextDelta(content.to_string()));
         }
     }
 
-    // Check for finish_reason
-    if let Some(reason) = json["choices"][0]["finish_reason"].as_str() {
-        if reason == "stop" || reason == "length" {
-            let usage = if let Some(usage_obj) = json.get("usage") {
-                TokenUsage::new(
-                    usage_obj["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-                    usage_obj["completion_tokens"].as_u64().unwrap_or(0) as u32,
-                )
-            } else {
-                TokenUsage::default()
-            };
-            return Some(StreamChunk::Done(usage));
+    // Tool calls: the first delta for an index carries id and name, later
+    // ones carry argument fragments
+    for call in delta["tool_calls"].as_array().into_iter().flatten() {
+        let index = call["index"].as_u64().unwrap_or(0) as usize;
+        if let Some(id) = call["id"].as_str() {
+            chunks.push(StreamChunk::ToolUseStart {
+                index,
+                id: id.to_string(),
+                name: call["function"]["name"].as_str().unwrap_or("").to_string(),
+            });
+        }
+        if let Some(args) = call["function"]["arguments"].as_str() {
+            if !args.is_empty() {
+                chunks.push(StreamChunk::ToolInputDelta {
+                    index,
+                    partial_json: args.to_string(),
+                });
+            }
         }
     }
 
-    None
+    let usage = json
+        .get("usage")
+        .filter(|u| u.is_object())
+        .map(parse_openai_usage);
+
+    // The finish_reason chunk ends generation, not the stream: with
+    // `include_usage` a usage-only chunk follows it, then `[DONE]`
+    if let Some(reason) = json["choices"][0]["finish_reason"].as_str() {
+        chunks.push(StreamChunk::Stop(StopReason::from_openai(reason)));
+    }
+
+    if let Some(usage) = usage {
+        chunks.push(StreamChunk::Usage(usage));
+    }
+
+    chunks
 }
 
-fn parse_anthropic_sse(line: &str) -> Option<StreamChunk> {
+fn parse_openai_usage(usage_obj: &Value) -> TokenUsage {
+    TokenUsage::from_openai(usage_obj)
+}
+
+fn parse_anthropic_sse(line: &str) -> Vec<StreamChunk> {
     // Skip event type lines (we process based on data content)
     if line.starts_with("event:") {
-        return None;
+        return Vec::new();
     }
 
-    let data = line.strip_prefix("data: ")?;
+    let Some(data) = line.strip_prefix("data: ") else {
+        return Vec::new();
+    };
 
     let json: Value = match serde_json::from_str(data) {
         Ok(v) => v,
-        Err(e) => return Some(StreamChunk::Error(format!("JSON parse error: {}", e))),
+        Err(e) => return vec![StreamChunk::Error(format!("JSON parse error: {}", e))],
     };
 
     // Check event type in the data
     let event_type = json["type"].as_str().unwrap_or("");
+    let index = json["index"].as_u64().unwrap_or(0) as usize;
 
     match event_type {
+        "message_start" => {
+            // Input and cache token counts arrive up front
+            let usage_obj = &json["message"]["usage"];
+            if usage_obj.is_object() {
+                return vec![StreamChunk::Usage(parse_anthropic_usage(usage_obj))];
+            }
+        }
+        "content_block_start" => {
+            let block = &json["content_block"];
+            if block["type"].as_str() == Some("tool_use") {
+                return vec![StreamChunk::ToolUseStart {
+                    index,
+                    id: block["id"].as_str().unwrap_or("").to_string(),
+                    name: block["name"].as_str().unwrap_or("").to_string(),
+                }];
+            }
+        }
         "content_block_delta" => {
-            if let Some(text) = json["delta"]["text"].as_str() {
-                if !text.is_empty() {
-                    return Some(StreamChunk::TextDelta(text.to_string()));
+            let delta = &json["delta"];
+            match delta["type"].as_str() {
+                Some("input_json_delta") => {
+                    let partial = delta["partial_json"].as_str().unwrap_or("");
+                    if !partial.is_empty() {
+                        return vec![StreamChunk::ToolInputDelta {
+                            index,
+                            partial_json: partial.to_string(),
+                        }];
+                    }
+                }
+                Some("thinking_delta") => {
+                    let thinking = delta["thinking"].as_str().unwrap_or("");
+                    if !thinking.is_empty() {
+                        return vec![StreamChunk::ThinkingDelta(thinking.to_string())];
+                    }
+                }
+                _ => {
+                    if let Some(text) = delta["text"].as_str() {
+                        if !text.is_empty() {
+                            return vec![StreamChunk::TextDelta(text.to_string())];
+                        }
+                    }
                 }
             }
         }
         "message_delta" => {
-            // Final message with usage info
-            let usage = if let Some(usage_obj) = json.get("usage") {
-                TokenUsage::new(
-                    usage_obj["input_tokens"].as_u64().unwrap_or(0) as u32,
-                    usage_obj["output_tokens"].as_u64().unwrap_or(0) as u32,
-                )
-            } else {
-                TokenUsage::default()
-            };
-            return Some(StreamChunk::Done(usage));
+            // Cumulative output token count; the stream ends at message_stop
+            if let Some(usage_obj) = json.get("usage") {
+                return vec![StreamChunk::Usage(parse_anthropic_usage(usage_obj))];
+            }
         }
         "message_stop" => {
-            return Some(StreamChunk::Done(TokenUsage::default()));
+            return vec![StreamChunk::Done(TokenUsage::default())];
         }
         "error" => {
             let msg = json["error"]["message"]
                 .as_str()
                 .unwrap_or("Unknown error");
-            return Some(StreamChunk::Error(msg.to_string()));
+            return vec![StreamChunk::Error(msg.to_string())];
         }
         _ => {}
     }
 
-    None
+    Vec::new()
+}
+
+fn parse_anthropic_usage(usage_obj: &Value) -> TokenUsage {
+    TokenUsage::from_anthropic(usage_obj)
 }
 
-fn parse_ollama_line(line: &str) -> Option<StreamChunk> {
+fn parse_ollama_line(line: &str) -> Vec<StreamChunk> {
     let json: Value = match serde_json::from_str(line) {
         Ok(v) => v,
-        Err(_) => return None,
+        Err(_) => return Vec::new(),
     };
 
+    let mut chunks = Vec::new();
+
+    // Thinking models stream reasoning in a separate field
+    if let Some(thinking) = json["message"]["thinking"].as_str() {
+        if !thinking.is_empty() {
+            chunks.push(StreamChunk::ThinkingDelta(thinking.to_string()));
+        }
+    }
+
     // Ollama chat format
     if let Some(content) = json["message"]["content"].as_str() {
         if !content.is_empty() {
-            return Some(StreamChunk::TextDelta(content.to_string()));
+            chunks.push(StreamChunk::TextDelta(content.to_string()));
         }
     }
 
+    // Ollama sends complete tool calls in a single chunk
+    for (index, call) in json["message"]["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let id = call["id"]
+            .as_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("call_{}", index));
+        chunks.push(StreamChunk::ToolUseStart {
+            index,
+            id,
+            name: call["function"]["name"].as_str().unwrap_or("").to_string(),
+        });
+        chunks.push(StreamChunk::ToolInputDelta {
+            index,
+            partial_json: call["function"]["arguments"].to_string(),
+        });
+    }
+
     // Ollama generate format
     if let Some(response) = json["response"].as_str() {
         if !response.is_empty() {
-            return Some(StreamChunk::TextDelta(response.to_string()));
+            chunks.push(StreamChunk::TextDelta(response.to_string()));
         }
     }
 
@@ -154,10 +305,72 @@ fn parse_ollama_line(line: &str) -> Option<StreamChunk> {
             json["prompt_eval_count"].as_u64().unwrap_or(0) as u32,
             json["eval_count"].as_u64().unwrap_or(0) as u32,
         );
-        return Some(StreamChunk::Done(usage));
+        chunks.push(StreamChunk::Done(usage));
     }
 
-    None
+    chunks
+}
+
+fn parse_gemini_sse(line: &str, tool_calls: &mut usize) -> Vec<StreamChunk> {
+    let Some(data) = line.strip_prefix("data: ") else {
+        return Vec::new();
+    };
+
+    let json: Value = match serde_json::from_str(data) {
+        Ok(v) => v,
+        Err(e) => return vec![StreamChunk::Error(format!("JSON parse error: {}", e))],
+    };
+
+    if let Some(msg) = json["error"]["message"].as_str() {
+        return vec![StreamChunk::Error(msg.to_string())];
+    }
+
+    let mut chunks = Vec::new();
+    let candidate = &json["candidates"][0];
+    let parts = &candidate["content"]["parts"];
+    let (text, thinking, calls) = parse_gemini_parts(parts);
+
+    if !thinking.is_empty() {
+        chunks.push(StreamChunk::ThinkingDelta(thinking));
+    }
+    if !text.is_empty() {
+        chunks.push(StreamChunk::TextDelta(text));
+    }
+
+    // Gemini sends each function call whole, in a single chunk. Calls without
+    // an id get one numbered across the stream, not just this chunk.
+    let raw_calls = parts.as_array().into_iter().flatten().filter_map(|p| p.get("functionCall"));
+    for (call, raw) in calls.into_iter().zip(raw_calls) {
+        let index = *tool_calls;
+        *tool_calls += 1;
+        let id = match raw["id"].as_str() {
+            Some(_) => call.id,
+            None => format!("call_{}", index),
+        };
+        chunks.push(StreamChunk::ToolUseStart {
+            index,
+            id,
+            name: call.name,
+        });
+        chunks.push(StreamChunk::ToolInputDelta {
+            index,
+            partial_json: call.input.to_string(),
+        });
+    }
+
+    // usageMetadata is cumulative and repeated on every chunk
+    let usage = json
+        .get("usageMetadata")
+        .filter(|u| u.is_object())
+        .map(TokenUsage::from_gemini);
+
+    if candidate["finishReason"].is_string() {
+        chunks.push(StreamChunk::Done(usage.unwrap_or_default()));
+    } else if let Some(usage) = usage {
+        chunks.push(StreamChunk::Usage(usage));
+    }
+
+    chunks
 }
 
 #[cfg(test)]
@@ -228,4 +441,144 @@ mod tests {
     fn test_comment_skipped() {
         assert!(parse_sse_line(": keep-alive", SseFormat::OpenAI).is_none());
     }
+
+    #[test]
+    fn test_anthropic_tool_use_start_and_input_delta() {
+        let start = r#"data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"read_file","input":{}}}"#;
+        match parse_sse_line(start, SseFormat::Anthropic) {
+            Some(StreamChunk::ToolUseStart { index, id, name }) => {
+                assert_eq!(index, 1);
+                assert_eq!(id, "toolu_1");
+                assert_eq!(name, "read_file");
+            }
+            other => panic!("Expected ToolUseStart, got {:?}", other),
+        }
+
+        let delta = r#"data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"path\":"}}"#;
+        match parse_sse_line(delta, SseFormat::Anthropic) {
+            Some(StreamChunk::ToolInputDelta { index, partial_json }) => {
+                assert_eq!(index, 1);
+                assert_eq!(partial_json, "{\"path\":");
+            }
+            other => panic!("Expected ToolInputDelta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_anthropic_thinking_delta() {
+        let line = r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"Let me see"}}"#;
+        match parse_sse_line(line, SseFormat::Anthropic) {
+            Some(StreamChunk::ThinkingDelta(text)) => assert_eq!(text, "Let me see"),
+            other => panic!("Expected ThinkingDelta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_anthropic_usage_events() {
+        let start = r#"data: {"type":"message_start","message":{"usage":{"input_tokens":25,"output_tokens":1,"cache_read_input_tokens":1000}}}"#;
+        match parse_sse_line(start, SseFormat::Anthropic) {
+            Some(StreamChunk::Usage(usage)) => {
+                assert_eq!(usage.prompt_tokens, 25);
+                assert_eq!(usage.cache_read_tokens, Some(1000));
+            }
+            other => panic!("Expected Usage, got {:?}", other),
+        }
+
+        let delta = r#"data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":42}}"#;
+        match parse_sse_line(delta, SseFormat::Anthropic) {
+            Some(StreamChunk::Usage(usage)) => assert_eq!(usage.completion_tokens, 42),
+            other => panic!("Expected Usage, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_openai_tool_call_start_with_arguments() {
+        let line = r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"grep","arguments":"{\"q\""}}]}}]}"#;
+        let chunks = parse_sse_events(line, SseFormat::OpenAI);
+        assert_eq!(chunks.len(), 2);
+        assert!(matches!(&chunks[0], StreamChunk::ToolUseStart { name, .. } if name == "grep"));
+        assert!(matches!(&chunks[1], StreamChunk::ToolInputDelta { index: 0, .. }));
+    }
+
+    #[test]
+    fn test_openai_reasoning_and_usage_chunk() {
+        let line = r#"data: {"choices":[{"delta":{"reasoning_content":"hmm"}}]}"#;
+        match parse_sse_line(line, SseFormat::OpenAI) {
+            Some(StreamChunk::ThinkingDelta(text)) => assert_eq!(text, "hmm"),
+            other => panic!("Expected ThinkingDelta, got {:?}", other),
+        }
+
+        let line = r#"data: {"choices":[],"usage":{"prompt_tokens":7,"completion_tokens":3}}"#;
+        match parse_sse_line(line, SseFormat::OpenAI) {
+            Some(StreamChunk::Usage(usage)) => assert_eq!(usage.total_tokens, 10),
+            other => panic!("Expected Usage, got {:?}", other),
+        }
+
+        // Cached prompt tokens are split out of prompt_tokens
+        let line = r#"data: {"choices":[],"usage":{"prompt_tokens":2048,"completion_tokens":3,"prompt_tokens_details":{"cached_tokens":1920}}}"#;
+        match parse_sse_line(line, SseFormat::OpenAI) {
+            Some(StreamChunk::Usage(usage)) => {
+                assert_eq!(usage.prompt_tokens, 128);
+                assert_eq!(usage.cache_read_tokens, Some(1920));
+            }
+            other => panic!("Expected Usage, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ollama_tool_call() {
+        let line = r#"{"message":{"content":"","tool_calls":[{"function":{"name":"ls","arguments":{"dir":"."}}}]},"done":false}"#;
+        let chunks = parse_sse_events(line, SseFormat::Ollama);
+        assert!(matches!(&chunks[0], StreamChunk::ToolUseStart { id, .. } if id == "call_0"));
+        match &chunks[1] {
+            StreamChunk::ToolInputDelta { partial_json, .. } => {
+                assert_eq!(partial_json, r#"{"dir":"."}"#)
+            }
+            other => panic!("Expected ToolInputDelta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_gemini_text_and_final_usage() {
+        let line = r#"data: {"candidates":[{"content":{"parts":[{"text":"Hi"}],"role":"model"}}],"usageMetadata":{"promptTokenCount":10,"candidatesTokenCount":1}}"#;
+        let chunks = parse_sse_events(line, SseFormat::Gemini);
+        assert!(matches!(&chunks[0], StreamChunk::TextDelta(t) if t == "Hi"));
+        assert!(matches!(&chunks[1], StreamChunk::Usage(_)));
+
+        let last = r#"data: {"candidates":[{"content":{"parts":[{"text":"!"}],"role":"model"},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":1200,"candidatesTokenCount":5,"cachedContentTokenCount":1000}}"#;
+        let chunks = parse_sse_events(last, SseFormat::Gemini);
+        match &chunks[1] {
+            StreamChunk::Done(usage) => {
+                assert_eq!(usage.prompt_tokens, 200);
+                assert_eq!(usage.cache_read_tokens, Some(1000));
+                assert_eq!(usage.completion_tokens, 5);
+            }
+            other => panic!("Expected Done, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_gemini_function_call() {
+        let line = r#"data: {"candidates":[{"content":{"parts":[{"functionCall":{"name":"ls","args":{"dir":"src"}}}],"role":"model"}}]}"#;
+        let chunks = parse_sse_events(line, SseFormat::Gemini);
+        assert!(matches!(&chunks[0], StreamChunk::ToolUseStart { name, .. } if name == "ls"));
+        assert!(
+            matches!(&chunks[1], StreamChunk::ToolInputDelta { partial_json, .. } if partial_json == r#"{"dir":"src"}"#)
+        );
+    }
+
+    #[test]
+    fn test_gemini_calls_numbered_across_stream() {
+        let line = r#"data: {"candidates":[{"content":{"parts":[{"functionCall":{"name":"ls","args":{}}}],"role":"model"}}]}"#;
+        let mut parser = SseParser::new(SseFormat::Gemini);
+        let starts: Vec<_> = [line, line]
+            .iter()
+            .flat_map(|line| parser.parse(line))
+            .filter_map(|chunk| match chunk {
+                StreamChunk::ToolUseStart { index, id, .. } => Some((index, id)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(starts, vec![(0, "call_0".to_string()), (1, "call_1".to_string())]);
+    }
 }
@@ -1,14 +1,32 @@
 //! Streaming response support for API providers
 
-use super::{ApiError, ApiRequest, TokenUsage};
+use super::sse::{SseFormat, SseParser};
+use super::{ApiError, ApiRequest, ApiResponse, StopReason, TokenUsage};
 use async_trait::async_trait;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
 /// A chunk of a streaming response
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum StreamChunk {
     /// A text delta (partial content)
     TextDelta(String),
+    /// The model started a tool call; `index` identifies it within the message
+    ToolUseStart {
+        index: usize,
+        id: String,
+        name: String,
+    },
+    /// A fragment of the JSON input for the tool call at `index`
+    ToolInputDelta { index: usize, partial_json: String },
+    /// Extended thinking / reasoning text
+    ThinkingDelta(String),
+    /// Usage reported mid-stream; already folded into the final `Done` usage
+    Usage(TokenUsage),
+    /// The model finished generating. Usage may still follow before `Done`.
+    Stop(Option<StopReason>),
     /// Stream completed with final usage stats
     Done(TokenUsage),
     /// An error occurred during streaming
@@ -25,3 +43,145 @@ pub trait StreamingProvider: Send + Sync {
         request: ApiRequest,
     ) -> Result<mpsc::Receiver<StreamChunk>, ApiError>;
 }
+
+/// Read an SSE (or line-delimited JSON) response body on a background task.
+///
+/// Usage chunks are forwarded as they arrive and merged into the usage
+/// carried by the final `Done` chunk, so consumers that only look at `Done`
+/// still see complete token counts.
+pub(crate) fn spawn_sse_reader(
+    response: reqwest::Response,
+    format: SseFormat,
+) -> mpsc::Receiver<StreamChunk> {
+    spawn_stream_reader(response.bytes_stream(), format)
+}
+
+/// [`spawn_sse_reader`] over any stream of body bytes
+pub(crate) fn spawn_stream_reader<S, B, E>(
+    mut stream: S,
+    format: SseFormat,
+) -> mpsc::Receiver<StreamChunk>
+where
+    S: Stream<Item = Result<B, E>> + Unpin + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        let mut buffer = String::new();
+        let mut usage = TokenUsage::default();
+        let mut parser = SseParser::new(format);
+
+        while let Some(chunk_result) = stream.next().await {
+            match chunk_result {
+                Ok(bytes) => {
+                    buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                    // Process complete lines
+                    while let Some(newline_pos) = buffer.find('\n') {
+                        let line = buffer[..newline_pos].to_string();
+                        buffer = buffer[newline_pos + 1..].to_string();
+
+                        for chunk in parser.parse(&line) {
+                            let chunk = match chunk {
+                                StreamChunk::Usage(u) => {
+                                    usage.merge(&u);
+                                    StreamChunk::Usage(u)
+                                }
+                                StreamChunk::Done(u) => {
+                                    usage.merge(&u);
+                                    StreamChunk::Done(usage.clone())
+                                }
+                                other => other,
+                            };
+                            let is_final =
+                                matches!(chunk, StreamChunk::Done(_) | StreamChunk::Error(_));
+                            if tx.send(chunk).await.is_err() {
+                                return; // Receiver dropped
+                            }
+                            if is_final {
+                                return;
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = tx
+                        .send(StreamChunk::Error(format!("Stream error: {}", e)))
+                        .await;
+                    return;
+                }
+            }
+        }
+
+        // If stream ends without a Done chunk, send one
+        let _ = tx.send(StreamChunk::Done(usage)).await;
+    });
+
+    rx
+}
+
+/// Split a blocking response into the chunks a stream would have produced
+pub(crate) fn chunks_from_response(response: &ApiResponse) -> Vec<StreamChunk> {
+    let mut chunks = Vec::new();
+    if !response.content.is_empty() {
+        chunks.push(StreamChunk::TextDelta(response.content.clone()));
+    }
+    for (index, call) in response.tool_calls.iter().enumerate() {
+        chunks.push(StreamChunk::ToolUseStart {
+            index,
+            id: call.id.clone(),
+            name: call.name.clone(),
+        });
+        chunks.push(StreamChunk::ToolInputDelta {
+            index,
+            partial_json: call.input.to_string(),
+        });
+    }
+    chunks.push(StreamChunk::Done(response.usage.clone()));
+    chunks
+}
+
+/// A receiver that yields `chunks` and then closes
+pub(crate) fn replay_chunks(chunks: Vec<StreamChunk>) -> mpsc::Receiver<StreamChunk> {
+    let (tx, rx) = mpsc::channel(chunks.len().max(1));
+    for chunk in chunks {
+        // Capacity covers every chunk, so this never waits
+        let _ = tx.try_send(chunk);
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_usage_after_finish_reason_reaches_done() {
+        // The real OpenAI sequence with `include_usage`, split mid-line
+        let body = concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"index\":0}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"index\":0,\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":2048,\"completion_tokens\":5,",
+            "\"prompt_tokens_details\":{\"cached_tokens\":1920}}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let parts: Vec<Result<&str, std::io::Error>> =
+            body.split_inclusive("\n\n").flat_map(|p| p.split_inclusive(',')).map(Ok).collect();
+        let mut rx = spawn_stream_reader(futures_util::stream::iter(parts), SseFormat::OpenAI);
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        assert!(matches!(chunks[1], StreamChunk::Stop(Some(StopReason::EndTurn))));
+        match chunks.last() {
+            Some(StreamChunk::Done(usage)) => {
+                assert_eq!((usage.prompt_tokens, usage.completion_tokens), (128, 5));
+                assert_eq!(usage.cache_read_tokens, Some(1920));
+            }
+            other => panic!("Expected Done, got {:?}", other),
+        }
+    }
+}
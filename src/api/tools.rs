@@ -0,0 +1,544 @@
+//! Tool (function) calling structures shared by all providers
+//!
+//! Tools are declared on an [`ApiRequest`](super::ApiRequest), the model answers
+//! with [`ToolCall`]s in the [`ApiResponse`](super::ApiResponse), and the caller
+//! sends [`ToolResult`]s back in the next request's history.
+
+use super::request::{Message, Role};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// A tool the model is allowed to call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    /// Tool name (must be unique within a request)
+    pub name: String,
+    /// What the tool does, shown to the model
+    pub description: String,
+    /// JSON Schema for the tool input
+    pub input_schema: Value,
+}
+
+/// How the model should choose between tools
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// Model decides whether to call a tool
+    Auto,
+    /// Model must call one of the tools
+    Any,
+    /// Model must call the named tool
+    Tool { name: String },
+    /// Model must not call any tool
+    None,
+}
+
+/// A tool invocation requested by the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Provider-assigned call id, echoed back in the matching [`ToolResult`]
+    pub id: String,
+    /// Name of the tool to invoke
+    pub name: String,
+    /// Parsed tool input
+    pub input: Value,
+}
+
+/// The outcome of running a tool, sent back to the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolResult {
+    /// Id of the [`ToolCall`] this answers
+    pub tool_use_id: String,
+    /// Tool output
+    pub content: String,
+    /// Whether the tool failed
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl ToolDefinition {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, input_schema: Value) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            input_schema,
+        }
+    }
+}
+
+impl ToolResult {
+    pub fn success(tool_use_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_use_id: tool_use_id.into(),
+            content: content.into(),
+            is_error: false,
+        }
+    }
+
+    pub fn error(tool_use_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_use_id: tool_use_id.into(),
+            content: content.into(),
+            is_error: true,
+        }
+    }
+}
+
+// ─── Anthropic wire format ──────────────────────────────────────────────────
+
+pub(crate) fn anthropic_tools(tools: &[ToolDefinition]) -> Value {
+    Value::Array(
+        tools
+            .iter()
+            .map(|t| {
+                json!({
+                    "name": t.name,
+                    "description": t.description,
+                    "input_schema": t.input_schema,
+                })
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn anthropic_tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!({ "type": "auto" }),
+        ToolChoice::Any => json!({ "type": "any" }),
+        ToolChoice::Tool { name } => json!({ "type": "tool", "name": name }),
+        ToolChoice::None => json!({ "type": "none" }),
+    }
+}
+
+/// Message content for Anthropic: a plain string, or content blocks when the
+/// message carries tool calls or tool results
+pub(crate) fn anthropic_message_content(msg: &Message) -> Value {
+    if msg.tool_calls.is_empty() && msg.tool_results.is_empty() {
+        return json!(msg.content);
+    }
+
+    let mut blocks = Vec::new();
+
+    for result in &msg.tool_results {
+        blocks.push(json!({
+            "type": "tool_result",
+            "tool_use_id": result.tool_use_id,
+            "content": result.content,
+            "is_error": result.is_error,
+        }));
+    }
+
+    if !msg.content.is_empty() {
+        blocks.push(json!({ "type": "text", "text": msg.content }));
+    }
+
+    for call in &msg.tool_calls {
+        blocks.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.name,
+            "input": call.input,
+        }));
+    }
+
+    Value::Array(blocks)
+}
+
+/// Split an Anthropic `content` array into concatenated text and tool calls
+pub(crate) fn parse_anthropic_content(content: &Value) -> (String, Vec<ToolCall>) {
+    let mut text = String::new();
+    let mut calls = Vec::new();
+
+    for block in content.as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or("")),
+            Some("tool_use") => calls.push(ToolCall {
+                id: block["id"].as_str().unwrap_or("").to_string(),
+                name: block["name"].as_str().unwrap_or("").to_string(),
+                input: block["input"].clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    (text, calls)
+}
+
+// ─── OpenAI wire format (also Venice and other compatible APIs) ─────────────
+
+pub(crate) fn openai_tools(tools: &[ToolDefinition]) -> Value {
+    Value::Array(
+        tools
+            .iter()
+            .map(|t| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": t.input_schema,
+                    }
+                })
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn openai_tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::Any => json!("required"),
+        ToolChoice::Tool { name } => json!({ "type": "function", "function": { "name": name } }),
+        ToolChoice::None => json!("none"),
+    }
+}
+
+/// Convert a history message to OpenAI messages.
+///
+/// Tool results become one `tool` message each, since OpenAI has no
+/// equivalent of Anthropic's `tool_result` content block.
+pub(crate) fn openai_messages(msg: &Message) -> Vec<Value> {
+    let role = match msg.role {
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::System => "system",
+    };
+
+    let mut out = Vec::new();
+
+    for result in &msg.tool_results {
+        out.push(json!({
+            "role": "tool",
+            "tool_call_id": result.tool_use_id,
+            "content": result.content,
+        }));
+    }
+
+    if !msg.tool_calls.is_empty() {
+        let calls: Vec<Value> = msg
+            .tool_calls
+            .iter()
+            .map(|c| {
+                json!({
+                    "id": c.id,
+                    "type": "function",
+                    "function": {
+                        "name": c.name,
+                        "arguments": c.input.to_string(),
+                    }
+                })
+            })
+            .collect();
+        let content = if msg.content.is_empty() {
+            Value::Null
+        } else {
+            json!(msg.content)
+        };
+        out.push(json!({
+            "role": role,
+            "content": content,
+            "tool_calls": calls,
+        }));
+    } else if !msg.content.is_empty() || msg.tool_results.is_empty() {
+        out.push(json!({
+            "role": role,
+            "content": msg.content,
+        }));
+    }
+
+    out
+}
+
+/// Parse `tool_calls` from an OpenAI `message` object.
+///
+/// Arguments arrive as a JSON-encoded string; if that string is not valid
+/// JSON it is kept verbatim as a string value.
+pub(crate) fn parse_openai_tool_calls(message: &Value) -> Vec<ToolCall> {
+    message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|call| {
+            let input = match &call["function"]["arguments"] {
+                Value::String(args) => {
+                    serde_json::from_str(args).unwrap_or_else(|_| Value::String(args.clone()))
+                }
+                other => other.clone(),
+            };
+            ToolCall {
+                id: call["id"].as_str().unwrap_or("").to_string(),
+                name: call["function"]["name"].as_str().unwrap_or("").to_string(),
+                input,
+            }
+        })
+        .collect()
+}
+
+// ─── Ollama native /api/chat format ─────────────────────────────────────────
+//
+// Tool declarations use the OpenAI shape, but call arguments are JSON
+// objects rather than encoded strings and calls carry no ids.
+
+/// Convert the history to Ollama chat messages. Results name the tool they
+/// answer, which Ollama uses to pair them with their calls.
+pub(crate) fn ollama_messages(messages: &[Message]) -> Vec<Value> {
+    let mut call_names: HashMap<&str, &str> = HashMap::new();
+    let mut out = Vec::new();
+
+    for msg in messages {
+        let role = match msg.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+        };
+
+        for result in &msg.tool_results {
+            let name = call_names
+                .get(result.tool_use_id.as_str())
+                .copied()
+                .unwrap_or(result.tool_use_id.as_str());
+            out.push(json!({
+                "role": "tool",
+                "tool_name": name,
+                "content": result.content,
+            }));
+        }
+
+        if !msg.tool_calls.is_empty() {
+            let calls: Vec<Value> = msg
+                .tool_calls
+                .iter()
+                .map(|c| json!({ "function": { "name": c.name, "arguments": c.input } }))
+                .collect();
+            for call in &msg.tool_calls {
+                call_names.insert(&call.id, &call.name);
+            }
+            out.push(json!({
+                "role": role,
+                "content": msg.content,
+                "tool_calls": calls,
+            }));
+        } else if !msg.content.is_empty() || msg.tool_results.is_empty() {
+            out.push(json!({
+                "role": role,
+                "content": msg.content,
+            }));
+        }
+    }
+
+    out
+}
+
+/// Parse `tool_calls` from an Ollama `message` object, assigning `call_{i}`
+/// ids since Ollama does not return any
+pub(crate) fn parse_ollama_tool_calls(message: &Value) -> Vec<ToolCall> {
+    parse_openai_tool_calls(message)
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut call)| {
+            if call.id.is_empty() {
+                call.id = format!("call_{}", i);
+            }
+            call
+        })
+        .collect()
+}
+
+// ─── Gemini generateContent format ──────────────────────────────────────────
+//
+// Gemini matches a `functionResponse` to its call by function name rather
+// than by id, so results are paired with the name of the call they answer.
+
+pub(crate) fn gemini_tools(tools: &[ToolDefinition]) -> Value {
+    let declarations: Vec<Value> = tools
+        .iter()
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "description": t.description,
+                "parameters": t.input_schema,
+            })
+        })
+        .collect();
+    json!([{ "functionDeclarations": declarations }])
+}
+
+pub(crate) fn gemini_tool_config(choice: &ToolChoice) -> Value {
+    let config = match choice {
+        ToolChoice::Auto => json!({ "mode": "AUTO" }),
+        ToolChoice::Any => json!({ "mode": "ANY" }),
+        ToolChoice::Tool { name } => json!({ "mode": "ANY", "allowedFunctionNames": [name] }),
+        ToolChoice::None => json!({ "mode": "NONE" }),
+    };
+    json!({ "functionCallingConfig": config })
+}
+
+/// Convert conversation history to Gemini `contents`
+pub(crate) fn gemini_contents(messages: &[Message]) -> Vec<Value> {
+    let mut call_names: HashMap<&str, &str> = HashMap::new();
+    let mut contents = Vec::new();
+
+    for msg in messages {
+        let mut parts = Vec::new();
+
+        for result in &msg.tool_results {
+            let name = call_names
+                .get(result.tool_use_id.as_str())
+                .copied()
+                .unwrap_or(result.tool_use_id.as_str());
+            let key = if result.is_error { "error" } else { "content" };
+            parts.push(json!({
+                "functionResponse": {
+                    "name": name,
+                    "response": { key: result.content },
+                }
+            }));
+        }
+
+        if !msg.content.is_empty() {
+            parts.push(json!({ "text": msg.content }));
+        }
+
+        for call in &msg.tool_calls {
+            call_names.insert(&call.id, &call.name);
+            parts.push(json!({
+                "functionCall": { "name": call.name, "args": call.input }
+            }));
+        }
+
+        if parts.is_empty() {
+            parts.push(json!({ "text": "" }));
+        }
+
+        let role = match msg.role {
+            Role::Assistant => "model",
+            Role::User | Role::System => "user",
+        };
+        contents.push(json!({ "role": role, "parts": parts }));
+    }
+
+    contents
+}
+
+/// Split Gemini candidate `parts` into text, thinking text and tool calls.
+///
+/// Calls get `call_{i}` ids unless the API supplied one.
+pub(crate) fn parse_gemini_parts(parts: &Value) -> (String, String, Vec<ToolCall>) {
+    let mut text = String::new();
+    let mut thinking = String::new();
+    let mut calls = Vec::new();
+
+    for part in parts.as_array().into_iter().flatten() {
+        if let Some(call) = part.get("functionCall") {
+            let id = call["id"]
+                .as_str()
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| format!("call_{}", calls.len()));
+            calls.push(ToolCall {
+                id,
+                name: call["name"].as_str().unwrap_or("").to_string(),
+                input: call["args"].clone(),
+            });
+        } else if let Some(t) = part["text"].as_str() {
+            if part["thought"].as_bool() == Some(true) {
+                thinking.push_str(t);
+            } else {
+                text.push_str(t);
+            }
+        }
+    }
+
+    (text, thinking, calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_anthropic_content_mixed() {
+        let content = json!([
+            { "type": "text", "text": "Let me check. " },
+            { "type": "tool_use", "id": "toolu_1", "name": "read_file", "input": { "path": "a.rs" } }
+        ]);
+        let (text, calls) = parse_anthropic_content(&content);
+        assert_eq!(text, "Let me check. ");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "read_file");
+        assert_eq!(calls[0].input["path"], "a.rs");
+    }
+
+    #[test]
+    fn test_parse_openai_tool_calls_decodes_arguments() {
+        let message = json!({
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "grep", "arguments": "{\"pattern\":\"fn main\"}" }
+            }]
+        });
+        let calls = parse_openai_tool_calls(&message);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].input["pattern"], "fn main");
+    }
+
+    #[test]
+    fn test_openai_messages_splits_tool_results() {
+        let msg = Message::tool_results(vec![
+            ToolResult::success("call_1", "ok"),
+            ToolResult::error("call_2", "not found"),
+        ]);
+        let out = openai_messages(&msg);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0]["role"], "tool");
+        assert_eq!(out[1]["tool_call_id"], "call_2");
+    }
+
+    #[test]
+    fn test_ollama_messages_keep_object_arguments() {
+        let history = vec![
+            Message::assistant_with_tool_calls(
+                "",
+                vec![ToolCall {
+                    id: "call_0".to_string(),
+                    name: "ls".to_string(),
+                    input: json!({ "dir": "." }),
+                }],
+            ),
+            Message::tool_results(vec![ToolResult::success("call_0", "a.rs")]),
+        ];
+        let out = ollama_messages(&history);
+        assert_eq!(out[0]["tool_calls"][0]["function"]["arguments"]["dir"], ".");
+        assert_eq!(out[1]["role"], "tool");
+        assert_eq!(out[1]["tool_name"], "ls");
+    }
+
+    #[test]
+    fn test_gemini_contents_pair_results_with_call_names() {
+        let history = vec![
+            Message::user("list files"),
+            Message::assistant_with_tool_calls(
+                "",
+                vec![ToolCall {
+                    id: "call_0".to_string(),
+                    name: "ls".to_string(),
+                    input: json!({ "dir": "." }),
+                }],
+            ),
+            Message::tool_results(vec![ToolResult::success("call_0", "a.rs")]),
+        ];
+        let contents = gemini_contents(&history);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][0]["functionCall"]["name"], "ls");
+        let response = &contents[2]["parts"][0]["functionResponse"];
+        assert_eq!(response["name"], "ls");
+        assert_eq!(response["response"]["content"], "a.rs");
+    }
+
+    #[test]
+    fn test_anthropic_message_content_plain_text() {
+        let msg = Message::user("hello");
+        assert_eq!(anthropic_message_content(&msg), json!("hello"));
+    }
+}
@@ -1,10 +1,15 @@
 //! Venice.ai API provider with credit tracking and fallback support
 
-use super::sse::{parse_sse_line, SseFormat};
-use super::streaming::{StreamChunk, StreamingProvider};
-use super::{ApiError, ApiProvider, ApiRequest, ApiResponse, ProviderType, TokenUsage};
+use super::classify::classify_error;
+use super::format::{apply_response_format, openai_response_format};
+use super::sampling::{openai_sampling, openai_stop_sequence};
+use super::retry::RetryPolicy;
+use super::sse::SseFormat;
+use super::streaming::{spawn_sse_reader, StreamChunk, StreamingProvider};
+use super::tools::{openai_messages, openai_tool_choice, openai_tools, parse_openai_tool_calls};
+use super::{ApiError, ApiProvider, ApiRequest, ApiResponse, ProviderType, StopReason, TokenUsage};
+use crate::tokenizer::tokenizer_for_model;
 use async_trait::async_trait;
-use futures_util::StreamExt;
 use reqwest::{Client, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -64,6 +69,7 @@ pub struct VeniceProvider {
     client: Client,
     balance: Arc<RwLock<VeniceBalance>>,
     credits_exhausted: Arc<AtomicBool>,
+    retry_policy: RetryPolicy,
 }
 
 impl VeniceProvider {
@@ -73,9 +79,21 @@ impl VeniceProvider {
             client: Client::new(),
             balance: Arc::new(RwLock::new(VeniceBalance::default())),
             credits_exhausted: Arc::new(AtomicBool::new(false)),
+            retry_policy: RetryPolicy::default(),
         }
     }
 
+    /// Set how rate-limited and transiently failing requests are retried
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Model requests are sent to
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
     fn base_url(&self) -> &str {
         self.config
             .base_url
@@ -107,22 +125,21 @@ impl VeniceProvider {
         if response.status().is_success() {
             let json: Value = response.json().await?;
 
+            let balance_usd = json["balance_usd"].as_f64().unwrap_or(0.0);
+            let balance_diem = json["balance_diem"].as_f64().unwrap_or(0.0);
+            // Below threshold, or topped up again after running out
+            let exhausted = balance_usd < self.config.min_balance_usd
+                && balance_diem < self.config.min_balance_diem;
             let balance = VeniceBalance {
-                balance_usd: json["balance_usd"].as_f64().unwrap_or(0.0),
-                balance_diem: json["balance_diem"].as_f64().unwrap_or(0.0),
-                exhausted: false,
+                balance_usd,
+                balance_diem,
+                exhausted,
                 last_updated: Some(std::time::Instant::now()),
             };
 
             // Update stored balance
             *self.balance.write().await = balance.clone();
-
-            // Check if below threshold
-            if balance.balance_usd < self.config.min_balance_usd
-                && balance.balance_diem < self.config.min_balance_diem
-            {
-                self.credits_exhausted.store(true, Ordering::SeqCst);
-            }
+            self.credits_exhausted.store(exhausted, Ordering::SeqCst);
 
             Ok(balance)
         } else {
@@ -133,6 +150,43 @@ impl VeniceProvider {
         }
     }
 
+    /// POST a chat completion and return the response if it succeeded.
+    ///
+    /// Updates the tracked balance from the response headers and marks
+    /// credits exhausted when a 429 is about quota rather than rate.
+    async fn post_chat(&self, url: &str, body: &Value) -> Result<Response, ApiError> {
+        let response = self
+            .client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", self.config.api_key))
+            .header("Content-Type", "application/json")
+            .json(body)
+            .send()
+            .await?;
+
+        // Update balance from headers before consuming response
+        self.update_balance_from_headers(&response).await;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let headers = response.headers().clone();
+        let error_text = response.text().await.unwrap_or_default();
+
+        // A quota error means credits are gone, as opposed to a rate limit
+        match classify_error(status.as_u16(), &headers, &error_text) {
+            ApiError::QuotaExceeded(_) => {
+                self.credits_exhausted.store(true, Ordering::SeqCst);
+                Err(ApiError::QuotaExceeded(
+                    "Venice credits exhausted - fallback required".to_string(),
+                ))
+            }
+            other => Err(other),
+        }
+    }
+
     /// Update balance from response headers
     async fn update_balance_from_headers(&self, response: &Response) {
         let mut balance = self.balance.write().await;
@@ -194,56 +248,57 @@ impl VeniceProvider {
 
         // Insert conversation history between context and current task
         for msg in &request.messages {
-            let role = match msg.role {
-                super::Role::User => "user",
-                super::Role::Assistant => "assistant",
-                super::Role::System => "system",
-            };
-            messages.push(json!({
-                "role": role,
-                "content": msg.content
-            }));
+            messages.extend(openai_messages(msg));
         }
 
         // Add the task
-        messages.push(json!({
-            "role": "user",
-            "content": request.task
-        }));
+        if !request.task.is_empty() || messages.is_empty() {
+            messages.push(json!({
+                "role": "user",
+                "content": request.task
+            }));
+        }
 
         let mut body = json!({
             "model": self.config.model,
             "messages": messages,
         });
 
-        if let Some(max_tokens) = self.config.max_tokens {
-            body["max_tokens"] = json!(max_tokens);
-        }
+        let sampling = request.sampling_or(self.config.temperature, self.config.max_tokens);
+        openai_sampling(&sampling, &mut body, true);
 
-        if let Some(temp) = self.config.temperature {
-            body["temperature"] = json!(temp);
+        if !request.tools.is_empty() {
+            body["tools"] = openai_tools(&request.tools);
+        }
+        if let Some(choice) = &request.tool_choice {
+            body["tool_choice"] = openai_tool_choice(choice);
+        }
+        if let Some(format) = openai_response_format(request.response_format()) {
+            body["response_format"] = format;
         }
 
         body
     }
 
     fn parse_response(&self, json: Value) -> Result<ApiResponse, ApiError> {
-        let content = json["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
+        let choice = &json["choices"][0];
+        let message = &choice["message"];
+        let content = message["content"].as_str().unwrap_or("").to_string();
+        let tool_calls = parse_openai_tool_calls(message);
 
-        let usage = TokenUsage::new(
-            json["usage"]["prompt_tokens"].as_u64().unwrap_or(0) as u32,
-            json["usage"]["completion_tokens"].as_u64().unwrap_or(0) as u32,
-        );
+        let usage = TokenUsage::from_openai(&json["usage"]);
 
         Ok(ApiResponse {
             content,
             usage,
             model: json["model"].as_str().unwrap_or(&self.config.model).to_string(),
-            truncated: json["choices"][0]["finish_reason"].as_str() == Some("length"),
-            stop_reason: None,
+            truncated: choice["finish_reason"].as_str() == Some("length"),
+            stop_reason: match openai_stop_sequence(choice) {
+                Some(_) => Some(StopReason::StopSequence),
+                None => choice["finish_reason"].as_str().and_then(StopReason::from_openai),
+            },
+            stop_sequence: openai_stop_sequence(choice),
+            tool_calls,
         })
     }
 }
@@ -253,7 +308,7 @@ impl ApiProvider for VeniceProvider {
     async fn send_request(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
         // Check if already exhausted
         if self.is_exhausted() {
-            return Err(ApiError::Provider(
+            return Err(ApiError::QuotaExceeded(
                 "Venice credits exhausted - fallback required".to_string(),
             ));
         }
@@ -261,48 +316,20 @@ impl ApiProvider for VeniceProvider {
         let url = format!("{}/chat/completions", self.base_url());
         let body = self.build_request(&request);
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
+        let json: Value = self
+            .retry_policy
+            .run(|| async {
+                let response = self.post_chat(&url, &body).await?;
+                Ok(response.json().await?)
+            })
             .await?;
 
-        // Update balance from headers before consuming response
-        self.update_balance_from_headers(&response).await;
-
-        let status = response.status();
-
-        if status.is_success() {
-            let json: Value = response.json().await?;
-            self.parse_response(json)
-        } else if status.as_u16() == 429 {
-            let error_text = response.text().await.unwrap_or_default();
-
-            // Check if it's a quota issue vs rate limit
-            if error_text.contains("insufficient")
-                || error_text.contains("quota")
-                || error_text.contains("balance")
-            {
-                self.credits_exhausted.store(true, Ordering::SeqCst);
-                Err(ApiError::Provider(
-                    "Venice credits exhausted - fallback required".to_string(),
-                ))
-            } else {
-                Err(ApiError::RateLimited {
-                    retry_after_secs: 60,
-                })
-            }
-        } else {
-            let error_text = response.text().await.unwrap_or_default();
-            Err(ApiError::Provider(format!("{}: {}", status, error_text)))
-        }
+        let response = self.parse_response(json)?;
+        apply_response_format(response, request.response_format())
     }
 
     fn estimate_tokens(&self, text: &str) -> usize {
-        text.len() / 4
+        tokenizer_for_model(&self.config.model).count(text)
     }
 
     fn provider_type(&self) -> ProviderType {
@@ -317,7 +344,7 @@ impl StreamingProvider for VeniceProvider {
         request: ApiRequest,
     ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
         if self.is_exhausted() {
-            return Err(ApiError::Provider(
+            return Err(ApiError::QuotaExceeded(
                 "Venice credits exhausted - fallback required".to_string(),
             ));
         }
@@ -325,75 +352,14 @@ impl StreamingProvider for VeniceProvider {
         let url = format!("{}/chat/completions", self.base_url());
         let mut body = self.build_request(&request);
         body["stream"] = json!(true);
+        body["stream_options"] = json!({ "include_usage": true });
 
         let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
+            .retry_policy
+            .run(|| self.post_chat(&url, &body))
             .await?;
 
-        // Update balance from headers before consuming stream body
-        self.update_balance_from_headers(&response).await;
-
-        let status = response.status();
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            if status.as_u16() == 429
-                && (error_text.contains("insufficient")
-                    || error_text.contains("quota")
-                    || error_text.contains("balance"))
-            {
-                self.credits_exhausted.store(true, Ordering::SeqCst);
-                return Err(ApiError::Provider(
-                    "Venice credits exhausted - fallback required".to_string(),
-                ));
-            }
-            return Err(ApiError::Provider(format!("{}: {}", status, error_text)));
-        }
-
-        let (tx, rx) = mpsc::channel(64);
-
-        tokio::spawn(async move {
-            let mut stream = response.bytes_stream();
-            let mut buffer = String::new();
-
-            while let Some(chunk_result) = stream.next().await {
-                match chunk_result {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-                        while let Some(newline_pos) = buffer.find('\n') {
-                            let line = buffer[..newline_pos].to_string();
-                            buffer = buffer[newline_pos + 1..].to_string();
-
-                            if let Some(chunk) = parse_sse_line(&line, SseFormat::OpenAI) {
-                                let is_done = matches!(chunk, StreamChunk::Done(_));
-                                let is_error = matches!(chunk, StreamChunk::Error(_));
-                                if tx.send(chunk).await.is_err() {
-                                    return;
-                                }
-                                if is_done || is_error {
-                                    return;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        let _ = tx
-                            .send(StreamChunk::Error(format!("Stream error: {}", e)))
-                            .await;
-                        return;
-                    }
-                }
-            }
-
-            let _ = tx.send(StreamChunk::Done(TokenUsage::default())).await;
-        });
-
-        Ok(rx)
+        Ok(spawn_sse_reader(response, SseFormat::OpenAI))
     }
 }
 
@@ -413,6 +379,19 @@ pub enum VeniceModel {
 }
 
 impl VeniceModel {
+    pub const ALL: [VeniceModel; 5] = [
+        VeniceModel::Llama3_3_70B,
+        VeniceModel::DeepSeekCoderV2,
+        VeniceModel::Qwen25Coder32B,
+        VeniceModel::VeniceSmall,
+        VeniceModel::GrokCodeFast,
+    ];
+
+    /// The model with API id `id`
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|model| model.model_id() == id)
+    }
+
     pub fn model_id(&self) -> &'static str {
         match self {
             VeniceModel::Llama3_3_70B => "llama-3.3-70b",
@@ -0,0 +1,106 @@
+//! Whether a cache breakpoint pays for itself
+//!
+//! Writing a prefix to the cache costs more than sending it uncached
+//! (1.25x for a 5-minute entry, 2x for a 1-hour one), so a breakpoint only
+//! saves money when enough later requests read the entry back before it
+//! expires or its content changes.
+
+use super::CacheControl;
+use crate::api::ModelPricing;
+use std::time::Duration;
+
+/// Expected later requests sharing a prefix when not configured
+pub const DEFAULT_EXPECTED_REUSES: f64 = 3.0;
+
+/// Expected time between requests when not configured
+pub const DEFAULT_REQUEST_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Prices a cache breakpoint from expected reuse, TTL and model pricing
+#[derive(Debug, Clone)]
+pub struct CacheCostModel {
+    pub pricing: ModelPricing,
+    /// Later requests expected to send the same prefix
+    pub expected_reuses: f64,
+    /// Expected time between those requests; reuses arriving after the TTL
+    /// find the entry expired and write it again
+    pub request_interval: Duration,
+}
+
+impl CacheCostModel {
+    pub fn new(pricing: ModelPricing) -> Self {
+        Self {
+            pricing,
+            expected_reuses: DEFAULT_EXPECTED_REUSES,
+            request_interval: DEFAULT_REQUEST_INTERVAL,
+        }
+    }
+
+    /// Cost model for a model with known prices
+    pub fn for_model(model: &str) -> Option<Self> {
+        ModelPricing::for_model(model).map(Self::new)
+    }
+
+    pub fn with_expected_reuses(mut self, reuses: f64) -> Self {
+        self.expected_reuses = reuses.max(0.0);
+        self
+    }
+
+    pub fn with_request_interval(mut self, interval: Duration) -> Self {
+        self.request_interval = interval;
+        self
+    }
+
+    /// Expected USD saved over the session by caching `tokens` behind
+    /// `control`, net of write premiums.
+    ///
+    /// `change_probability` is the chance the prefix differs between two
+    /// requests, and `read_multiplier` the price of a cache read relative
+    /// to base input. A negative result means the breakpoint costs money.
+    pub fn net_savings(
+        &self,
+        tokens: usize,
+        control: &CacheControl,
+        change_probability: f64,
+        read_multiplier: f64,
+    ) -> f64 {
+        let base = tokens as f64 / 1_000_000.0 * self.pricing.input_per_mtok;
+        let premium = (control.write_multiplier() - 1.0) * base;
+        let hit_probability = if self.request_interval <= control.ttl() {
+            1.0 - change_probability.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        // A hit saves the read discount; a miss writes the entry again
+        let per_reuse =
+            hit_probability * (1.0 - read_multiplier) * base - (1.0 - hit_probability) * premium;
+        self.expected_reuses * per_reuse - premium
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CACHE_READ_MULTIPLIER;
+
+    #[test]
+    fn test_breakpoint_pays_off_only_with_reuse() {
+        let model = CacheCostModel::new(ModelPricing::new(3.0, 15.0));
+        let five_min = CacheControl::default();
+
+        // 1M stable tokens read back 3 times: -0.75 + 3 * 2.70
+        let net = model.net_savings(1_000_000, &five_min, 0.0, CACHE_READ_MULTIPLIER);
+        assert!((net - 7.35).abs() < 1e-9);
+
+        // Never reused, or always changed: only the premium is paid
+        let once = model.clone().with_expected_reuses(0.0);
+        assert!(once.net_savings(1_000_000, &five_min, 0.0, CACHE_READ_MULTIPLIER) < 0.0);
+        assert!(model.net_savings(1_000_000, &five_min, 1.0, CACHE_READ_MULTIPLIER) < 0.0);
+
+        // Requests 10 minutes apart outlive a 5m entry but not a 1h one
+        let slow = model.with_request_interval(Duration::from_secs(600));
+        assert!(slow.net_savings(1_000_000, &five_min, 0.0, CACHE_READ_MULTIPLIER) < 0.0);
+        let one_hour = CacheControl::one_hour();
+        assert!(slow.net_savings(1_000_000, &one_hour, 0.0, CACHE_READ_MULTIPLIER) > 0.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Why a cached prefix missed
+//!
+//! A request reads from the cache only if its prefix matches an earlier
+//! one byte for byte and the entry has not expired. Fingerprinting every
+//! block of the prefix lets a miss be traced to the first block that
+//! diverged.
+
+use super::CacheStrategy;
+use crate::api::{fnv1a, ApiRequest, Role};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// Fingerprint of one prompt block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockFingerprint {
+    /// Human-readable name, e.g. "system" or "context src/main.rs"
+    pub label: String,
+    pub hash: u64,
+    /// Hash with whitespace runs collapsed, to spot whitespace-only edits
+    pub normalized_hash: u64,
+}
+
+impl BlockFingerprint {
+    fn new(label: String, text: &str) -> Self {
+        let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        // A stable hash, since fingerprints are saved between runs
+        Self {
+            label,
+            hash: fnv1a(text.as_bytes()),
+            normalized_hash: fnv1a(normalized.as_bytes()),
+        }
+    }
+}
+
+/// Fingerprints of a request's cacheable prefix, in request order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefixFingerprint {
+    /// System prompt, context blocks, then history messages
+    pub blocks: Vec<BlockFingerprint>,
+    pub sent_at: SystemTime,
+    /// Longest TTL among the request's cache breakpoints
+    pub ttl: Duration,
+    /// Estimated size of the prefix in tokens
+    #[serde(default)]
+    pub tokens: usize,
+    /// Whether the request marks any cache breakpoint
+    #[serde(default)]
+    pub breakpoints: bool,
+}
+
+impl PrefixFingerprint {
+    pub fn of(request: &ApiRequest) -> Self {
+        let mut blocks = Vec::new();
+        let mut chars = 0;
+        if let Some(system) = &request.system {
+            blocks.push(BlockFingerprint::new("system".to_string(), system));
+            chars += system.len();
+        }
+        for item in &request.context {
+            blocks.push(BlockFingerprint::new(format!("context {}", item.name), &item.content));
+            chars += item.content.len();
+        }
+        for (idx, message) in request.messages.iter().enumerate() {
+            let role = match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+                Role::System => "system",
+            };
+            let label = format!("message {} ({})", idx, role);
+            blocks.push(BlockFingerprint::new(label, &message.content));
+            chars += message.content.len();
+        }
+
+        // Bare context and rolling history breakpoints use the default TTL
+        let ttl = request
+            .system_cache_control
+            .iter()
+            .chain(request.context.iter().filter_map(|c| c.cache_control.as_ref()))
+            .map(|control| control.ttl())
+            .max()
+            .unwrap_or(super::CacheControl::default().ttl());
+
+        Self {
+            blocks,
+            sent_at: SystemTime::now(),
+            ttl,
+            tokens: chars / 4,
+            breakpoints: request.prefix_breakpoints() > 0
+                || !request.history_breakpoints().is_empty(),
+        }
+    }
+
+    /// Whether a provider caching by `strategy` can serve this prefix from
+    /// its cache: it is large enough and marked where the provider needs it
+    pub fn cacheable_by(&self, strategy: &dyn CacheStrategy) -> bool {
+        (self.breakpoints || !strategy.needs_breakpoints())
+            && strategy.cacheable_tokens(self.tokens) > 0
+    }
+
+    /// Explain why `next` cannot reuse the cache entry this prefix wrote,
+    /// or `None` if it can
+    pub fn diverge(&self, next: &PrefixFingerprint) -> Option<CacheMissReport> {
+        let (prev, curr) = (&self.blocks, &next.blocks);
+        let report = |block: usize, reason: MissReason| Some(CacheMissReport {
+            block,
+            label: curr
+                .get(block)
+                .or(prev.get(block))
+                .map(|b| b.label.clone())
+                .unwrap_or_default(),
+            reason,
+        });
+
+        for (idx, (before, after)) in prev.iter().zip(curr).enumerate() {
+            if before.hash == after.hash {
+                continue;
+            }
+            let moved_to = curr[idx..].iter().position(|b| b.hash == before.hash);
+            let moved_from = prev[idx..].iter().position(|b| b.hash == after.hash);
+            let reason = if before.normalized_hash == after.normalized_hash {
+                MissReason::WhitespaceChanged
+            } else {
+                match (moved_from, moved_to) {
+                    (Some(from), Some(_)) => MissReason::Reordered { from: idx + from },
+                    (None, Some(_)) => MissReason::Inserted,
+                    (Some(_), None) => MissReason::Removed {
+                        label: before.label.clone(),
+                    },
+                    (None, None) => MissReason::ContentEdited,
+                }
+            };
+            return report(idx, reason);
+        }
+
+        if curr.len() < prev.len() {
+            let removed = &prev[curr.len()];
+            return report(
+                curr.len(),
+                MissReason::Removed {
+                    label: removed.label.clone(),
+                },
+            );
+        }
+
+        let age = next.sent_at.duration_since(self.sent_at).unwrap_or_default();
+        if age > self.ttl {
+            return report(
+                0,
+                MissReason::Expired {
+                    age,
+                    ttl: self.ttl,
+                },
+            );
+        }
+        None
+    }
+}
+
+/// Where and why a prefix diverged from the previous request's
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheMissReport {
+    /// Index of the first diverging block
+    pub block: usize,
+    pub label: String,
+    pub reason: MissReason,
+}
+
+impl CacheMissReport {
+    /// A miss on a prefix that matches the previous one
+    pub fn unexplained() -> Self {
+        Self {
+            block: 0,
+            label: String::new(),
+            reason: MissReason::Unexplained,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MissReason {
+    /// The block previously at index `from` moved here
+    Reordered { from: usize },
+    ContentEdited,
+    /// Only whitespace differs, e.g. `strip_whitespace` ran on one request
+    /// but not the other
+    WhitespaceChanged,
+    /// A new block was inserted before the previous one
+    Inserted,
+    /// A previously sent block is gone
+    Removed { label: String },
+    /// The prefix is unchanged but the entry outlived its TTL
+    Expired { age: Duration, ttl: Duration },
+    /// The prefix is unchanged, cacheable and within its TTL; the provider
+    /// may have evicted it
+    Unexplained,
+}
+
+impl std::fmt::Display for CacheMissReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.reason {
+            MissReason::Reordered { from } => {
+                write!(f, "{} moved from block {} to block {}", self.label, from, self.block)
+            }
+            MissReason::ContentEdited => write!(f, "{} was edited", self.label),
+            MissReason::WhitespaceChanged => {
+                write!(f, "{} differs only in whitespace (strip_whitespace?)", self.label)
+            }
+            MissReason::Inserted => {
+                write!(f, "{} was inserted at block {}", self.label, self.block)
+            }
+            MissReason::Removed { label } => write!(f, "{} was removed", label),
+            MissReason::Expired { age, ttl } => write!(
+                f,
+                "prefix unchanged, but {}s since the last request exceeds the {}s TTL",
+                age.as_secs(),
+                ttl.as_secs()
+            ),
+            MissReason::Unexplained => {
+                write!(f, "prefix unchanged within its TTL; the provider may have evicted it")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ContextItem, ContextType};
+
+    fn item(name: &str, content: &str) -> ContextItem {
+        ContextItem {
+            name: name.to_string(),
+            content: content.to_string(),
+            item_type: ContextType::File,
+            relevance: None,
+            cache_control: None,
+            is_static: false,
+        }
+    }
+
+    fn prefix(context: Vec<ContextItem>) -> PrefixFingerprint {
+        let request = ApiRequest::new("task".to_string())
+            .with_system("You are helpful".to_string())
+            .with_context(context);
+        PrefixFingerprint::of(&request)
+    }
+
+    #[test]
+    fn test_first_divergence_is_explained() {
+        let base = prefix(vec![item("a.rs", "fn a() {}"), item("b.rs", "fn b() {}")]);
+
+        let swapped = prefix(vec![item("b.rs", "fn b() {}"), item("a.rs", "fn a() {}")]);
+        let report = base.diverge(&swapped).unwrap();
+        assert_eq!((report.block, report.reason), (1, MissReason::Reordered { from: 2 }));
+
+        let edited = prefix(vec![item("a.rs", "fn a() { 1 }"), item("b.rs", "fn b() {}")]);
+        let report = base.diverge(&edited).unwrap();
+        assert_eq!(report.label, "context a.rs");
+        assert_eq!(report.reason, MissReason::ContentEdited);
+
+        let stripped = prefix(vec![item("a.rs", "fn a()  {}\n"), item("b.rs", "fn b() {}")]);
+        assert_eq!(base.diverge(&stripped).unwrap().reason, MissReason::WhitespaceChanged);
+
+        let inserted = prefix(vec![item("new.rs", "x"), item("a.rs", "fn a() {}")]);
+        assert_eq!(base.diverge(&inserted).unwrap().reason, MissReason::Inserted);
+
+        let mut later = base.clone();
+        later.sent_at = base.sent_at + Duration::from_secs(600);
+        assert!(matches!(base.diverge(&later).unwrap().reason, MissReason::Expired { .. }));
+        assert!(base.diverge(&base).is_none());
+    }
+
+    #[test]
+    fn test_claude_history_only_request_reports_misses() {
+        use crate::api::{Message, TokenUsage};
+        use crate::cache::{cache_strategy_for, CacheTracker};
+
+        // As the shell sends it: history, but no system or context cache control
+        let turn = |question: &str| {
+            let mut request = ApiRequest::new("next".to_string());
+            request.messages = vec![
+                Message::user(question.repeat(600)),
+                Message::assistant("sure ".repeat(600)),
+            ];
+            PrefixFingerprint::of(&request)
+        };
+        let claude = cache_strategy_for("claude", "claude-sonnet-4");
+        let first = turn("explain ");
+        assert!(first.breakpoints);
+        assert!(first.cacheable_by(&*claude));
+
+        let tracker = CacheTracker::new(100);
+        let written = TokenUsage::with_cache(100, 10, Some(2400), Some(0));
+        assert!(tracker.check_prefix(first, &written, &*claude).is_none());
+        let report = tracker.check_prefix(turn("describe "), &written, &*claude).unwrap();
+        assert_eq!((report.block, report.reason), (0, MissReason::ContentEdited));
+    }
+}
@@ -9,31 +9,67 @@
 //! 2. **Minimum size**: Ensure cacheable sections meet minimum token thresholds (~1024 for Claude)
 //! 3. **Stability**: Content that changes invalidates the cache for everything after it
 //! 4. **Explicit markers**: Use cache_control blocks to mark cache breakpoints
+//!
+//! Providers without explicit markers cache prefixes automatically; see
+//! [`CacheStrategy`] for how the layout adapts to each of them.
 
+mod cost;
+mod diagnostics;
+mod provider;
 mod strategy;
 mod tracker;
 
-pub use strategy::{BreakpointPosition, CacheOptimizer, CacheOptimizedRequest, CacheableContent, ContentStability};
-pub use tracker::{CacheMetrics, CacheSummary, CacheTracker};
+pub use diagnostics::{BlockFingerprint, CacheMissReport, MissReason, PrefixFingerprint};
+pub use cost::{CacheCostModel, DEFAULT_EXPECTED_REUSES, DEFAULT_REQUEST_INTERVAL};
+pub use provider::{
+    cache_strategy_for, AnthropicCacheStrategy, CacheStrategy, GeminiCacheStrategy,
+    OpenAiCacheStrategy, PrefixCacheStrategy, PrefixSegment,
+};
+pub use strategy::{
+    BreakpointPosition, CacheBreakpoint, CacheOptimizer, CacheOptimizedRequest, CacheableContent,
+    ContentStability,
+};
+pub use tracker::{
+    CacheMetrics, CachePrediction, CacheStatus, CacheSummary, CacheTracker, PredictionAccuracy,
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
 
 /// Minimum tokens required for caching (Anthropic requirement)
 pub const MIN_CACHE_TOKENS: usize = 1024;
 
+/// Cache breakpoints Anthropic accepts per request
+pub const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+/// Price of writing a 5-minute cache entry, relative to base input
+pub const CACHE_WRITE_MULTIPLIER_5M: f64 = 1.25;
+
+/// Price of writing a 1-hour cache entry, relative to base input
+pub const CACHE_WRITE_MULTIPLIER_1H: f64 = 2.0;
+
+/// Price of reading from the cache, relative to base input
+pub const CACHE_READ_MULTIPLIER: f64 = 0.10;
+
 /// Cache control directive for API requests
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CacheControl {
     /// Type of cache control
     #[serde(rename = "type")]
     pub control_type: CacheControlType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CacheControlType {
-    /// Mark this as an ephemeral cache breakpoint
+    /// Mark this as an ephemeral cache breakpoint (5-minute TTL)
     Ephemeral,
+    /// Ephemeral breakpoint with a 1-hour TTL; writes cost 2x base input
+    /// instead of 1.25x, so it pays off for content reused after more than
+    /// five minutes
+    #[serde(rename = "ephemeral_1h")]
+    Ephemeral1h,
 }
 
 impl Default for CacheControl {
@@ -44,6 +80,48 @@ impl Default for CacheControl {
     }
 }
 
+impl CacheControl {
+    /// 1-hour cache breakpoint
+    pub fn one_hour() -> Self {
+        Self {
+            control_type: CacheControlType::Ephemeral1h,
+        }
+    }
+
+    /// TTL suited to content of the given stability: 1 hour for static
+    /// content, 5 minutes for everything else
+    pub fn for_stability(stability: ContentStability) -> Self {
+        match stability {
+            ContentStability::Static => Self::one_hour(),
+            _ => Self::default(),
+        }
+    }
+
+    /// How long an entry written with this directive lives without a hit
+    pub fn ttl(&self) -> Duration {
+        match self.control_type {
+            CacheControlType::Ephemeral => Duration::from_secs(5 * 60),
+            CacheControlType::Ephemeral1h => Duration::from_secs(60 * 60),
+        }
+    }
+
+    /// Write price relative to base input
+    pub fn write_multiplier(&self) -> f64 {
+        match self.control_type {
+            CacheControlType::Ephemeral => CACHE_WRITE_MULTIPLIER_5M,
+            CacheControlType::Ephemeral1h => CACHE_WRITE_MULTIPLIER_1H,
+        }
+    }
+
+    /// Anthropic `cache_control` block
+    pub fn to_anthropic(&self) -> Value {
+        match self.control_type {
+            CacheControlType::Ephemeral => json!({ "type": "ephemeral" }),
+            CacheControlType::Ephemeral1h => json!({ "type": "ephemeral", "ttl": "1h" }),
+        }
+    }
+}
+
 /// Configuration for cache optimization
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheConfig {
@@ -55,7 +133,8 @@ pub struct CacheConfig {
     pub auto_reorder: bool,
     /// Whether to pad small cacheable sections to meet minimum
     pub pad_to_minimum: bool,
-    /// Estimated tokens per character (for size calculations)
+    /// Estimated tokens per character, used for size calculations when the
+    /// optimizer has no model tokenizer (see `CacheOptimizer::with_tokenizer`)
     pub tokens_per_char: f32,
 }
 
@@ -63,7 +142,7 @@ impl Default for CacheConfig {
     fn default() -> Self {
         Self {
             min_cache_tokens: MIN_CACHE_TOKENS,
-            max_breakpoints: 4, // Anthropic supports up to 4 cache breakpoints
+            max_breakpoints: MAX_CACHE_BREAKPOINTS,
             auto_reorder: true,
             pad_to_minimum: false,
             tokens_per_char: 0.25, // ~4 chars per token
@@ -0,0 +1,343 @@
+//! Provider-specific prompt caching rules
+//!
+//! Every provider caches a stable prompt prefix, but they differ in how the
+//! prefix is delimited and how much of it is cached:
+//!
+//! - Anthropic caches up to explicit `cache_control` breakpoints (at most 4)
+//! - OpenAI caches prompts of 1024+ tokens automatically, in 128-token steps
+//! - Gemini caches a prefix stored as an explicit cached-content object,
+//!   which the Gemini client does not create, so nothing is cached there yet
+//! - DeepSeek, Venice and Ollama reuse a matching prefix from a disk or
+//!   in-memory cache without any markers
+//!
+//! [`CacheOptimizer`](super::CacheOptimizer) orders content by stability for
+//! all of them and asks the [`CacheStrategy`] where to place breakpoints.
+
+use super::strategy::{BreakpointPosition, CacheBreakpoint, ContentStability};
+use super::{CacheConfig, CacheControl, MIN_CACHE_TOKENS};
+
+/// One block of the prompt prefix, in request order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixSegment {
+    /// Breakpoint position that closes this segment
+    pub position: BreakpointPosition,
+    pub stability: ContentStability,
+    pub tokens: usize,
+}
+
+impl PrefixSegment {
+    fn is_cacheable(&self) -> bool {
+        matches!(self.stability, ContentStability::Static | ContentStability::SemiStatic)
+    }
+}
+
+/// How a provider caches prompt prefixes
+pub trait CacheStrategy: Send + Sync {
+    /// Provider family, for display
+    fn name(&self) -> &str;
+
+    /// Smallest prefix, in tokens, the provider will cache
+    fn min_cache_tokens(&self) -> usize;
+
+    /// Fraction of the input price saved on a cached token
+    fn read_discount(&self) -> f32;
+
+    /// Tokens of a stable prefix that will actually be cached
+    fn cacheable_tokens(&self, prefix_tokens: usize) -> usize {
+        if prefix_tokens >= self.min_cache_tokens() {
+            prefix_tokens
+        } else {
+            0
+        }
+    }
+
+    /// Breakpoints to mark on a request laid out as `segments`; providers
+    /// that cache automatically need none
+    fn breakpoints(&self, _segments: &[PrefixSegment]) -> Vec<CacheBreakpoint> {
+        Vec::new()
+    }
+
+    /// Whether only a prefix closed by a breakpoint is cached
+    fn needs_breakpoints(&self) -> bool {
+        false
+    }
+}
+
+/// Explicit `cache_control` breakpoints with a per-breakpoint TTL
+#[derive(Debug, Clone)]
+pub struct AnthropicCacheStrategy {
+    min_cache_tokens: usize,
+    max_breakpoints: usize,
+}
+
+impl AnthropicCacheStrategy {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            min_cache_tokens: config.min_cache_tokens,
+            max_breakpoints: config.max_breakpoints,
+        }
+    }
+}
+
+impl Default for AnthropicCacheStrategy {
+    fn default() -> Self {
+        Self::new(&CacheConfig::default())
+    }
+}
+
+impl CacheStrategy for AnthropicCacheStrategy {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn min_cache_tokens(&self) -> usize {
+        self.min_cache_tokens
+    }
+
+    fn read_discount(&self) -> f32 {
+        0.9
+    }
+
+    fn needs_breakpoints(&self) -> bool {
+        true
+    }
+
+    /// Each breakpoint's TTL follows the least stable content in the prefix
+    /// it closes: 1 hour while everything before it is static, 5 minutes
+    /// once semi-static content is included. Anthropic requires longer TTLs
+    /// to come first, which this ordering guarantees.
+    fn breakpoints(&self, segments: &[PrefixSegment]) -> Vec<CacheBreakpoint> {
+        let mut breakpoints = Vec::new();
+
+        // Only add breakpoints if we have enough static content
+        let static_tokens: usize =
+            segments.iter().filter(|s| s.is_cacheable()).map(|s| s.tokens).sum();
+        if static_tokens < self.min_cache_tokens {
+            return breakpoints;
+        }
+
+        // Add breakpoint after system prompt if it's large enough; it counts
+        // toward the prefix cached at later breakpoints
+        let mut context = segments;
+        let mut cumulative_tokens = 0;
+        if let Some((system, rest)) = segments
+            .split_first()
+            .filter(|(s, _)| s.position == BreakpointPosition::AfterSystem)
+        {
+            if system.tokens >= self.min_cache_tokens {
+                breakpoints.push(CacheBreakpoint::new(
+                    BreakpointPosition::AfterSystem,
+                    CacheControl::for_stability(ContentStability::Static),
+                ));
+            }
+            cumulative_tokens = system.tokens;
+            context = rest;
+        }
+
+        // Add breakpoints after the static and semi-static context runs
+        let mut last_static = None;
+        let mut last_semi_static = None;
+        let mut seen_semi_static = false;
+
+        for segment in context.iter().take_while(|s| s.is_cacheable()) {
+            cumulative_tokens += segment.tokens;
+            seen_semi_static |= segment.stability == ContentStability::SemiStatic;
+            if cumulative_tokens >= self.min_cache_tokens {
+                if seen_semi_static {
+                    last_semi_static = Some(&segment.position);
+                } else {
+                    last_static = Some(&segment.position);
+                }
+            }
+        }
+
+        // The static run gets its own 1h breakpoint so it stays cached when
+        // the semi-static content after it changes
+        let runs = [
+            (last_static, ContentStability::Static),
+            (last_semi_static, ContentStability::SemiStatic),
+        ];
+        for (position, stability) in runs {
+            let Some(position) = position else { continue };
+            if breakpoints.len() >= self.max_breakpoints {
+                break;
+            }
+            breakpoints.push(CacheBreakpoint::new(
+                position.clone(),
+                CacheControl::for_stability(stability),
+            ));
+        }
+
+        breakpoints
+    }
+}
+
+/// Automatic prefix caching of prompts over 1024 tokens
+#[derive(Debug, Clone, Default)]
+pub struct OpenAiCacheStrategy;
+
+/// OpenAI extends a cached prefix in steps of this many tokens
+const OPENAI_CACHE_INCREMENT: usize = 128;
+
+impl CacheStrategy for OpenAiCacheStrategy {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn min_cache_tokens(&self) -> usize {
+        MIN_CACHE_TOKENS
+    }
+
+    /// The discount varies by model (50-90%); the lowest is assumed
+    fn read_discount(&self) -> f32 {
+        0.5
+    }
+
+    fn cacheable_tokens(&self, prefix_tokens: usize) -> usize {
+        if prefix_tokens < MIN_CACHE_TOKENS {
+            return 0;
+        }
+        let extra = prefix_tokens - MIN_CACHE_TOKENS;
+        MIN_CACHE_TOKENS + extra / OPENAI_CACHE_INCREMENT * OPENAI_CACHE_INCREMENT
+    }
+}
+
+/// Explicit cached-content objects holding the stable prefix.
+///
+/// Not supported: the Gemini client never creates a cachedContent resource,
+/// so this reports no breakpoints and no cacheable tokens rather than
+/// savings that would never happen.
+#[derive(Debug, Clone)]
+pub struct GeminiCacheStrategy {
+    min_cache_tokens: usize,
+}
+
+impl GeminiCacheStrategy {
+    /// Pro models need a larger cached-content object than Flash models
+    pub fn for_model(model: &str) -> Self {
+        let min_cache_tokens = if model.contains("pro") { 4096 } else { 1024 };
+        Self { min_cache_tokens }
+    }
+}
+
+impl CacheStrategy for GeminiCacheStrategy {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn min_cache_tokens(&self) -> usize {
+        self.min_cache_tokens
+    }
+
+    fn read_discount(&self) -> f32 {
+        0.75
+    }
+
+    fn cacheable_tokens(&self, _prefix_tokens: usize) -> usize {
+        0
+    }
+}
+
+/// Automatic reuse of a matching prefix, stored in fixed-size units
+#[derive(Debug, Clone)]
+pub struct PrefixCacheStrategy {
+    name: String,
+    unit_tokens: usize,
+    read_discount: f32,
+}
+
+impl PrefixCacheStrategy {
+    /// DeepSeek and Venice store prefixes in 64-token units
+    pub fn disk(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            unit_tokens: 64,
+            read_discount: 0.9,
+        }
+    }
+
+    /// Set the fraction of the input price saved on a cached token
+    pub fn with_read_discount(mut self, read_discount: f32) -> Self {
+        self.read_discount = read_discount;
+        self
+    }
+}
+
+impl CacheStrategy for PrefixCacheStrategy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn min_cache_tokens(&self) -> usize {
+        self.unit_tokens
+    }
+
+    fn read_discount(&self) -> f32 {
+        self.read_discount
+    }
+
+    fn cacheable_tokens(&self, prefix_tokens: usize) -> usize {
+        prefix_tokens / self.unit_tokens * self.unit_tokens
+    }
+}
+
+/// Strategy for the provider registered as `provider` (see
+/// [`ProviderRegistry`](crate::api::ProviderRegistry)) serving `model`
+pub fn cache_strategy_for(provider: &str, model: &str) -> Box<dyn CacheStrategy> {
+    match provider.to_lowercase().as_str() {
+        "claude" | "anthropic" => Box::new(AnthropicCacheStrategy::default()),
+        "openai" => Box::new(OpenAiCacheStrategy),
+        "gemini" => Box::new(GeminiCacheStrategy::for_model(model)),
+        // OpenAI-compatible, cached reads billed at half price
+        "venice" => Box::new(PrefixCacheStrategy::disk("venice").with_read_discount(0.5)),
+        other => Box::new(PrefixCacheStrategy::disk(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(position: BreakpointPosition, stability: ContentStability) -> PrefixSegment {
+        PrefixSegment {
+            position,
+            stability,
+            tokens: 1500,
+        }
+    }
+
+    #[test]
+    fn test_layout_per_provider() {
+        let segments = vec![
+            segment(BreakpointPosition::AfterSystem, ContentStability::Static),
+            segment(BreakpointPosition::AfterContext(0), ContentStability::SemiStatic),
+            segment(BreakpointPosition::AfterContext(1), ContentStability::Dynamic),
+        ];
+
+        let anthropic = cache_strategy_for("claude", "claude-sonnet-4");
+        let positions: Vec<_> =
+            anthropic.breakpoints(&segments).into_iter().map(|b| b.position).collect();
+        assert_eq!(
+            positions,
+            vec![BreakpointPosition::AfterSystem, BreakpointPosition::AfterContext(0)]
+        );
+
+        // OpenAI caches automatically, in 128-token steps past 1024
+        let openai = cache_strategy_for("openai", "gpt-4o");
+        assert!(openai.breakpoints(&segments).is_empty());
+        assert_eq!(openai.cacheable_tokens(3000), 2944);
+        assert_eq!(openai.cacheable_tokens(1000), 0);
+
+        // No cached-content object is created for Gemini, so nothing is cached
+        let gemini = cache_strategy_for("gemini", "gemini-2.5-flash");
+        assert!(gemini.breakpoints(&segments).is_empty());
+        assert_eq!(gemini.cacheable_tokens(3000), 0);
+        assert_eq!(cache_strategy_for("gemini", "gemini-2.5-pro").min_cache_tokens(), 4096);
+
+        let venice = cache_strategy_for("venice", "deepseek-r1");
+        assert_eq!(venice.name(), "venice");
+        assert!(venice.breakpoints(&segments).is_empty());
+        assert_eq!(venice.cacheable_tokens(3000), 2944);
+        assert_eq!(venice.cacheable_tokens(100), 64);
+    }
+}
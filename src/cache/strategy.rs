@@ -1,9 +1,14 @@
 //! Cache optimization strategies for prompt structuring
 
-use super::{CacheAnalysis, CacheConfig};
+use super::cost::CacheCostModel;
+use super::provider::{AnthropicCacheStrategy, CacheStrategy, PrefixSegment};
+use super::tracker::CachePrediction;
+use super::{CacheAnalysis, CacheConfig, CacheControl, CacheControlType};
 use crate::api::{ApiRequest, ContextItem, ContextType};
+use crate::tokenizer::Tokenizer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Stability classification for content
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -28,6 +33,17 @@ impl ContentStability {
             ContentStability::Volatile => 3,
         }
     }
+
+    /// Assumed chance the content changes between two requests, before any
+    /// history of it has been seen
+    pub fn change_prior(&self) -> f64 {
+        match self {
+            ContentStability::Static => 0.0,
+            ContentStability::SemiStatic => 0.1,
+            ContentStability::Dynamic => 0.5,
+            ContentStability::Volatile => 1.0,
+        }
+    }
 }
 
 /// Content wrapper with caching metadata
@@ -80,41 +96,88 @@ pub struct CacheOptimizer {
     config: CacheConfig,
     /// Cache of content hashes to track what's been sent before
     content_cache: HashMap<String, ContentFingerprint>,
+    /// Tokenizer of the target model; `tokens_per_char` is used without one
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    /// Caching rules of the provider that will receive the request
+    strategy: Box<dyn CacheStrategy>,
+    /// Drops breakpoints whose writes are not expected to pay off
+    cost_model: Option<CacheCostModel>,
 }
 
 #[derive(Debug, Clone)]
 struct ContentFingerprint {
     hash: u64,
     token_count: usize,
-    #[allow(dead_code)]
     last_used: std::time::Instant,
+    /// Times this key was sent
+    sends: u32,
+    /// Times it was sent with different content than the time before
+    changes: u32,
 }
 
 impl CacheOptimizer {
+    /// Optimizer for Anthropic-style explicit breakpoints, limited by
+    /// `config.min_cache_tokens` and `config.max_breakpoints`
     pub fn new(config: CacheConfig) -> Self {
         Self {
+            strategy: Box::new(AnthropicCacheStrategy::new(&config)),
             config,
             content_cache: HashMap::new(),
+            tokenizer: None,
+            cost_model: None,
+        }
+    }
+
+    /// Lay out requests for another provider's caching rules (see
+    /// [`cache_strategy_for`](super::cache_strategy_for))
+    pub fn with_strategy(mut self, strategy: Box<dyn CacheStrategy>) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn strategy(&self) -> &dyn CacheStrategy {
+        self.strategy.as_ref()
+    }
+
+    /// Keep only breakpoints expected to save more than their write premium,
+    /// and report that saving on each
+    pub fn with_cost_model(mut self, cost_model: CacheCostModel) -> Self {
+        self.cost_model = Some(cost_model);
+        self
+    }
+
+    /// Size content with the target model's tokenizer, so minimum cache
+    /// sizes are checked against what the provider will count
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count(text),
+            None => (text.len() as f32 * self.config.tokens_per_char) as usize,
         }
     }
 
     /// Analyze content for caching potential
     pub fn analyze(&self, content: &str) -> CacheAnalysis {
-        let estimated_tokens = (content.len() as f32 * self.config.tokens_per_char) as usize;
-        let meets_minimum = estimated_tokens >= self.config.min_cache_tokens;
+        let estimated_tokens = self.count_tokens(content);
+        let min_cache_tokens = self.strategy.min_cache_tokens();
+        let meets_minimum = estimated_tokens >= min_cache_tokens;
 
         let mut suggestions = Vec::new();
 
         if !meets_minimum {
-            let needed = self.config.min_cache_tokens - estimated_tokens;
+            let needed = min_cache_tokens - estimated_tokens;
             suggestions.push(format!(
                 "Content is ~{} tokens short of minimum cache size ({}). Consider combining with other static content.",
-                needed, self.config.min_cache_tokens
+                needed, min_cache_tokens
             ));
         }
 
-        // Calculate potential savings (cached tokens are ~90% cheaper)
-        let potential_savings = if meets_minimum { 0.9 } else { 0.0 };
+        // Calculate potential savings (cached tokens are cheaper)
+        let potential_savings = if meets_minimum { self.strategy.read_discount() } else { 0.0 };
 
         // Find good breakpoint positions (after major sections)
         let breakpoint_positions = self.find_breakpoint_positions(content);
@@ -128,7 +191,10 @@ impl CacheOptimizer {
         }
     }
 
-    /// Optimize an API request for cache efficiency
+    /// Optimize an API request for cache efficiency.
+    ///
+    /// The system prompt and context items are recorded as sent, building
+    /// the change history the cost model uses.
     pub fn optimize_request(&mut self, mut request: ApiRequest) -> CacheOptimizedRequest {
         let mut sections = Vec::new();
         let mut total_static_tokens = 0;
@@ -136,7 +202,7 @@ impl CacheOptimizer {
 
         // Classify system prompt as static
         if let Some(system) = &request.system {
-            let tokens = (system.len() as f32 * self.config.tokens_per_char) as usize;
+            let tokens = self.count_tokens(system);
             total_static_tokens += tokens;
             sections.push(CacheableContent::new(system.clone(), ContentStability::Static));
         }
@@ -148,7 +214,7 @@ impl CacheOptimizer {
 
         for item in request.context.drain(..) {
             let stability = self.classify_context(&item);
-            let tokens = (item.content.len() as f32 * self.config.tokens_per_char) as usize;
+            let tokens = self.count_tokens(&item.content);
 
             match stability {
                 ContentStability::Static => {
@@ -176,11 +242,41 @@ impl CacheOptimizer {
             request.context = reordered.into_iter().map(|(item, _)| item).collect();
         }
 
-        // Determine cache breakpoint positions
-        let breakpoints = self.calculate_breakpoints(&request, total_static_tokens);
+        // Let the provider's strategy place breakpoints, and mark them on
+        // the request
+        let segments = self.prefix_segments(&request);
+        let mut breakpoints = self.strategy.breakpoints(&segments);
+        let keyed = keyed_segments(&request);
+        let prediction = self.predict_hit(&keyed, &segments);
+        for (key, text) in &keyed {
+            self.register_sent(key, text);
+        }
+        if let Some(cost_model) = &self.cost_model {
+            let change_probabilities: Vec<f64> = keyed
+                .iter()
+                .zip(&segments)
+                .map(|((key, _), segment)| self.change_probability(key, segment.stability))
+                .collect();
+            breakpoints =
+                self.price_breakpoints(cost_model, &segments, &change_probabilities, breakpoints);
+        }
+        for breakpoint in &breakpoints {
+            match breakpoint.position {
+                BreakpointPosition::AfterSystem => {
+                    request.system_cache_control = Some(breakpoint.control.clone());
+                }
+                BreakpointPosition::AfterContext(idx) => {
+                    if let Some(item) = request.context.get_mut(idx) {
+                        item.cache_control = Some(breakpoint.control.clone());
+                    }
+                }
+                BreakpointPosition::AfterAllContext => {}
+            }
+        }
+        downgrade_late_long_ttls(&mut request);
 
         // Task is always volatile
-        let task_tokens = (request.task.len() as f32 * self.config.tokens_per_char) as usize;
+        let task_tokens = self.count_tokens(&request.task);
         total_dynamic_tokens += task_tokens;
 
         CacheOptimizedRequest {
@@ -188,11 +284,100 @@ impl CacheOptimizer {
             breakpoints,
             static_tokens: total_static_tokens,
             dynamic_tokens: total_dynamic_tokens,
-            estimated_cache_savings: if total_static_tokens >= self.config.min_cache_tokens {
-                (total_static_tokens as f32 * 0.9) as usize
-            } else {
-                0
-            },
+            estimated_cache_savings: (self.strategy.cacheable_tokens(total_static_tokens) as f32
+                * self.strategy.read_discount()) as usize,
+            prediction,
+        }
+    }
+
+    /// Expected cache read for a request: the leading segments sent before
+    /// with the same content, as far as the provider caches them
+    fn predict_hit(&self, keyed: &[(String, &str)], segments: &[PrefixSegment]) -> CachePrediction {
+        let mut tokens = 0;
+        let mut idle = None;
+        for ((key, text), segment) in keyed.iter().zip(segments) {
+            let Some(fingerprint) = self.content_cache.get(key) else { break };
+            if fingerprint.hash != self.hash_content(text) {
+                break;
+            }
+            tokens += segment.tokens;
+            idle = idle.max(Some(fingerprint.last_used.elapsed()));
+        }
+
+        let cached_tokens = self.strategy.cacheable_tokens(tokens);
+        CachePrediction {
+            hit: cached_tokens > 0,
+            cached_tokens: Some(cached_tokens),
+            idle,
+            ttl: None,
+        }
+    }
+
+    /// System prompt and context items in request order, as seen by the
+    /// cache strategy
+    fn prefix_segments(&self, request: &ApiRequest) -> Vec<PrefixSegment> {
+        let system = request.system.as_ref().map(|system| PrefixSegment {
+            position: BreakpointPosition::AfterSystem,
+            stability: ContentStability::Static,
+            tokens: self.count_tokens(system),
+        });
+        let context = request.context.iter().enumerate().map(|(idx, item)| PrefixSegment {
+            position: BreakpointPosition::AfterContext(idx),
+            stability: self.classify_context(item),
+            tokens: self.count_tokens(&item.content),
+        });
+        system.into_iter().chain(context).collect()
+    }
+
+    /// Keep breakpoints with a positive expected net saving. Each one writes
+    /// the tokens since the previous kept breakpoint, and is invalidated by
+    /// a change anywhere before it.
+    fn price_breakpoints(
+        &self,
+        cost_model: &CacheCostModel,
+        segments: &[PrefixSegment],
+        change_probabilities: &[f64],
+        breakpoints: Vec<CacheBreakpoint>,
+    ) -> Vec<CacheBreakpoint> {
+        let read_multiplier = 1.0 - self.strategy.read_discount() as f64;
+        let mut priced = Vec::new();
+        let mut start = 0;
+
+        for mut breakpoint in breakpoints {
+            let Some(end) = segments.iter().position(|s| s.position == breakpoint.position) else {
+                continue;
+            };
+            if end < start {
+                continue;
+            }
+            let tokens = segments[start..=end].iter().map(|s| s.tokens).sum();
+            let unchanged: f64 = change_probabilities[..=end].iter().map(|p| 1.0 - p).product();
+            let net = cost_model.net_savings(
+                tokens,
+                &breakpoint.control,
+                1.0 - unchanged,
+                read_multiplier,
+            );
+            if net > 0.0 {
+                breakpoint.expected_net_usd = Some(net);
+                priced.push(breakpoint);
+                start = end + 1;
+            }
+        }
+
+        priced
+    }
+
+    /// Chance that the content under `key` changes before the next request:
+    /// its observed change rate, with the stability prior counted as one
+    /// extra observation
+    fn change_probability(&self, key: &str, stability: ContentStability) -> f64 {
+        let prior = stability.change_prior();
+        match self.content_cache.get(key) {
+            Some(fingerprint) if fingerprint.sends > 1 => {
+                (fingerprint.changes as f64 + prior) / fingerprint.sends as f64
+            }
+            _ => prior,
         }
     }
 
@@ -205,20 +390,18 @@ impl CacheOptimizer {
             // Files depend on their content patterns
             ContextType::File => {
                 // Type definition files are semi-static
-                if item.name.ends_with(".d.ts")
+                let is_type_definition = item.name.ends_with(".d.ts")
                     || item.name.ends_with("types.rs")
                     || item.name.ends_with("types.py")
                     || item.name.ends_with("schema.prisma")
-                    || item.name.contains("interface")
-                {
-                    ContentStability::SemiStatic
-                }
+                    || item.name.contains("interface");
                 // Config files are semi-static
-                else if item.name.ends_with(".json")
+                let is_config = item.name.ends_with(".json")
                     || item.name.ends_with(".toml")
                     || item.name.ends_with(".yaml")
-                    || item.name.ends_with(".yml")
-                {
+                    || item.name.ends_with(".yml");
+
+                if is_type_definition || is_config {
                     ContentStability::SemiStatic
                 }
                 // Regular code files are dynamic
@@ -235,51 +418,6 @@ impl CacheOptimizer {
         }
     }
 
-    /// Calculate optimal cache breakpoint positions
-    fn calculate_breakpoints(&self, request: &ApiRequest, static_tokens: usize) -> Vec<BreakpointPosition> {
-        let mut breakpoints = Vec::new();
-
-        // Only add breakpoints if we have enough static content
-        if static_tokens < self.config.min_cache_tokens {
-            return breakpoints;
-        }
-
-        // Add breakpoint after system prompt if it's large enough
-        if let Some(system) = &request.system {
-            let system_tokens = (system.len() as f32 * self.config.tokens_per_char) as usize;
-            if system_tokens >= self.config.min_cache_tokens {
-                breakpoints.push(BreakpointPosition::AfterSystem);
-            }
-        }
-
-        // Add breakpoint after static context if we haven't hit the limit
-        if breakpoints.len() < self.config.max_breakpoints {
-            let mut cumulative_tokens = 0;
-            let mut last_static_idx = None;
-
-            for (idx, item) in request.context.iter().enumerate() {
-                let stability = self.classify_context(item);
-                let tokens = (item.content.len() as f32 * self.config.tokens_per_char) as usize;
-                cumulative_tokens += tokens;
-
-                if stability == ContentStability::Static || stability == ContentStability::SemiStatic {
-                    if cumulative_tokens >= self.config.min_cache_tokens {
-                        last_static_idx = Some(idx);
-                    }
-                } else {
-                    // We've hit dynamic content
-                    break;
-                }
-            }
-
-            if let Some(idx) = last_static_idx {
-                breakpoints.push(BreakpointPosition::AfterContext(idx));
-            }
-        }
-
-        breakpoints
-    }
-
     /// Find natural breakpoint positions in text
     fn find_breakpoint_positions(&self, content: &str) -> Vec<usize> {
         let mut positions = Vec::new();
@@ -304,7 +442,14 @@ impl CacheOptimizer {
     /// Register content as sent (for cache tracking)
     pub fn register_sent(&mut self, cache_key: &str, content: &str) {
         let hash = self.hash_content(content);
-        let token_count = (content.len() as f32 * self.config.tokens_per_char) as usize;
+        let token_count = self.count_tokens(content);
+        let (sends, changes) = match self.content_cache.get(cache_key) {
+            Some(previous) => {
+                let changed = u32::from(previous.hash != hash);
+                (previous.sends + 1, previous.changes + changed)
+            }
+            None => (1, 0),
+        };
 
         self.content_cache.insert(
             cache_key.to_string(),
@@ -312,6 +457,8 @@ impl CacheOptimizer {
                 hash,
                 token_count,
                 last_used: std::time::Instant::now(),
+                sends,
+                changes,
             },
         );
     }
@@ -342,6 +489,34 @@ impl CacheOptimizer {
     }
 }
 
+/// Cache-tracking keys and text of the system prompt and context items, in
+/// the order of [`CacheOptimizer::prefix_segments`]
+fn keyed_segments(request: &ApiRequest) -> Vec<(String, &str)> {
+    let system = request.system.as_deref().map(|system| ("system".to_string(), system));
+    let context = request
+        .context
+        .iter()
+        .map(|item| (format!("context:{}", item.name), item.content.as_str()));
+    system.into_iter().chain(context).collect()
+}
+
+/// Anthropic rejects a 1h breakpoint after a 5m one, so once a 5m breakpoint
+/// appears (system prompt first, then context) later ones are shortened
+fn downgrade_late_long_ttls(request: &mut ApiRequest) {
+    let controls = request
+        .system_cache_control
+        .iter_mut()
+        .chain(request.context.iter_mut().filter_map(|c| c.cache_control.as_mut()));
+    let mut seen_short = false;
+    for control in controls {
+        if control.control_type == CacheControlType::Ephemeral {
+            seen_short = true;
+        } else if seen_short {
+            *control = CacheControl::default();
+        }
+    }
+}
+
 /// Position for a cache breakpoint
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BreakpointPosition {
@@ -353,19 +528,43 @@ pub enum BreakpointPosition {
     AfterAllContext,
 }
 
+/// A cache breakpoint and the TTL of the prefix it closes
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheBreakpoint {
+    pub position: BreakpointPosition,
+    pub control: CacheControl,
+    /// Expected USD saved over the session, net of cache writes; set when
+    /// the optimizer has a cost model
+    pub expected_net_usd: Option<f64>,
+}
+
+impl CacheBreakpoint {
+    pub fn new(position: BreakpointPosition, control: CacheControl) -> Self {
+        Self {
+            position,
+            control,
+            expected_net_usd: None,
+        }
+    }
+}
+
 /// Result of optimizing a request for caching
 #[derive(Debug)]
 pub struct CacheOptimizedRequest {
-    /// The optimized request
+    /// The optimized request, with `cache_control` set at each breakpoint
     pub request: ApiRequest,
-    /// Positions where cache breakpoints should be inserted
-    pub breakpoints: Vec<BreakpointPosition>,
+    /// Positions where cache breakpoints were inserted
+    pub breakpoints: Vec<CacheBreakpoint>,
     /// Estimated cacheable (static) tokens
     pub static_tokens: usize,
     /// Estimated non-cacheable (dynamic) tokens
     pub dynamic_tokens: usize,
     /// Estimated token cost savings from caching
     pub estimated_cache_savings: usize,
+    /// Cache read expected from what this optimizer sent before, to be
+    /// reconciled with provider usage by
+    /// [`CacheTracker::reconcile`](super::CacheTracker::reconcile)
+    pub prediction: CachePrediction,
 }
 
 /// Result of checking cache status
@@ -404,4 +603,75 @@ mod tests {
         let analysis = optimizer.analyze(&large);
         assert!(analysis.meets_minimum);
     }
+
+    #[test]
+    fn test_breakpoint_ttl_follows_stability() {
+        let item = |name: &str, item_type: ContextType| ContextItem {
+            name: name.to_string(),
+            content: "x".repeat(8000),
+            item_type,
+            relevance: None,
+            cache_control: None,
+            is_static: false,
+        };
+        let request = ApiRequest::new("fix it".to_string())
+            .with_system("s".repeat(8000))
+            .with_context(vec![
+                item("Cargo.toml", ContextType::File),
+                item("guide.md", ContextType::Documentation),
+                item("main.rs", ContextType::File),
+            ]);
+
+        let mut optimizer = CacheOptimizer::new(CacheConfig::default());
+        let optimized = optimizer.optimize_request(request);
+        let one_hour = CacheControl::one_hour();
+        let five_min = CacheControl::default();
+        assert_eq!(
+            optimized.breakpoints,
+            vec![
+                CacheBreakpoint::new(BreakpointPosition::AfterSystem, one_hour.clone()),
+                CacheBreakpoint::new(BreakpointPosition::AfterContext(0), one_hour.clone()),
+                CacheBreakpoint::new(BreakpointPosition::AfterContext(1), five_min.clone()),
+            ]
+        );
+
+        // Docs were moved ahead of the config file and marked on the request
+        let request = &optimized.request;
+        assert_eq!(request.context[0].name, "guide.md");
+        assert_eq!(request.system_cache_control, Some(one_hour));
+        assert_eq!(request.context[1].cache_control, Some(five_min));
+        assert!(request.context[2].cache_control.is_none());
+    }
+
+    #[test]
+    fn test_cost_model_drops_unprofitable_breakpoints() {
+        let request = |config: String| {
+            ApiRequest::new("fix it".to_string())
+                .with_system("s".repeat(8000))
+                .with_context(vec![ContextItem {
+                    name: "Cargo.toml".to_string(),
+                    content: config,
+                    item_type: ContextType::File,
+                    relevance: None,
+                    cache_control: None,
+                    is_static: false,
+                }])
+        };
+        let cost_model = CacheCostModel::new(crate::api::ModelPricing::new(3.0, 15.0));
+        let mut optimizer = CacheOptimizer::new(CacheConfig::default()).with_cost_model(cost_model);
+
+        let first = optimizer.optimize_request(request("a".repeat(8000)));
+        assert_eq!(first.breakpoints.len(), 2);
+        assert!(first.breakpoints.iter().all(|b| b.expected_net_usd.unwrap() > 0.0));
+
+        // A config file that changes on every request no longer earns its
+        // own breakpoint
+        for c in ["b", "c", "d", "e"] {
+            optimizer.optimize_request(request(c.repeat(8000)));
+        }
+        let churned = optimizer.optimize_request(request("f".repeat(8000)));
+        let positions: Vec<_> = churned.breakpoints.into_iter().map(|b| b.position).collect();
+        assert_eq!(positions, vec![BreakpointPosition::AfterSystem]);
+        assert!(churned.request.context[0].cache_control.is_none());
+    }
 }
@@ -1,7 +1,10 @@
 //! Cache tracking and metrics for monitoring cache efficiency
 
+use super::diagnostics::{CacheMissReport, MissReason, PrefixFingerprint};
+use super::{CacheControl, CacheStrategy};
+use crate::api::TokenUsage;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
@@ -77,6 +80,66 @@ impl std::fmt::Display for CacheMetrics {
     }
 }
 
+/// What the local model expected a request to read from the provider cache
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CachePrediction {
+    pub hit: bool,
+    /// Tokens expected to be read, when the prediction sized the hit
+    pub cached_tokens: Option<usize>,
+    /// Time since the prefix was last sent, if it was
+    pub idle: Option<Duration>,
+    /// TTL the entry was written with; the 5-minute default if unset
+    pub ttl: Option<Duration>,
+}
+
+impl From<&CacheStatus> for CachePrediction {
+    fn from(status: &CacheStatus) -> Self {
+        match status {
+            CacheStatus::Hit { tokens, idle, .. } => Self {
+                hit: true,
+                cached_tokens: Some(*tokens),
+                idle: Some(*idle),
+                ttl: None,
+            },
+            CacheStatus::Expired { idle } => Self {
+                idle: Some(*idle),
+                ..Self::default()
+            },
+            CacheStatus::Stale | CacheStatus::Miss => Self::default(),
+        }
+    }
+}
+
+/// Predictions compared with the cache activity providers reported
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PredictionAccuracy {
+    pub predictions: u64,
+    pub correct: u64,
+    /// Predicted hits the provider reported as misses
+    pub false_hits: u64,
+    /// Predicted misses the provider served from cache
+    pub false_misses: u64,
+    /// Predicted and actual cached tokens, over predictions that sized the hit
+    pub predicted_tokens: u64,
+    pub actual_tokens: u64,
+}
+
+impl PredictionAccuracy {
+    /// Share of predictions that matched, once any were made
+    pub fn accuracy(&self) -> Option<f64> {
+        (self.predictions > 0).then(|| self.correct as f64 / self.predictions as f64)
+    }
+
+    /// Relative error of predicted cached tokens; positive when providers
+    /// cached more than predicted
+    pub fn token_drift(&self) -> Option<f64> {
+        (self.predicted_tokens > 0).then(|| {
+            let predicted = self.predicted_tokens as f64;
+            (self.actual_tokens as f64 - predicted) / predicted
+        })
+    }
+}
+
 /// Entry in the cache tracker
 #[derive(Debug, Clone)]
 struct CacheEntry {
@@ -115,6 +178,12 @@ pub struct CacheTracker {
     max_entries: usize,
     /// Session start time
     session_start: Instant,
+    /// Prefix of the last request passed to `check_prefix`
+    last_prefix: Mutex<Option<PrefixFingerprint>>,
+    /// How long the provider keeps an idle entry, as observed so far, by
+    /// the TTL the entry was written with
+    ttls: Mutex<BTreeMap<Duration, Duration>>,
+    accuracy: Mutex<PredictionAccuracy>,
 }
 
 impl CacheTracker {
@@ -124,6 +193,9 @@ impl CacheTracker {
             metrics: Arc::new(Mutex::new(CacheMetrics::default())),
             max_entries,
             session_start: Instant::now(),
+            last_prefix: Mutex::new(None),
+            ttls: Mutex::new(BTreeMap::new()),
+            accuracy: Mutex::new(PredictionAccuracy::default()),
         }
     }
 
@@ -159,12 +231,23 @@ impl CacheTracker {
         }
     }
 
-    /// Check if content is cached and matches
+    /// Check if content is cached and matches. Entries idle for longer
+    /// than the observed TTL (see [`reconcile`](Self::reconcile)) have
+    /// expired.
     pub fn check(&self, key: &str, content_hash: u64) -> CacheStatus {
+        let ttl = self.ttl();
         let mut entries = self.entries.lock().unwrap();
 
         if let Some(entry) = entries.get_mut(key) {
-            if entry.content_hash == content_hash {
+            let idle = entry.last_accessed.elapsed();
+            if entry.content_hash == content_hash && idle > ttl {
+                // Sending it again writes a fresh entry
+                entry.last_accessed = Instant::now();
+                if let Ok(mut metrics) = self.metrics.lock() {
+                    metrics.record_miss(entry.token_count);
+                }
+                CacheStatus::Expired { idle }
+            } else if entry.content_hash == content_hash {
                 // Update access time and hit count
                 entry.last_accessed = Instant::now();
                 entry.hit_count += 1;
@@ -179,6 +262,7 @@ impl CacheTracker {
                 CacheStatus::Hit {
                     tokens: token_count,
                     age: entry.created_at.elapsed(),
+                    idle,
                 }
             } else {
                 // Content changed
@@ -196,6 +280,118 @@ impl CacheTracker {
         }
     }
 
+    /// Record the cache activity a provider reported for one request.
+    ///
+    /// A request that read from the cache is a hit and one that reported
+    /// zero cached tokens a miss; usage without cache fields (a provider that
+    /// does not report caching) is ignored.
+    pub fn record_usage(&self, usage: &TokenUsage) {
+        let Some(read) = usage.cache_read_tokens else {
+            return;
+        };
+        let mut metrics = self.metrics.lock().unwrap();
+        if read > 0 {
+            metrics.record_hit(read as usize);
+        } else {
+            metrics.record_miss(usage.prompt_tokens as usize);
+        }
+        if let Some(written) = usage.cache_creation_tokens.filter(|&t| t > 0) {
+            metrics.record_write(written as usize);
+        }
+    }
+
+    /// Remember the prefix of a sent request, reconcile the hit predicted
+    /// from the previous prefix with `usage`, and, if the provider reported
+    /// a cache miss, explain where the prefix diverged. Hits are predicted
+    /// and misses reported only for a prefix `strategy` can cache.
+    pub fn check_prefix(
+        &self,
+        prefix: PrefixFingerprint,
+        usage: &TokenUsage,
+        strategy: &dyn CacheStrategy,
+    ) -> Option<CacheMissReport> {
+        let cacheable = prefix.cacheable_by(strategy);
+        let missed = cacheable
+            && usage.cache_read_tokens.unwrap_or(0) == 0
+            && (usage.cache_read_tokens.is_some() || usage.cache_creation_tokens.unwrap_or(0) > 0);
+        let mut last = self.last_prefix.lock().unwrap();
+        if let Some(previous) = last.as_ref() {
+            let idle = prefix.sent_at.duration_since(previous.sent_at).unwrap_or_default();
+            // Expiry is judged by the learned TTL, not the nominal one
+            let unchanged = matches!(
+                previous.diverge(&prefix),
+                None | Some(CacheMissReport { reason: MissReason::Expired { .. }, .. })
+            );
+            let prediction = CachePrediction {
+                hit: cacheable && unchanged && idle <= self.ttl_for(previous.ttl),
+                cached_tokens: None,
+                idle: Some(idle),
+                ttl: Some(previous.ttl),
+            };
+            self.reconcile(prediction, usage);
+        }
+        let report = match last.as_ref() {
+            Some(previous) if missed => {
+                Some(previous.diverge(&prefix).unwrap_or_else(CacheMissReport::unexplained))
+            }
+            _ => None,
+        };
+        *last = Some(prefix);
+        report
+    }
+
+    /// Compare a prediction with the cache activity the provider reported,
+    /// and move the TTL model toward what was observed: a hit after a long
+    /// idle time lengthens it, a predicted hit that missed shortens it. A
+    /// miss within half the TTL is put down to eviction, not expiry, and
+    /// leaves it alone.
+    pub fn reconcile(&self, prediction: CachePrediction, usage: &TokenUsage) {
+        let Some(read) = usage.cache_read_tokens else {
+            return;
+        };
+        let hit = read > 0;
+
+        if let Ok(mut accuracy) = self.accuracy.lock() {
+            accuracy.predictions += 1;
+            match (prediction.hit, hit) {
+                (true, false) => accuracy.false_hits += 1,
+                (false, true) => accuracy.false_misses += 1,
+                _ => accuracy.correct += 1,
+            }
+            if let Some(tokens) = prediction.cached_tokens {
+                accuracy.predicted_tokens += tokens as u64;
+                accuracy.actual_tokens += read as u64;
+            }
+        }
+
+        if let Some(idle) = prediction.idle {
+            let written = prediction.ttl.unwrap_or(CacheControl::default().ttl());
+            let mut ttls = self.ttls.lock().unwrap();
+            let ttl = ttls.entry(written).or_insert(written);
+            if hit && idle > *ttl {
+                *ttl = idle;
+            } else if prediction.hit && !hit && idle < *ttl && idle > *ttl / 2 {
+                *ttl = (*ttl + idle) / 2;
+            }
+        }
+    }
+
+    /// How long an idle 5-minute entry is expected to stay cached
+    pub fn ttl(&self) -> Duration {
+        self.ttl_for(CacheControl::default().ttl())
+    }
+
+    /// How long an idle entry written with a `written` TTL is expected to
+    /// stay cached
+    pub fn ttl_for(&self, written: Duration) -> Duration {
+        self.ttls.lock().unwrap().get(&written).copied().unwrap_or(written)
+    }
+
+    /// Predictions reconciled so far
+    pub fn prediction_accuracy(&self) -> PredictionAccuracy {
+        self.accuracy.lock().unwrap().clone()
+    }
+
     /// Invalidate a cache entry
     pub fn invalidate(&self, key: &str) {
         let mut entries = self.entries.lock().unwrap();
@@ -267,6 +463,7 @@ impl CacheTracker {
             }
         }
 
+        let accuracy = self.prediction_accuracy();
         CacheSummary {
             entry_count: entries.len(),
             permanent_tokens,
@@ -275,6 +472,11 @@ impl CacheTracker {
             total_misses: metrics.cache_misses,
             hit_rate: metrics.hit_rate,
             estimated_savings: metrics.estimated_savings,
+            predictions: accuracy.predictions,
+            prediction_accuracy: accuracy.accuracy(),
+            token_drift: accuracy.token_drift(),
+            ttl_secs: self.ttl().as_secs(),
+            ttl_1h_secs: self.ttl_for(Duration::from_secs(60 * 60)).as_secs(),
             session_duration: self.session_start.elapsed(),
         }
     }
@@ -289,8 +491,14 @@ impl Default for CacheTracker {
 /// Status of a cache check
 #[derive(Debug)]
 pub enum CacheStatus {
-    /// Content is cached and matches
-    Hit { tokens: usize, age: Duration },
+    /// Content is cached and matches; `idle` is the time since last use
+    Hit {
+        tokens: usize,
+        age: Duration,
+        idle: Duration,
+    },
+    /// Content matches but sat idle for longer than the TTL
+    Expired { idle: Duration },
     /// Content was cached but has changed
     Stale,
     /// Content is not in cache
@@ -307,10 +515,27 @@ pub struct CacheSummary {
     pub total_misses: u64,
     pub hit_rate: f64,
     pub estimated_savings: u64,
+    /// Hit/miss predictions reconciled with provider usage
+    pub predictions: u64,
+    pub prediction_accuracy: Option<f64>,
+    /// Relative error of predicted cached tokens
+    pub token_drift: Option<f64>,
+    /// Observed TTL of idle 5-minute and 1-hour entries
+    pub ttl_secs: u64,
+    pub ttl_1h_secs: u64,
     #[serde(skip)]
     pub session_duration: Duration,
 }
 
+impl CacheSummary {
+    /// Whether predictions have stopped matching provider behaviour: under
+    /// 80% accurate, or cached tokens off by more than 25%
+    pub fn is_drifting(&self) -> bool {
+        let inaccurate = self.predictions >= 5 && self.prediction_accuracy.unwrap_or(1.0) < 0.8;
+        inaccurate || self.token_drift.is_some_and(|drift| drift.abs() > 0.25)
+    }
+}
+
 impl std::fmt::Display for CacheSummary {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "=== Cache Summary ===")?;
@@ -321,6 +546,21 @@ impl std::fmt::Display for CacheSummary {
         writeln!(f, "Total misses: {}", self.total_misses)?;
         writeln!(f, "Hit rate: {:.1}%", self.hit_rate * 100.0)?;
         writeln!(f, "Est. token savings: {}", self.estimated_savings)?;
+        if let Some(accuracy) = self.prediction_accuracy {
+            writeln!(
+                f,
+                "Prediction accuracy: {:.1}% ({} predictions)",
+                accuracy * 100.0,
+                self.predictions
+            )?;
+        }
+        if let Some(drift) = self.token_drift {
+            writeln!(f, "Cached token drift: {:+.1}%", drift * 100.0)?;
+        }
+        writeln!(f, "Observed TTL: {}s (1h entries: {}s)", self.ttl_secs, self.ttl_1h_secs)?;
+        if self.is_drifting() {
+            writeln!(f, "Warning: cache predictions drift from provider behaviour")?;
+        }
         writeln!(f, "Session duration: {:?}", self.session_duration)?;
         Ok(())
     }
@@ -384,4 +624,131 @@ mod tests {
 
         assert!((metrics.hit_rate - 0.666).abs() < 0.01);
     }
+
+    #[test]
+    fn test_record_usage() {
+        let tracker = CacheTracker::new(100);
+
+        tracker.record_usage(&TokenUsage::with_cache(200, 50, Some(0), Some(1800)));
+        tracker.record_usage(&TokenUsage::with_cache(2000, 50, Some(1500), Some(0)));
+        // No cache fields: the provider does not report caching
+        tracker.record_usage(&TokenUsage::new(2000, 50));
+
+        let metrics = tracker.get_metrics();
+        assert_eq!((metrics.cache_hits, metrics.cache_misses), (1, 1));
+        assert_eq!(metrics.cache_writes, 1);
+        assert_eq!(metrics.cached_tokens, 1800);
+        assert_eq!(metrics.uncached_tokens, 3500);
+    }
+
+    #[test]
+    fn test_reconcile_learns_ttl_and_drift() {
+        let tracker = CacheTracker::new(100);
+        let five_min = tracker.ttl();
+        let predict = |hit, tokens, idle_secs| CachePrediction {
+            hit,
+            cached_tokens: Some(tokens),
+            idle: Some(Duration::from_secs(idle_secs)),
+            ttl: None,
+        };
+        let read = |tokens| TokenUsage::with_cache(2000, 10, None, Some(tokens));
+
+        // Served from cache after 10 idle minutes: entries live longer
+        tracker.reconcile(predict(false, 0, 600), &read(2000));
+        assert_eq!(tracker.ttl(), Duration::from_secs(600));
+
+        // Predicted a hit after 8 minutes that missed: entries live shorter
+        tracker.reconcile(predict(true, 2000, 480), &read(0));
+        assert_eq!(tracker.ttl(), Duration::from_secs(540));
+        assert!(tracker.ttl() > five_min);
+
+        // One-hour entries are learned separately
+        let one_hour = Duration::from_secs(3600);
+        let hour_entry = CachePrediction {
+            cached_tokens: None,
+            ttl: Some(one_hour),
+            ..predict(false, 0, 4000)
+        };
+        tracker.reconcile(hour_entry, &TokenUsage::with_cache(2000, 10, None, Some(2000)));
+        assert_eq!(tracker.ttl_for(one_hour), Duration::from_secs(4000));
+        assert_eq!(tracker.ttl(), Duration::from_secs(540));
+
+        tracker.reconcile(predict(true, 2000, 60), &read(500));
+        // Providers without cache reporting are not reconciled
+        tracker.reconcile(predict(true, 2000, 60), &TokenUsage::new(2000, 10));
+
+        let accuracy = tracker.prediction_accuracy();
+        assert_eq!(accuracy.predictions, 4);
+        assert_eq!((accuracy.correct, accuracy.false_hits, accuracy.false_misses), (1, 1, 2));
+        // 2500 tokens read against 4000 predicted
+        assert!((accuracy.token_drift().unwrap() + 0.375).abs() < 1e-9);
+
+        let summary = tracker.summary();
+        assert_eq!(summary.ttl_secs, 540);
+        assert!(summary.is_drifting());
+    }
+
+    #[test]
+    fn test_no_hit_predicted_below_cache_minimum() {
+        use crate::api::ApiRequest;
+        use crate::cache::OpenAiCacheStrategy;
+
+        let tracker = CacheTracker::new(100);
+        let turn = |system: &str, sent_at| {
+            let request = ApiRequest::new("task".to_string()).with_system(system.to_string());
+            PrefixFingerprint {
+                sent_at,
+                ..PrefixFingerprint::of(&request)
+            }
+        };
+        let uncached = TokenUsage::with_cache(300, 10, None, Some(0));
+        let start = std::time::SystemTime::now();
+        let later = start + Duration::from_secs(30);
+
+        // A short prefix is never cached, so its misses are expected
+        tracker.check_prefix(turn("Be brief", start), &uncached, &OpenAiCacheStrategy);
+        let report = tracker.check_prefix(turn("Be brief", later), &uncached, &OpenAiCacheStrategy);
+        assert!(report.is_none());
+        let accuracy = tracker.prediction_accuracy();
+        assert_eq!((accuracy.correct, accuracy.false_hits), (1, 0));
+
+        let long = "stable instructions ".repeat(400);
+        let report = tracker.check_prefix(turn(&long, start), &uncached, &OpenAiCacheStrategy);
+        assert!(report.is_some());
+        let cached = TokenUsage::with_cache(2000, 10, None, Some(1920));
+        tracker.check_prefix(turn(&long, later), &cached, &OpenAiCacheStrategy);
+        assert_eq!(tracker.prediction_accuracy().correct, 3);
+        assert_eq!(tracker.ttl(), CacheControl::default().ttl());
+    }
+
+    #[test]
+    fn test_multi_turn_claude_history_reconciles() {
+        use crate::api::{ApiRequest, Message};
+        use crate::cache::AnthropicCacheStrategy;
+
+        let tracker = CacheTracker::new(100);
+        let claude = AnthropicCacheStrategy::default();
+        let start = std::time::SystemTime::now();
+        let mut history = Vec::new();
+
+        // Each turn extends the history; only its rolling breakpoints mark it
+        for turn in 0..6u64 {
+            history.push(Message::user(format!("question {} ", turn).repeat(200)));
+            history.push(Message::assistant(format!("answer {} ", turn).repeat(200)));
+            let mut request = ApiRequest::new("next".to_string());
+            request.messages = history.clone();
+            let prefix = PrefixFingerprint {
+                sent_at: start + Duration::from_secs(30 * turn),
+                ..PrefixFingerprint::of(&request)
+            };
+            let read = if turn == 0 { 0 } else { 2000 };
+            let usage = TokenUsage::with_cache(200, 10, Some(600), Some(read));
+            assert!(tracker.check_prefix(prefix, &usage, &claude).is_none());
+        }
+
+        let accuracy = tracker.prediction_accuracy();
+        assert_eq!((accuracy.predictions, accuracy.correct), (5, 5));
+        assert_eq!(accuracy.false_misses, 0);
+        assert!(!tracker.summary().is_drifting());
+    }
 }
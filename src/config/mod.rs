@@ -5,10 +5,16 @@
 //! 2. Environment variables (VENICE_API_KEY, ANTHROPIC_API_KEY, etc.)
 //! 3. CLI arguments (override file/env settings)
 
+use crate::api::ProviderSpec;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use thiserror::Error;
 
+/// Default Gemini API root, used when a section switches to `gemini` but
+/// keeps the base URL of the provider it had before
+pub const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Config file not found: {0}")]
@@ -28,7 +34,7 @@ pub enum ConfigError {
 }
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     /// Primary provider configuration (Venice.ai)
@@ -49,6 +55,17 @@ pub struct Config {
     /// Cache settings
     pub cache: CacheSettings,
 
+    /// Model per task complexity, for `send --provider auto`
+    pub routing: RoutingSettings,
+
+    /// Spending limits, overall and per provider
+    pub budget: BudgetSettings,
+
+    /// Additional named providers (`[providers.<name>]` tables), resolved
+    /// through the provider registry
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, ProviderSpec>,
+
     /// Legacy Venice settings (for backward compatibility)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub venice: Option<VeniceSettings>,
@@ -62,31 +79,14 @@ pub struct Config {
     pub openai: Option<OpenAISettings>,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            primary: PrimaryProviderSettings::default(),
-            fallback: FallbackProviderSettings::default(),
-            local: LocalLLMSettings::default(),
-            orchestrator: OrchestratorSettings::default(),
-            optimization: OptimizationSettings::default(),
-            cache: CacheSettings::default(),
-            // Legacy fields
-            venice: None,
-            claude: None,
-            openai: None,
-        }
-    }
-}
-
 /// Primary provider settings (Venice.ai)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PrimaryProviderSettings {
-    /// Provider type (currently only "venice" supported)
+    /// Provider type: "venice" or "gemini"
     pub provider: String,
 
-    /// API key (can also use VENICE_API_KEY env var)
+    /// API key (can also use VENICE_API_KEY or GEMINI_API_KEY env var)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_key: Option<String>,
 
@@ -95,6 +95,7 @@ pub struct PrimaryProviderSettings {
 
     /// Model to use for code generation
     /// Venice options: llama-3.3-70b, deepseek-coder-v2, qwen-2.5-coder-32b, etc.
+    /// Gemini options: gemini-2.5-pro, gemini-2.5-flash
     pub model: String,
 
     /// Minimum USD balance before triggering fallback
@@ -133,10 +134,11 @@ impl Default for PrimaryProviderSettings {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct FallbackProviderSettings {
-    /// Provider type: "claude", "openai", or "none"
+    /// Provider type: "claude", "openai", "gemini", or "none"
     pub provider: String,
 
-    /// API key (can also use ANTHROPIC_API_KEY or OPENAI_API_KEY env var)
+    /// API key (can also use ANTHROPIC_API_KEY, OPENAI_API_KEY or
+    /// GEMINI_API_KEY env var)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_key: Option<String>,
 
@@ -146,6 +148,7 @@ pub struct FallbackProviderSettings {
     /// Model to use for code generation
     /// Claude options: claude-sonnet-4-20250514, claude-opus-4-20250514
     /// OpenAI options: gpt-4, gpt-4-turbo, gpt-4o
+    /// Gemini options: gemini-2.5-pro, gemini-2.5-flash
     pub model: String,
 
     /// Maximum tokens for responses
@@ -326,6 +329,10 @@ pub struct LocalLLMSettings {
 
     /// Enable aggressive compression
     pub aggressive_compression: bool,
+
+    /// Context window requested from Ollama when it is used as a provider
+    /// (`None` keeps the model's default)
+    pub num_ctx: Option<u32>,
 }
 
 impl Default for LocalLLMSettings {
@@ -337,6 +344,7 @@ impl Default for LocalLLMSettings {
             max_compressed_tokens: 2000,
             relevance_threshold: 0.3,
             aggressive_compression: false,
+            num_ctx: None,
         }
     }
 }
@@ -357,9 +365,12 @@ pub struct OrchestratorSettings {
     /// Preserve context during handoff
     pub preserve_context: bool,
 
-    /// Allow primary after fallback triggered
+    /// Return to a provider automatically once it recovers from fallback
     pub allow_primary_after_fallback: bool,
 
+    /// Seconds a failing provider is skipped before its health is re-checked
+    pub recovery_cooldown_secs: u64,
+
     /// Session timeout in seconds
     pub session_timeout_secs: u64,
 
@@ -374,7 +385,8 @@ impl Default for OrchestratorSettings {
             fallback_provider: "claude".to_string(),
             max_retries: 2,
             preserve_context: true,
-            allow_primary_after_fallback: false,
+            allow_primary_after_fallback: true,
+            recovery_cooldown_secs: 60,
             session_timeout_secs: 3600,
             max_history: 20,
         }
@@ -441,6 +453,125 @@ impl Default for CacheSettings {
     }
 }
 
+/// A provider and model that requests can be routed to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteTarget {
+    /// Provider name, resolved like `send --provider`
+    pub provider: String,
+
+    /// Model to use instead of the provider's configured one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl RouteTarget {
+    pub fn new(provider: &str, model: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            model: Some(model.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RouteTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.model {
+            Some(model) => write!(f, "{}/{}", self.provider, model),
+            None => write!(f, "{}", self.provider),
+        }
+    }
+}
+
+/// Routing table from task complexity to the cheapest capable model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingSettings {
+    /// Ask the local LLM to classify tasks (requires local.enabled = true);
+    /// a heuristic is used otherwise or when it is unavailable
+    pub use_local_llm: bool,
+
+    /// Renames, typos, formatting
+    pub trivial: RouteTarget,
+
+    /// Everyday coding tasks
+    pub routine: RouteTarget,
+
+    /// Design, debugging and large or failing contexts
+    pub hard: RouteTarget,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        Self {
+            use_local_llm: false,
+            trivial: RouteTarget::new("venice", "venice-small"),
+            routine: RouteTarget::new("venice", "qwen-2.5-coder-32b"),
+            hard: RouteTarget::new("claude", "claude-sonnet-4-20250514"),
+        }
+    }
+}
+
+/// Spending caps in USD over rolling windows; unset caps are unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpendingLimits {
+    /// Cap over the last 24 hours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<f64>,
+
+    /// Cap over the last 7 days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weekly: Option<f64>,
+
+    /// Cap over the last 30 days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+}
+
+/// Spending budgets enforced before each request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetSettings {
+    /// Fraction of a cap at which requests start to warn (0.0 - 1.0)
+    pub warn_at: f64,
+
+    /// Caps on spending across all providers
+    pub total: SpendingLimits,
+
+    /// Caps per provider name (e.g. `[budget.providers.claude]`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, SpendingLimits>,
+
+    /// Cheaper provider to use once a cap is reached, e.g. "ollama";
+    /// requests are refused when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downgrade_to: Option<String>,
+
+    /// Prices per provider name, for providers whose responses carry no
+    /// cost and whose models have no list price (e.g. OpenAI, Gemini)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pricing: BTreeMap<String, TokenPrices>,
+}
+
+/// Prices in USD per million tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenPrices {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+impl Default for BudgetSettings {
+    fn default() -> Self {
+        Self {
+            warn_at: 0.8,
+            total: SpendingLimits::default(),
+            providers: BTreeMap::new(),
+            downgrade_to: None,
+            pricing: BTreeMap::new(),
+        }
+    }
+}
+
 impl Config {
     /// Get default config file path
     pub fn default_path() -> PathBuf {
@@ -472,9 +603,11 @@ impl Config {
 
     /// Apply environment variable overrides
     pub fn with_env_overrides(mut self) -> Self {
-        // Primary provider (Venice)
+        // Primary provider (Venice or Gemini)
         if let Ok(key) = std::env::var("VENICE_API_KEY") {
-            self.primary.api_key = Some(key);
+            if self.primary.provider == "venice" {
+                self.primary.api_key = Some(key);
+            }
         }
         if let Ok(url) = std::env::var("VENICE_BASE_URL") {
             self.primary.base_url = url;
@@ -495,6 +628,16 @@ impl Config {
                 self.fallback.api_key = Some(key);
             }
         }
+
+        // Gemini can serve as either primary or fallback
+        if let Some(key) = gemini_env_key() {
+            if self.primary.provider == "gemini" {
+                self.primary.api_key = Some(key.clone());
+            }
+            if self.fallback.provider == "gemini" {
+                self.fallback.api_key = Some(key);
+            }
+        }
         if let Ok(url) = std::env::var("FALLBACK_BASE_URL") {
             self.fallback.base_url = url;
         }
@@ -513,6 +656,19 @@ impl Config {
         // Migrate legacy config sections if present
         self = self.migrate_legacy();
 
+        // A section switched to Gemini without its own base URL keeps the
+        // default of the provider it replaced
+        if self.primary.provider == "gemini"
+            && self.primary.base_url == PrimaryProviderSettings::default().base_url
+        {
+            self.primary.base_url = GEMINI_BASE_URL.to_string();
+        }
+        if self.fallback.provider == "gemini"
+            && self.fallback.base_url == FallbackProviderSettings::default().base_url
+        {
+            self.fallback.base_url = GEMINI_BASE_URL.to_string();
+        }
+
         self
     }
 
@@ -583,9 +739,7 @@ impl Config {
     /// Validate configuration
     pub fn validate(&self) -> Result<(), ConfigError> {
         // Check if at least one provider is configured
-        let primary_configured = self.primary.enabled
-            && (self.primary.api_key.is_some()
-                || std::env::var("VENICE_API_KEY").is_ok());
+        let primary_configured = self.primary.enabled && self.primary_api_key().is_some();
 
         let fallback_configured = self.fallback.enabled
             && (self.fallback.api_key.is_some()
@@ -594,19 +748,21 @@ impl Config {
 
         if !primary_configured && !fallback_configured {
             return Err(ConfigError::MissingRequired(
-                "At least one provider must be configured (VENICE_API_KEY or ANTHROPIC_API_KEY/OPENAI_API_KEY)".to_string()
+                "At least one provider must be configured (VENICE_API_KEY, ANTHROPIC_API_KEY, OPENAI_API_KEY or GEMINI_API_KEY)".to_string()
             ));
         }
 
         Ok(())
     }
 
-    /// Get primary provider (Venice) API key (from config or env)
+    /// Get primary provider API key (from config or env)
     pub fn primary_api_key(&self) -> Option<String> {
-        self.primary
-            .api_key
-            .clone()
-            .or_else(|| std::env::var("VENICE_API_KEY").ok())
+        self.primary.api_key.clone().or_else(|| {
+            match self.primary.provider.as_str() {
+                "gemini" => gemini_env_key(),
+                _ => std::env::var("VENICE_API_KEY").ok(),
+            }
+        })
     }
 
     /// Get fallback provider API key (from config or env)
@@ -615,6 +771,7 @@ impl Config {
             match self.fallback.provider.as_str() {
                 "claude" => std::env::var("ANTHROPIC_API_KEY").ok(),
                 "openai" => std::env::var("OPENAI_API_KEY").ok(),
+                "gemini" => gemini_env_key(),
                 _ => None,
             }
         })
@@ -634,6 +791,28 @@ impl Config {
         }
     }
 
+    /// Get Gemini API key from whichever section uses Gemini, or the env
+    pub fn gemini_api_key(&self) -> Option<String> {
+        if self.primary.provider == "gemini" {
+            self.primary_api_key()
+        } else if self.fallback.provider == "gemini" {
+            self.fallback_api_key()
+        } else {
+            gemini_env_key()
+        }
+    }
+
+    /// Gemini API root from whichever section uses Gemini
+    pub fn gemini_base_url(&self) -> String {
+        if self.primary.provider == "gemini" {
+            self.primary.base_url.clone()
+        } else if self.fallback.provider == "gemini" {
+            self.fallback.base_url.clone()
+        } else {
+            GEMINI_BASE_URL.to_string()
+        }
+    }
+
     /// Generate example config content
     pub fn example() -> String {
         let example = Config::default();
@@ -641,6 +820,13 @@ impl Config {
     }
 }
 
+/// Gemini key from GEMINI_API_KEY, or GOOGLE_API_KEY as Google's SDKs accept
+fn gemini_env_key() -> Option<String> {
+    std::env::var("GEMINI_API_KEY")
+        .or_else(|_| std::env::var("GOOGLE_API_KEY"))
+        .ok()
+}
+
 /// Builder for creating Config programmatically
 pub struct ConfigBuilder {
     config: Config,
@@ -784,4 +970,13 @@ mod tests {
         assert!(example.contains("[primary]"));
         assert!(example.contains("[fallback]"));
     }
+
+    #[test]
+    fn test_gemini_section_gets_gemini_base_url() {
+        let mut config = Config::default();
+        config.fallback.provider = "gemini".to_string();
+        let config = config.with_env_overrides();
+        assert_eq!(config.fallback.base_url, GEMINI_BASE_URL);
+        assert_eq!(config.gemini_base_url(), GEMINI_BASE_URL);
+    }
 }
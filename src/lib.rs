@@ -18,15 +18,21 @@ pub mod config;
 pub mod metrics;
 pub mod optimization;
 pub mod orchestrator;
+pub mod tokenizer;
 pub mod tui;
 
 pub use agents::{LocalAgent, LocalAgentConfig, PreprocessingAgent};
-pub use api::{ApiAgent, ApiRequest, ApiResponse, VeniceConfig, VeniceProvider};
+pub use api::{
+    ApiAgent, ApiRequest, ApiResponse, ProviderRegistry, ProviderSpec, StreamChunk, VeniceConfig,
+    VeniceProvider,
+};
 pub use cache::{CacheConfig, CacheOptimizer, CacheTracker, CacheMetrics};
 pub use config::{Config, ConfigBuilder, ConfigError};
-pub use metrics::TokenMetrics;
+pub use metrics::{Budget, TokenMetrics};
 pub use orchestrator::{
-    ClaudeApiFallback, ClaudeCodeFallback, FallbackProvider, Orchestrator, OrchestratorConfig,
-    OrchestratorState, Session, SessionConfig,
+    BreakerPolicy, CircuitState, ClaudeApiFallback, ClaudeCodeFallback, ErrorClass, FallbackLink, FallbackProvider, LinkPolicy,
+    LinkState, LinkStatus, Orchestrator, OrchestratorConfig, OrchestratorState, ProviderFallback,
+    RegistryFallback, Session, SessionConfig,
 };
 pub use optimization::{OptimizationStrategy, PromptOptimizer};
+pub use tokenizer::{tokenizer_for_model, Tokenizer};
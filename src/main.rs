@@ -5,13 +5,17 @@ use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use token_optimizer::{
     agents::{LocalAgent, LocalAgentConfig, PreprocessingAgent},
-    api::{ApiConfig, ApiRequest, ContextItem, ContextType, ProviderType},
-    cache::{CacheConfig, CacheOptimizer},
+    api::{ApiRequest, ContextItem, ContextType, ResponseFormat},
+    cache::{
+        cache_strategy_for, CacheConfig, CacheCostModel, CacheOptimizer, PrefixFingerprint,
+        DEFAULT_EXPECTED_REUSES,
+    },
     config::Config,
     metrics::MetricsTracker,
     optimization::{OptimizationConfig, PromptOptimizer, StrategyType},
+    tokenizer::tokenizer_for_model,
 };
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 #[derive(Parser)]
@@ -62,14 +66,53 @@ enum Commands {
         #[arg(short, long)]
         context: Vec<PathBuf>,
 
-        /// API provider (claude, openai, ollama)
+        /// API provider (venice, claude, openai, ollama, gemini, or a
+        /// [providers.<name>] table from the config), or "auto" to pick one
+        /// by task complexity from the [routing] table
         #[arg(short, long, default_value = "claude")]
         provider: String,
 
-        /// Model to use
+        /// Model to use; not allowed with "auto", which picks the model too
+        #[arg(short, long)]
+        model: Option<String>,
+
+        /// Skip optimization
+        #[arg(long)]
+        no_optimize: bool,
+
+        /// Require the response to be a JSON object
+        #[arg(long)]
+        json: bool,
+
+        /// Require the response to match the JSON Schema in this file
+        #[arg(long, conflicts_with = "json")]
+        schema: Option<PathBuf>,
+    },
+
+    /// Run tasks through the Anthropic Message Batches API at half price
+    Batch {
+        /// JSONL file of tasks: {"id": "...", "task": "...", "system": "...",
+        /// "context": ["path", ...]}; only "task" is required
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// JSONL file to write one result per task to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Model to use (default: the configured Claude model)
         #[arg(short, long)]
         model: Option<String>,
 
+        /// Seconds between status checks
+        #[arg(long, default_value = "30")]
+        poll_secs: u64,
+
+        /// Collect the results of an already submitted batch (with the same
+        /// input file) instead of submitting a new one
+        #[arg(long)]
+        resume: Option<String>,
+
         /// Skip optimization
         #[arg(long)]
         no_optimize: bool,
@@ -116,6 +159,20 @@ enum Commands {
         /// Mark specific context files as static (by index, comma-separated)
         #[arg(long)]
         static_indices: Option<String>,
+
+        /// Model whose tokenizer sizes the cacheable blocks
+        #[arg(short, long, default_value = "claude-sonnet-4-20250514")]
+        model: String,
+
+        /// Provider whose caching rules decide the layout (claude, openai,
+        /// gemini, venice, ...)
+        #[arg(short, long, default_value = "claude")]
+        provider: String,
+
+        /// Later requests expected to reuse the cached prefix; breakpoints
+        /// that would not pay for their cache writes are dropped
+        #[arg(long, default_value_t = DEFAULT_EXPECTED_REUSES)]
+        expected_reuses: f64,
     },
 
     /// Manage configuration
@@ -156,7 +213,7 @@ enum ConfigCommands {
 
     /// Set API key interactively (masks input)
     SetKey {
-        /// Provider (venice, claude, openai)
+        /// Provider (venice, claude, openai, gemini)
         provider: String,
     },
 }
@@ -197,8 +254,33 @@ async fn main() -> Result<()> {
             provider,
             model,
             no_optimize,
+            json,
+            schema,
+        } => {
+            let format = if let Some(path) = schema {
+                let schema = serde_json::from_str(&tokio::fs::read_to_string(&path).await?)?;
+                let name = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "response".to_string());
+                // Most hand-written schemas are too loose for strict mode
+                ResponseFormat::json_schema(name, schema).with_strict(false)
+            } else if json {
+                ResponseFormat::JsonObject
+            } else {
+                ResponseFormat::Text
+            };
+            run_send(task, context, provider, model, no_optimize, format).await?;
+        }
+        Commands::Batch {
+            input,
+            output,
+            model,
+            poll_secs,
+            resume,
+            no_optimize,
         } => {
-            run_send(task, context, provider, model, no_optimize).await?;
+            run_batch(input, output, model, poll_secs, resume, no_optimize).await?;
         }
         Commands::Benchmark { input, context } => {
             run_benchmark(input, context).await?;
@@ -217,8 +299,20 @@ async fn main() -> Result<()> {
             context,
             system,
             static_indices,
+            model,
+            provider,
+            expected_reuses,
         } => {
-            run_cache_optimize(task, context, system, static_indices).await?;
+            run_cache_optimize(
+                task,
+                context,
+                system,
+                static_indices,
+                &model,
+                &provider,
+                expected_reuses,
+            )
+            .await?;
         }
         Commands::Config(cmd) => {
             run_config_command(cmd).await?;
@@ -313,8 +407,11 @@ async fn run_send(
     provider: String,
     model: Option<String>,
     no_optimize: bool,
+    format: ResponseFormat,
 ) -> Result<()> {
-    use token_optimizer::api::{ApiAgent, ApiProvider};
+    use token_optimizer::api::ProviderRegistry;
+    use token_optimizer::optimization::send_with_context_recovery;
+    use token_optimizer::orchestrator::ComplexityRouter;
 
     // Load context
     let mut context = Vec::new();
@@ -330,12 +427,53 @@ async fn run_send(
         });
     }
 
-    let mut request = ApiRequest::new(task).with_context(context);
+    let mut request = ApiRequest::new(task)
+        .with_context(context)
+        .with_response_format(format);
+    let settings = Config::load().unwrap_or_default();
+
+    // Send the task to the cheapest model capable of it
+    let (provider, model) = if provider == "auto" {
+        if let Some(model) = model {
+            anyhow::bail!("--model {} conflicts with --provider auto, which picks one", model);
+        }
+        let mut router = ComplexityRouter::new(settings.routing.clone());
+        if settings.routing.use_local_llm && settings.local.enabled {
+            let agent = LocalAgent::new(LocalAgentConfig {
+                ollama_url: settings.local.url.clone(),
+                model: settings.local.model.clone(),
+                ..Default::default()
+            });
+            router = router.with_local_agent(std::sync::Arc::new(agent));
+        }
+        let decision = router.route(&request).await;
+        println!(
+            "Routed {} task ({}) to {}",
+            decision.classification.complexity.as_str(),
+            decision.classification.reason,
+            decision.target
+        );
+        (decision.target.provider, decision.target.model)
+    } else {
+        (provider, model)
+    };
+
+    // Resolve the provider through the registry: a [providers.<name>]
+    // table, the section configured for it, or its defaults and env key
+    let registry = ProviderRegistry::with_builtins();
+    let (mut spec, mut agent) = registry.build_from_config(&settings, &provider)?;
+    if let Some(model) = model {
+        spec = spec.with_model(model);
+        agent = registry.build(&spec)?;
+    }
+
+    // Count tokens the way the target model bills them
+    let tokenizer = tokenizer_for_model(spec.model.as_deref().unwrap_or_default());
+    let optimizer =
+        PromptOptimizer::new(OptimizationConfig::default(), None).with_tokenizer(tokenizer);
 
     // Optimize if requested
     let tokens_saved = if !no_optimize {
-        let config = OptimizationConfig::default();
-        let optimizer = PromptOptimizer::new(config, None);
         let (optimized, stats) = optimizer.optimize(request).await?;
         request = optimized;
         stats.tokens_saved
@@ -343,39 +481,9 @@ async fn run_send(
         0
     };
 
-    // Setup API client
-    let provider_type = match provider.to_lowercase().as_str() {
-        "claude" => ProviderType::Claude,
-        "openai" => ProviderType::OpenAI,
-        "ollama" => ProviderType::Ollama,
-        _ => ProviderType::Custom,
-    };
-
-    let default_model = match provider_type {
-        ProviderType::Claude => "claude-sonnet-4-20250514",
-        ProviderType::OpenAI => "gpt-4",
-        ProviderType::Ollama => "llama3.2",
-        ProviderType::Custom => "default",
-    };
-
-    let api_key = std::env::var(match provider_type {
-        ProviderType::Claude => "ANTHROPIC_API_KEY",
-        ProviderType::OpenAI => "OPENAI_API_KEY",
-        _ => "API_KEY",
-    })
-    .unwrap_or_default();
-
-    let config = ApiConfig {
-        provider: provider_type,
-        api_key,
-        base_url: None,
-        model: model.unwrap_or_else(|| default_model.to_string()),
-        max_tokens: Some(4096),
-        temperature: Some(0.7),
-    };
-
-    let agent = ApiAgent::new(config);
-    let response = agent.send_request(request).await?;
+    // Shrink and resend automatically if the provider says it is too long
+    let response =
+        send_with_context_recovery(&optimizer, request, |req| agent.send_request(req)).await?;
 
     println!("{}", response.content);
     println!("\n--- Token Usage ---");
@@ -387,6 +495,128 @@ async fn run_send(
     Ok(())
 }
 
+/// One line of a `batch` input file
+#[derive(serde::Deserialize)]
+struct BatchTask {
+    /// Custom id (default: `task-<line number>`)
+    id: Option<String>,
+    task: String,
+    system: Option<String>,
+    #[serde(default)]
+    context: Vec<PathBuf>,
+}
+
+async fn run_batch(
+    input: PathBuf,
+    output: PathBuf,
+    model: Option<String>,
+    poll_secs: u64,
+    resume: Option<String>,
+    no_optimize: bool,
+) -> Result<()> {
+    use std::time::Duration;
+    use token_optimizer::api::{
+        record_batch_metrics, ApiAgent, BatchRequest, BatchResultStatus, MessageBatch,
+        ProviderRegistry, BATCH_DISCOUNT,
+    };
+    use token_optimizer::metrics::TokenMetrics;
+
+    let settings = Config::load().unwrap_or_default();
+    let registry = ProviderRegistry::with_builtins();
+    let (mut spec, mut provider) = registry.build_from_config(&settings, "claude")?;
+    if let Some(model) = model {
+        spec = spec.with_model(model);
+        provider = registry.build(&spec)?;
+    }
+    let agent = provider
+        .as_any()
+        .downcast_ref::<ApiAgent>()
+        .ok_or_else(|| anyhow::anyhow!("batch mode requires the Anthropic API client"))?;
+
+    let tokenizer = tokenizer_for_model(spec.model.as_deref().unwrap_or_default());
+    let optimizer =
+        PromptOptimizer::new(OptimizationConfig::default(), None).with_tokenizer(tokenizer);
+
+    let mut requests = Vec::new();
+    let content = tokio::fs::read_to_string(&input).await?;
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let task: BatchTask = serde_json::from_str(line)
+            .map_err(|e| anyhow::anyhow!("{}:{}: {}", input.display(), i + 1, e))?;
+
+        let mut context = Vec::new();
+        for path in task.context {
+            let content = tokio::fs::read_to_string(&path).await?;
+            context.push(ContextItem {
+                name: path.display().to_string(),
+                content,
+                item_type: ContextType::File,
+                relevance: None,
+                cache_control: None,
+                is_static: false,
+            });
+        }
+        let mut request = ApiRequest::new(task.task).with_context(context);
+        if let Some(system) = task.system {
+            request = request.with_cached_system(system);
+        }
+        // A resumed batch was optimized when it was submitted
+        if !no_optimize && resume.is_none() {
+            request = optimizer.optimize(request).await?.0;
+        }
+        let id = task.id.unwrap_or_else(|| format!("task-{}", i + 1));
+        requests.push(BatchRequest::new(id, request));
+    }
+
+    let poll_interval = Duration::from_secs(poll_secs);
+    let report = |batch: &MessageBatch| {
+        let counts = &batch.request_counts;
+        info!(
+            "Batch {}: {:?}, {} processing, {} succeeded, {} errored",
+            batch.id, batch.processing_status, counts.processing, counts.succeeded, counts.errored
+        );
+    };
+    let results = match resume {
+        Some(batch_id) => {
+            let batch = agent.wait_for_batch(&batch_id, poll_interval, report).await?;
+            agent.collect_batch(&batch, &requests).await?
+        }
+        None => {
+            println!("Submitting {} requests as one batch...", requests.len());
+            agent.run_batch(&requests, poll_interval, report).await?
+        }
+    };
+
+    let mut lines = String::new();
+    for result in &results {
+        lines.push_str(&serde_json::to_string(result)?);
+        lines.push('\n');
+    }
+    tokio::fs::write(&output, lines).await?;
+
+    let mut metrics = TokenMetrics::new();
+    record_batch_metrics(&results, &mut metrics);
+    let succeeded = results
+        .iter()
+        .filter(|r| r.status == BatchResultStatus::Succeeded)
+        .count();
+
+    println!("Wrote {} results to {}", results.len(), output.display());
+    println!("\n--- Batch Summary ---");
+    println!("Succeeded: {}/{}", succeeded, results.len());
+    println!("Input tokens: {}", metrics.total_input_tokens);
+    println!("Output tokens: {}", metrics.total_output_tokens);
+    println!(
+        "Cost: ${:.4} (${:.4} at standard prices)",
+        metrics.estimated_cost,
+        metrics.estimated_cost / BATCH_DISCOUNT
+    );
+
+    Ok(())
+}
+
 async fn run_benchmark(input: PathBuf, context_files: Vec<PathBuf>) -> Result<()> {
     use std::time::Instant;
 
@@ -522,6 +752,9 @@ async fn run_cache_optimize(
     context_files: Vec<PathBuf>,
     system_file: Option<PathBuf>,
     static_indices: Option<String>,
+    model: &str,
+    provider: &str,
+    expected_reuses: f64,
 ) -> Result<()> {
     info!("Analyzing request for cache optimization");
 
@@ -568,8 +801,16 @@ async fn run_cache_optimize(
 
     // Run cache optimizer
     let cache_config = CacheConfig::default();
-    let mut cache_optimizer = CacheOptimizer::new(cache_config);
+    let mut cache_optimizer = CacheOptimizer::new(cache_config)
+        .with_tokenizer(tokenizer_for_model(model))
+        .with_strategy(cache_strategy_for(provider, model));
+    if let Some(cost_model) = CacheCostModel::for_model(model) {
+        cache_optimizer =
+            cache_optimizer.with_cost_model(cost_model.with_expected_reuses(expected_reuses));
+    }
     let optimized = cache_optimizer.optimize_request(request);
+    let strategy = cache_optimizer.strategy();
+    let min_cache_tokens = strategy.min_cache_tokens();
 
     // Display results
     println!("\n=== Cache Optimization Analysis ===\n");
@@ -580,36 +821,71 @@ async fn run_cache_optimize(
             optimized.request.system_cache_control.is_some());
     }
     println!("  Context items: {}", optimized.request.context.len());
-    println!("  Cache breakpoints: {:?}", optimized.breakpoints);
+    println!("  Cache breakpoints: {}", optimized.breakpoints.len());
+    for bp in &optimized.breakpoints {
+        let net = bp
+            .expected_net_usd
+            .map(|usd| format!(", expected net ${:.4}", usd))
+            .unwrap_or_default();
+        println!("    {:?} ({}m TTL{})", bp.position, bp.control.ttl().as_secs() / 60, net);
+    }
 
     println!("\nToken Estimates:");
     println!("  Static (cacheable) tokens: ~{}", optimized.static_tokens);
     println!("  Dynamic tokens: ~{}", optimized.dynamic_tokens);
     println!("  Total tokens: ~{}", optimized.static_tokens + optimized.dynamic_tokens);
 
-    println!("\nCaching Potential:");
-    if optimized.static_tokens >= 1024 {
+    println!("\nCaching Potential ({}):", strategy.name());
+    if optimized.static_tokens >= min_cache_tokens {
         println!("  Status: CACHE ELIGIBLE");
         println!("  Est. tokens saved on repeat: ~{}", optimized.estimated_cache_savings);
-        println!("  Est. cost reduction: ~90% on cached portion");
+        println!("  Est. cost reduction: ~{:.0}% on cached portion",
+            strategy.read_discount() * 100.0);
     } else {
         println!("  Status: BELOW MINIMUM");
         println!("  Need {} more tokens in static content to enable caching",
-            1024 - optimized.static_tokens);
+            min_cache_tokens - optimized.static_tokens);
     }
 
     println!("\nContext Item Classification:");
     for (idx, item) in optimized.request.context.iter().enumerate() {
         let status = if item.is_static { "STATIC" } else { "DYNAMIC" };
-        let has_breakpoint = optimized.breakpoints.iter().any(|bp| {
-            matches!(bp, token_optimizer::cache::BreakpointPosition::AfterContext(i) if *i == idx)
-        });
-        let bp_marker = if has_breakpoint { " [BREAKPOINT]" } else { "" };
+        let bp_marker = match &item.cache_control {
+            Some(control) => format!(" [BREAKPOINT {}m]", control.ttl().as_secs() / 60),
+            None => String::new(),
+        };
         println!("  [{}] {} - {}{}", idx, status, item.name, bp_marker);
     }
 
+    // Compare with the previous run to predict whether this prefix would
+    // still hit the cache it wrote
+    let prefix = PrefixFingerprint::of(&optimized.request);
+    let prefix_path = dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("token-optimizer")
+        .join("last-prefix.json");
+    let previous: Option<PrefixFingerprint> = std::fs::read_to_string(&prefix_path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    if let Some(previous) = previous {
+        println!("\nCache Prefix vs Previous Run:");
+        match previous.diverge(&prefix) {
+            Some(report) => println!("  MISS: {}", report),
+            None => println!("  HIT: prefix unchanged"),
+        }
+    }
+    // Kept for the next run's miss diagnosis; failing to save is not fatal
+    let json = serde_json::to_string(&prefix)?;
+    let saved = prefix_path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&prefix_path, json));
+    if let Err(e) = saved {
+        warn!("Failed to save cache prefix to {}: {}", prefix_path.display(), e);
+    }
+
     println!("\nRecommendations:");
-    if optimized.static_tokens < 1024 {
+    if optimized.static_tokens < min_cache_tokens {
         println!("  - Add more static content (type definitions, documentation) to enable caching");
     }
     if !optimized.request.context.iter().any(|c| c.is_static) {
@@ -699,6 +975,11 @@ fn config_show(section: Option<String>) -> Result<()> {
         if display_config.fallback.api_key.is_some() {
             display_config.fallback.api_key = Some("***".to_string());
         }
+        for spec in display_config.providers.values_mut() {
+            if spec.api_key.is_some() {
+                spec.api_key = Some("***".to_string());
+            }
+        }
         toml::to_string_pretty(&display_config)?
     };
 
@@ -709,6 +990,7 @@ fn config_show(section: Option<String>) -> Result<()> {
     println!("VENICE_API_KEY: {}", if std::env::var("VENICE_API_KEY").is_ok() { "set" } else { "not set" });
     println!("ANTHROPIC_API_KEY: {}", if std::env::var("ANTHROPIC_API_KEY").is_ok() { "set" } else { "not set" });
     println!("OPENAI_API_KEY: {}", if std::env::var("OPENAI_API_KEY").is_ok() { "set" } else { "not set" });
+    println!("GEMINI_API_KEY: {}", if std::env::var("GEMINI_API_KEY").is_ok() { "set" } else { "not set" });
     println!("OLLAMA_URL: {}", std::env::var("OLLAMA_URL").unwrap_or_else(|_| "not set".to_string()));
 
     Ok(())
@@ -763,6 +1045,7 @@ fn config_set(key: &str, value: &str) -> Result<()> {
             "model" => config.local.model = value.to_string(),
             "enabled" => config.local.enabled = value.parse()?,
             "relevance_threshold" => config.local.relevance_threshold = value.parse()?,
+            "num_ctx" => config.local.num_ctx = Some(value.parse()?),
             _ => {
                 println!("Unknown local field: {}", field);
                 return Ok(());
@@ -854,6 +1137,13 @@ fn config_validate() -> Result<()> {
                 println!("  Local LLM: disabled");
             }
 
+            let registry = token_optimizer::api::ProviderRegistry::with_builtins();
+            for (name, spec) in &config.providers {
+                let kind = if spec.provider.is_empty() { name } else { &spec.provider };
+                let status = if registry.contains(kind) { "registered" } else { "UNKNOWN PROVIDER" };
+                println!("  {} ({}): {}", name, kind, status);
+            }
+
             println!();
             println!("Orchestration: {} -> {}",
                 config.orchestrator.primary_provider,
@@ -879,9 +1169,10 @@ async fn config_set_key(provider: &str) -> Result<()> {
         "venice" => "VENICE_API_KEY",
         "claude" | "anthropic" => "ANTHROPIC_API_KEY",
         "openai" => "OPENAI_API_KEY",
+        "gemini" | "google" => "GEMINI_API_KEY",
         _ => {
             println!("Unknown provider: {}", provider);
-            println!("Available: venice, claude, openai");
+            println!("Available: venice, claude, openai, gemini");
             return Ok(());
         }
     };
@@ -913,6 +1204,20 @@ async fn config_set_key(provider: &str) -> Result<()> {
             config.fallback.provider = "openai".to_string();
             config.fallback.api_key = Some(key.to_string());
         }
+        "gemini" | "google" => {
+            // Keep Gemini wherever it is already configured, else use it as fallback
+            if config.primary.provider == "gemini" {
+                config.primary.api_key = Some(key.to_string());
+            } else {
+                if config.fallback.provider != "gemini" {
+                    config.fallback.provider = "gemini".to_string();
+                    config.fallback.base_url =
+                        token_optimizer::config::GEMINI_BASE_URL.to_string();
+                    config.fallback.model = "gemini-2.5-flash".to_string();
+                }
+                config.fallback.api_key = Some(key.to_string());
+            }
+        }
         _ => {}
     }
 
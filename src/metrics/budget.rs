@@ -0,0 +1,347 @@
+//! Spending budgets backed by a persisted cost history
+//!
+//! Every request's cost is appended to a JSONL ledger that is re-read
+//! before each check, so caps hold across sessions and concurrent
+//! processes. Caps cover rolling windows of 24 hours, 7 days and 30 days,
+//! overall and per provider.
+
+use crate::api::{ModelPricing, TokenUsage};
+use crate::cache::cache_strategy_for;
+use crate::config::{BudgetSettings, SpendingLimits, TokenPrices};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Cost of one request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpendRecord {
+    /// Unix time in seconds
+    pub at: u64,
+    pub provider: String,
+    pub cost_usd: f64,
+}
+
+/// Window a cap applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn duration(&self) -> Duration {
+        let days = match self {
+            Self::Daily => 1,
+            Self::Weekly => 7,
+            Self::Monthly => 30,
+        };
+        Duration::from_secs(days * 24 * 60 * 60)
+    }
+
+    fn cap(&self, limits: &SpendingLimits) -> Option<f64> {
+        match self {
+            Self::Daily => limits.daily,
+            Self::Weekly => limits.weekly,
+            Self::Monthly => limits.monthly,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+        }
+    }
+}
+
+/// Outcome of checking a provider against its budgets
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetCheck {
+    Ok,
+    /// A soft threshold was crossed; the request may proceed
+    Warn(String),
+    /// A hard cap was reached
+    Exceeded(String),
+}
+
+/// Cost of `usage` on `provider`'s `model`: the provider-reported cost, else
+/// list prices where known, else nothing
+pub fn request_cost(usage: &TokenUsage, provider: &str, model: &str) -> f64 {
+    usage
+        .estimated_cost_usd
+        .or_else(|| {
+            let pricing = ModelPricing::for_model(model)?;
+            Some(billed_by(pricing, provider, model).cost(usage))
+        })
+        .unwrap_or(0.0)
+}
+
+fn configured_price(prices: &TokenPrices) -> ModelPricing {
+    ModelPricing::new(prices.input_per_mtok, prices.output_per_mtok)
+}
+
+/// `pricing` with cached reads charged at `provider`'s rate
+fn billed_by(pricing: ModelPricing, provider: &str, model: &str) -> ModelPricing {
+    let read_discount = cache_strategy_for(provider, model).read_discount() as f64;
+    pricing.with_cache_read_multiplier(1.0 - read_discount)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Enforces [`BudgetSettings`] against the recorded spend history
+pub struct Budget {
+    settings: BudgetSettings,
+    /// Ledger file; `None` keeps the history in memory only
+    path: Option<PathBuf>,
+    records: Mutex<Vec<SpendRecord>>,
+}
+
+impl Budget {
+    /// Default ledger location
+    pub fn default_path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("token-optimizer")
+            .join("spend.jsonl")
+    }
+
+    /// Budget over the ledger at `path`
+    pub fn load(settings: BudgetSettings, path: PathBuf) -> Self {
+        let budget = Self {
+            settings,
+            path: Some(path),
+            records: Mutex::new(Vec::new()),
+        };
+        budget.refresh();
+        budget
+    }
+
+    /// Re-read the last 30 days of the ledger, picking up spend recorded by
+    /// other processes. A missing or unreadable ledger keeps what is known.
+    fn refresh(&self) {
+        let Some(Ok(ledger)) = self.path.as_ref().map(std::fs::read_to_string) else {
+            return;
+        };
+        let cutoff = now_secs().saturating_sub(BudgetPeriod::Monthly.duration().as_secs());
+        *self.records.lock().unwrap() = ledger
+            .lines()
+            .filter_map(|line| serde_json::from_str::<SpendRecord>(line).ok())
+            .filter(|record| record.at >= cutoff)
+            .collect();
+    }
+
+    /// Budget whose history is not persisted
+    pub fn in_memory(settings: BudgetSettings) -> Self {
+        Self {
+            settings,
+            path: None,
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Provider to switch to once a cap is reached, if configured
+    pub fn downgrade_to(&self) -> Option<&str> {
+        self.settings.downgrade_to.as_deref()
+    }
+
+    /// USD spent within `period`, on `provider` or on all providers
+    pub fn spent(&self, provider: Option<&str>, period: BudgetPeriod) -> f64 {
+        let since = now_secs().saturating_sub(period.duration().as_secs());
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.at >= since)
+            .filter(|record| provider.is_none_or(|p| record.provider.eq_ignore_ascii_case(p)))
+            .map(|record| record.cost_usd)
+            .sum()
+    }
+
+    /// Cost of `usage` on `provider`'s `model`: the provider-reported cost,
+    /// else the configured price of the provider, else list prices
+    pub fn cost(&self, provider: &str, usage: &TokenUsage, model: &str) -> f64 {
+        match (usage.estimated_cost_usd, self.pricing(provider)) {
+            (None, Some(prices)) => {
+                billed_by(configured_price(prices), provider, model).cost(usage)
+            }
+            _ => request_cost(usage, provider, model),
+        }
+    }
+
+    /// Why spend on `provider` would go uncounted: it is capped but has
+    /// neither a configured price nor a list price for `model`
+    pub fn unpriced(&self, provider: &str, model: Option<&str>) -> Option<String> {
+        let limits = |l: &SpendingLimits| {
+            l.daily.is_some() || l.weekly.is_some() || l.monthly.is_some()
+        };
+        let capped = limits(&self.settings.total)
+            || self.provider_limits(provider).is_some_and(limits);
+        let priced = self.pricing(provider).is_some()
+            || model.and_then(ModelPricing::for_model).is_some();
+        (capped && !priced).then(|| {
+            format!(
+                "{} has a spending cap but no price; add [budget.pricing.{}] or its spend \
+                 counts as $0",
+                provider, provider
+            )
+        })
+    }
+
+    fn pricing(&self, provider: &str) -> Option<&TokenPrices> {
+        self.settings
+            .pricing
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(provider))
+            .map(|(_, prices)| prices)
+    }
+
+    fn provider_limits(&self, provider: &str) -> Option<&SpendingLimits> {
+        self.settings
+            .providers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(provider))
+            .map(|(_, limits)| limits)
+    }
+
+    /// Whether `provider` is the one to switch to once a cap is reached
+    pub fn is_downgrade(&self, provider: &str) -> bool {
+        self.downgrade_to().is_some_and(|to| to.eq_ignore_ascii_case(provider))
+    }
+
+    /// Check whether another request to `provider` stays within budget. The
+    /// downgrade provider is held only to its own caps, so it keeps serving
+    /// once the total is spent.
+    pub fn check(&self, provider: &str) -> BudgetCheck {
+        self.refresh();
+        let provider_limits = self.provider_limits(provider);
+        let total = (!self.is_downgrade(provider)).then_some(&self.settings.total);
+        let scopes = [(None, total), (Some(provider), provider_limits)];
+
+        let mut result = BudgetCheck::Ok;
+        for (scope, limits) in scopes {
+            let Some(limits) = limits else { continue };
+            for period in [BudgetPeriod::Daily, BudgetPeriod::Weekly, BudgetPeriod::Monthly] {
+                let Some(cap) = period.cap(limits) else { continue };
+                let spent = self.spent(scope, period);
+                let message = format!(
+                    "${:.2} of the ${:.2} {} {} budget spent",
+                    spent,
+                    cap,
+                    period.as_str(),
+                    scope.unwrap_or("total")
+                );
+                if spent >= cap {
+                    return BudgetCheck::Exceeded(message);
+                }
+                if spent >= cap * self.settings.warn_at && result == BudgetCheck::Ok {
+                    result = BudgetCheck::Warn(message);
+                }
+            }
+        }
+        result
+    }
+
+    /// Record a request's cost and append it to the ledger
+    pub fn record(&self, provider: &str, cost_usd: f64) {
+        if cost_usd <= 0.0 {
+            return;
+        }
+        let record = SpendRecord {
+            at: now_secs(),
+            provider: provider.to_string(),
+            cost_usd,
+        };
+        if let Some(path) = &self.path {
+            if let Err(e) = append(path, &record) {
+                warn!("Failed to record spend in {}: {}", path.display(), e);
+            }
+        }
+        self.records.lock().unwrap().push(record);
+    }
+}
+
+fn append(path: &PathBuf, record: &SpendRecord) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_caps_warn_then_refuse() {
+        let settings = BudgetSettings {
+            total: SpendingLimits {
+                daily: Some(10.0),
+                ..SpendingLimits::default()
+            },
+            providers: BTreeMap::from([(
+                "claude".to_string(),
+                SpendingLimits {
+                    weekly: Some(2.0),
+                    ..SpendingLimits::default()
+                },
+            )]),
+            downgrade_to: Some("ollama".to_string()),
+            ..BudgetSettings::default()
+        };
+        let path = std::env::temp_dir().join(format!("spend-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let budget = Budget::load(settings.clone(), path.clone());
+
+        budget.record("claude", 1.7);
+        assert!(matches!(budget.check("claude"), BudgetCheck::Warn(_)));
+        assert_eq!(budget.check("venice"), BudgetCheck::Ok);
+
+        // Spend by another process counts at the next check
+        let other = Budget::load(settings.clone(), path.clone());
+        other.record("venice", 8.5);
+        assert!(matches!(budget.check("venice"), BudgetCheck::Exceeded(_)));
+        assert!((budget.spent(None, BudgetPeriod::Daily) - 10.2).abs() < 1e-9);
+        assert_eq!(budget.check("ollama"), BudgetCheck::Ok);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unpriced_providers_are_flagged() {
+        let settings = BudgetSettings {
+            total: SpendingLimits {
+                monthly: Some(50.0),
+                ..SpendingLimits::default()
+            },
+            pricing: BTreeMap::from([(
+                "openai".to_string(),
+                TokenPrices {
+                    input_per_mtok: 2.5,
+                    output_per_mtok: 10.0,
+                },
+            )]),
+            ..BudgetSettings::default()
+        };
+        let budget = Budget::in_memory(settings);
+        let usage = TokenUsage::new(1_000_000, 100_000);
+
+        assert!((budget.cost("openai", &usage, "gpt-4o") - 3.5).abs() < 1e-9);
+        // OpenAI bills cached reads at half the input price, not a tenth
+        let cached = TokenUsage::with_cache(0, 0, None, Some(1_000_000));
+        assert!((budget.cost("openai", &cached, "gpt-4o") - 1.25).abs() < 1e-6);
+        assert!(budget.unpriced("openai", Some("gpt-4o")).is_none());
+        assert!(budget.unpriced("claude", Some("claude-sonnet-4-20250514")).is_none());
+        assert!(budget.unpriced("gemini", Some("gemini-2.0-flash")).is_some());
+    }
+}
@@ -1,7 +1,11 @@
 //! Metrics and tracking for token usage
 
+mod budget;
+
+pub use budget::{request_cost, Budget, BudgetCheck, BudgetPeriod, SpendRecord};
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
@@ -18,6 +22,9 @@ pub struct TokenMetrics {
     pub request_count: u64,
     /// Total estimated cost (USD)
     pub estimated_cost: f64,
+    /// Requests per routing decision, keyed "complexity -> provider/model"
+    #[serde(default)]
+    pub routes: BTreeMap<String, u64>,
     /// Per-session metrics
     #[serde(skip)]
     pub sessions: HashMap<String, SessionMetrics>,
@@ -56,6 +63,11 @@ impl TokenMetrics {
         }
     }
 
+    /// Count a request routed to `target` for its `complexity`
+    pub fn record_route(&mut self, complexity: &str, target: &str) {
+        *self.routes.entry(format!("{} -> {}", complexity, target)).or_default() += 1;
+    }
+
     pub fn compression_ratio(&self) -> f64 {
         let total_before = self.total_input_tokens + self.tokens_saved;
         if total_before == 0 {
@@ -133,6 +145,12 @@ impl MetricsTracker {
         }
     }
 
+    pub fn record_route(&self, complexity: &str, target: &str) {
+        if let Ok(mut metrics) = self.inner.lock() {
+            metrics.record_route(complexity, target);
+        }
+    }
+
     pub fn get_metrics(&self) -> TokenMetrics {
         self.inner
             .lock()
@@ -171,6 +189,7 @@ impl MetricsTracker {
             request_count: metrics.request_count,
             estimated_cost: metrics.estimated_cost,
             avg_tokens_per_request: metrics.average_tokens_per_request(),
+            routes: metrics.routes,
         }
     }
 }
@@ -189,6 +208,7 @@ pub struct MetricsSummary {
     pub request_count: u64,
     pub estimated_cost: f64,
     pub avg_tokens_per_request: f64,
+    pub routes: BTreeMap<String, u64>,
 }
 
 impl std::fmt::Display for MetricsSummary {
@@ -200,6 +220,12 @@ impl std::fmt::Display for MetricsSummary {
         writeln!(f, "Total requests: {}", self.request_count)?;
         writeln!(f, "Avg tokens/request: {:.1}", self.avg_tokens_per_request)?;
         writeln!(f, "Estimated cost: ${:.4}", self.estimated_cost)?;
+        if !self.routes.is_empty() {
+            writeln!(f, "Routing:")?;
+            for (route, count) in &self.routes {
+                writeln!(f, "  {}: {}", route, count)?;
+            }
+        }
         Ok(())
     }
 }
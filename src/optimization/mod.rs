@@ -1,9 +1,11 @@
 //! Optimization strategies for reducing token consumption
 
+mod recovery;
 mod strategies;
 
+pub use recovery::{send_with_context_recovery, MAX_CONTEXT_RETRIES};
 pub use strategies::{OptimizationStrategy, PromptOptimizer};
-pub(crate) use strategies::{count_tokens, smart_truncate};
+pub(crate) use strategies::smart_truncate;
 
 use serde::{Deserialize, Serialize};
 
@@ -0,0 +1,60 @@
+//! Automatic recovery from context-length errors
+//!
+//! When a provider rejects a request with [`ApiError::ContextTooLong`], the
+//! request is shrunk with [`PromptOptimizer::shrink_to_fit`] and resent.
+
+use super::PromptOptimizer;
+use crate::api::{ApiError, ApiRequest};
+use std::future::Future;
+use tracing::info;
+
+/// How many times a request rejected as too long is shrunk and resent
+pub const MAX_CONTEXT_RETRIES: u32 = 2;
+
+/// Send `request` via `send`, shrinking and resending it whenever the
+/// provider reports the context is too long.
+///
+/// Gives up with the provider's error once [`MAX_CONTEXT_RETRIES`] is
+/// reached or the request cannot be made any smaller.
+pub async fn send_with_context_recovery<T, F, Fut>(
+    optimizer: &PromptOptimizer,
+    mut request: ApiRequest,
+    mut send: F,
+) -> Result<T, ApiError>
+where
+    F: FnMut(ApiRequest) -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let mut attempts = 0;
+    loop {
+        match send(request.clone()).await {
+            Err(ApiError::ContextTooLong {
+                limit,
+                requested,
+                message,
+            }) if attempts < MAX_CONTEXT_RETRIES => {
+                attempts += 1;
+                match optimizer.shrink_to_fit(request, limit, requested).await {
+                    Ok(Some((shrunk, stats))) => {
+                        info!(
+                            "Context too long, retry {}/{} after shrinking {} -> {} tokens",
+                            attempts,
+                            MAX_CONTEXT_RETRIES,
+                            stats.original_tokens,
+                            stats.optimized_tokens
+                        );
+                        request = shrunk;
+                    }
+                    _ => {
+                        return Err(ApiError::ContextTooLong {
+                            limit,
+                            requested,
+                            message,
+                        })
+                    }
+                }
+            }
+            other => return other,
+        }
+    }
+}
@@ -2,13 +2,17 @@
 
 use super::{OptimizationConfig, OptimizationStats, StrategyType};
 use crate::agents::{LocalAgent, LocalTask, LocalTaskResult, PreprocessingAgent};
-use crate::api::ApiRequest;
+use crate::api::{ApiRequest, Message, Role};
+use crate::tokenizer::{default_tokenizer, Tokenizer};
 use std::collections::HashSet;
+use std::sync::Arc;
 
 /// Prompt optimizer that applies various strategies
 pub struct PromptOptimizer {
     config: OptimizationConfig,
     local_agent: Option<LocalAgent>,
+    /// Counts tokens against the budget (cl100k unless set per model)
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl PromptOptimizer {
@@ -16,9 +20,16 @@ impl PromptOptimizer {
         Self {
             config,
             local_agent,
+            tokenizer: default_tokenizer(),
         }
     }
 
+    /// Count tokens with the target model's tokenizer
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
     /// Optimize an API request using configured strategies
     pub async fn optimize(
         &self,
@@ -88,19 +99,93 @@ impl PromptOptimizer {
         Ok((optimized, stats))
     }
 
+    /// Re-optimize a request that a provider rejected as too long.
+    ///
+    /// Aims below the provider's limit when it reported `limit` and
+    /// `requested`, otherwise cuts the request by a quarter. Context
+    /// truncation is always applied, then the oldest history messages are
+    /// dropped while the request is still too long. Returns `None` if the
+    /// request could not be made any smaller.
+    pub async fn shrink_to_fit(
+        &self,
+        request: ApiRequest,
+        limit: Option<u32>,
+        requested: Option<u32>,
+    ) -> Result<Option<(ApiRequest, OptimizationStats)>, anyhow::Error> {
+        let current = self.estimate_tokens(&request);
+        let target = reduced_target_tokens(current, limit, requested);
+        let mut config = self.config.clone();
+        config.target_tokens = Some(target);
+        if !config
+            .strategies
+            .iter()
+            .any(|s| matches!(s, StrategyType::TruncateContext))
+        {
+            config.strategies.push(StrategyType::TruncateContext);
+        }
+
+        let shrinker = PromptOptimizer::new(config, self.local_agent.clone())
+            .with_tokenizer(self.tokenizer.clone());
+        let (shrunk, mut stats) = shrinker.optimize(request).await?;
+        let history = shrunk.messages.len();
+        let shrunk = self.trim_history(shrunk, target);
+        if shrunk.messages.len() < history {
+            let strategies = std::mem::take(&mut stats.strategies_applied);
+            stats = OptimizationStats::new(current, self.estimate_tokens(&shrunk));
+            stats.strategies_applied = strategies;
+            stats.strategies_applied.push("trim_history".to_string());
+        }
+        if stats.optimized_tokens >= current {
+            return Ok(None);
+        }
+        Ok(Some((shrunk, stats)))
+    }
+
+    /// Drop the oldest history messages until `request` fits in `target`
+    /// tokens. The history then restarts at a plain user message, so no
+    /// tool result is left without the call it answers.
+    fn trim_history(&self, mut request: ApiRequest, target: usize) -> ApiRequest {
+        let mut excess = self.estimate_tokens(&request).saturating_sub(target);
+        let mut drop = 0;
+        while excess > 0 && drop < request.messages.len() {
+            excess = excess.saturating_sub(self.message_tokens(&request.messages[drop]));
+            drop += 1;
+        }
+        if drop > 0 {
+            while request
+                .messages
+                .get(drop)
+                .is_some_and(|m| m.role != Role::User || !m.tool_results.is_empty())
+            {
+                drop += 1;
+            }
+            request.messages.drain(..drop);
+        }
+        request
+    }
+
+    fn message_tokens(&self, message: &Message) -> usize {
+        let count = |text: &str| self.tokenizer.count(text);
+        count(&message.content)
+            + message.tool_calls.iter().map(|c| count(&c.input.to_string())).sum::<usize>()
+            + message.tool_results.iter().map(|r| count(&r.content)).sum::<usize>()
+    }
+
     fn estimate_tokens(&self, request: &ApiRequest) -> usize {
+        let count = |text: &str| self.tokenizer.count(text);
         let mut total = 0;
 
         if let Some(system) = &request.system {
-            total += count_tokens(system);
+            total += count(system);
         }
 
         for ctx in &request.context {
-            total += count_tokens(&ctx.name);
-            total += count_tokens(&ctx.content);
+            total += count(&ctx.name);
+            total += count(&ctx.content);
         }
 
-        total += count_tokens(&request.task);
+        total += request.messages.iter().map(|m| self.message_tokens(m)).sum::<usize>();
+        total += count(&request.task);
 
         total
     }
@@ -135,10 +220,10 @@ impl PromptOptimizer {
         let tokens_per_item = target / num_items;
 
         for item in &mut request.context {
-            let item_tokens = count_tokens(&item.content);
+            let item_tokens = self.tokenizer.count(&item.content);
             if item_tokens > tokens_per_item {
-                // Convert token budget to approximate char budget (multiply by 4)
-                let char_budget = tokens_per_item * 4;
+                // Convert the token budget to chars at this item's own ratio
+                let char_budget = item.content.len() * tokens_per_item / item_tokens;
                 item.content = smart_truncate(&item.content, char_budget);
             }
         }
@@ -237,7 +322,7 @@ impl PromptOptimizer {
             let mut scored_items: Vec<_> = request
                 .context
                 .into_iter()
-                .zip(scores.into_iter())
+                .zip(scores)
                 .map(|(mut item, (_, llm_score))| {
                     let kw_score = if keywords.is_empty() {
                         llm_score
@@ -319,11 +404,30 @@ pub trait OptimizationStrategy: Send + Sync {
 
 // ─── Token counting ─────────────────────────────────────────────────────────
 
-/// Count tokens using tiktoken cl100k_base; falls back to len()/4
+/// Count tokens with the default tokenizer (cl100k_base); see
+/// [`crate::tokenizer::tokenizer_for_model`] for model-specific counts
 pub(crate) fn count_tokens(text: &str) -> usize {
-    tiktoken_rs::cl100k_base()
-        .map(|bpe| bpe.encode_with_special_tokens(text).len())
-        .unwrap_or_else(|_| text.len() / 4)
+    default_tokenizer().count(text)
+}
+
+/// Token budget for a request of `current` tokens that a provider rejected.
+///
+/// With reported counts, drop the excess plus 10% headroom (our count is not
+/// the provider's), and at least 5% of the request so each retry makes real
+/// progress. Never aims below a quarter of the current size.
+fn reduced_target_tokens(
+    current: usize,
+    limit: Option<u32>,
+    requested: Option<u32>,
+) -> usize {
+    match (limit, requested) {
+        (Some(limit), Some(requested)) if requested > limit => {
+            let excess = (requested - limit) as usize;
+            let cut = (excess + excess / 10).max(current / 20);
+            current.saturating_sub(cut).max(current / 4)
+        }
+        _ => current * 3 / 4,
+    }
 }
 
 // ─── Boundary-aware truncation ───────────────────────────────────────────────
@@ -902,4 +1006,71 @@ mod tests {
         let expected = 0.5 * 0.4 + 0.5 * 0.6;
         assert!((result - expected).abs() < 1e-5, "got {result}, expected {expected}");
     }
+
+    // ── shrink_to_fit ──
+
+    #[test]
+    fn reduced_target_drops_excess_with_headroom() {
+        // 1000 over the limit -> cut 1100
+        assert_eq!(reduced_target_tokens(10_000, Some(200_000), Some(201_000)), 8_900);
+        // Tiny excess still cuts 5%
+        assert_eq!(reduced_target_tokens(10_000, Some(100), Some(101)), 9_500);
+        // Huge excess is floored at a quarter
+        assert_eq!(reduced_target_tokens(10_000, Some(1_000), Some(90_000)), 2_500);
+        // Unknown counts cut a quarter
+        assert_eq!(reduced_target_tokens(10_000, None, None), 7_500);
+    }
+
+    #[tokio::test]
+    async fn shrink_to_fit_truncates_context() {
+        let content = "fn item() {}\n\n".repeat(2_000);
+        let request =
+            ApiRequest::new("fix it".to_string()).with_context(vec![crate::api::ContextItem {
+                name: "big.rs".to_string(),
+                content,
+                item_type: crate::api::ContextType::File,
+                relevance: None,
+                cache_control: None,
+                is_static: false,
+            }]);
+        let config = OptimizationConfig {
+            strategies: vec![StrategyType::StripWhitespace],
+            target_tokens: None,
+            ..OptimizationConfig::default()
+        };
+        let optimizer = PromptOptimizer::new(config, None);
+
+        let (shrunk, stats) = optimizer
+            .shrink_to_fit(request, None, None)
+            .await
+            .unwrap()
+            .expect("request should shrink");
+        assert!(stats.optimized_tokens < stats.original_tokens);
+        assert!(stats.strategies_applied.contains(&"truncate_context".to_string()));
+        assert!(shrunk.context[0].content.len() < 2_000 * 14);
+    }
+
+    #[tokio::test]
+    async fn shrink_to_fit_drops_oldest_history() {
+        let turn = |n: usize| {
+            vec![
+                Message::user(format!("question {} ", n).repeat(200)),
+                Message::assistant(format!("answer {} ", n).repeat(200)),
+            ]
+        };
+        let mut request = ApiRequest::new("and now?".to_string());
+        request.messages = (0..4).flat_map(turn).collect();
+        let optimizer = PromptOptimizer::new(OptimizationConfig::default(), None);
+
+        // Just over the limit: the first turn goes, not only its question
+        let (shrunk, stats) = optimizer
+            .shrink_to_fit(request, Some(100_000), Some(100_001))
+            .await
+            .unwrap()
+            .expect("history should be trimmed");
+        assert!(stats.strategies_applied.contains(&"trim_history".to_string()));
+        assert_eq!(shrunk.messages.len(), 6);
+        assert_eq!(shrunk.messages[0].role, Role::User);
+        assert!(shrunk.messages[0].content.starts_with("question 1"));
+    }
 }
@@ -0,0 +1,245 @@
+//! Circuit breaker for a link of the fallback chain
+//!
+//! A closed breaker lets requests through and watches their error rate. Too
+//! many failures, or an error that exhausts the provider, open it: the link
+//! is skipped until a cooldown passes. The breaker then half-opens, lets a
+//! health probe and a single trial request through, and closes again if the
+//! trial succeeds. A trial that never reports back, because its request was
+//! cancelled, expires after a timeout so the link is tried again.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// When a breaker opens and how long it stays open
+#[derive(Debug, Clone)]
+pub struct BreakerPolicy {
+    /// Error rate over the window that opens the breaker
+    pub failure_rate: f64,
+    /// Outcomes of the most recent requests considered
+    pub window: usize,
+    /// Requests in the window before the error rate is trusted
+    pub min_requests: usize,
+    /// Time an open breaker waits before probing the provider again
+    pub cooldown: Duration,
+    /// Time a half-open trial may run before it is presumed lost
+    pub trial_timeout: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.5,
+            window: 10,
+            min_requests: 4,
+            cooldown: Duration::from_secs(60),
+            trial_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl BreakerPolicy {
+    pub fn with_failure_rate(mut self, failure_rate: f64) -> Self {
+        self.failure_rate = failure_rate;
+        self
+    }
+
+    pub fn with_window(mut self, window: usize, min_requests: usize) -> Self {
+        self.window = window.max(1);
+        self.min_requests = min_requests.min(self.window);
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn with_trial_timeout(mut self, trial_timeout: Duration) -> Self {
+        self.trial_timeout = trial_timeout;
+        self
+    }
+}
+
+/// Position of a circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests flow normally
+    Closed,
+    /// Requests skip the link until the cooldown passes
+    Open,
+    /// One trial request is allowed through
+    HalfOpen,
+}
+
+/// Breaker state of one link
+#[derive(Debug, Clone)]
+pub(super) struct CircuitBreaker {
+    policy: BreakerPolicy,
+    state: CircuitState,
+    opened_at: Option<Instant>,
+    /// Recent outcomes, `true` for failures
+    outcomes: VecDeque<bool>,
+    /// When the half-open trial request in flight was let through
+    trial_started: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(super) fn new(policy: BreakerPolicy) -> Self {
+        Self {
+            policy,
+            state: CircuitState::Closed,
+            opened_at: None,
+            outcomes: VecDeque::new(),
+            trial_started: None,
+        }
+    }
+
+    pub(super) fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    /// Whether an open breaker has waited out its cooldown
+    pub(super) fn cooldown_elapsed(&self) -> bool {
+        self.state == CircuitState::Open
+            && self.opened_at.is_some_and(|at| at.elapsed() >= self.policy.cooldown)
+    }
+
+    /// Whether a request may be sent now. Only one trial at a time passes
+    /// a half-open breaker, unless the last one has timed out.
+    pub(super) fn allow(&mut self) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                let timeout = self.policy.trial_timeout;
+                if self.trial_started.is_some_and(|at| at.elapsed() < timeout) {
+                    return false;
+                }
+                self.trial_started = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Move an open breaker to half-open after a passing health probe
+    pub(super) fn half_open(&mut self) {
+        if self.state == CircuitState::Open {
+            self.state = CircuitState::HalfOpen;
+            self.trial_started = None;
+        }
+    }
+
+    pub(super) fn record_success(&mut self) {
+        if self.state == CircuitState::HalfOpen {
+            self.close();
+        } else {
+            self.push(false);
+        }
+    }
+
+    pub(super) fn record_failure(&mut self) {
+        if self.state == CircuitState::HalfOpen {
+            self.trip();
+            return;
+        }
+        self.push(true);
+        let failures = self.outcomes.iter().filter(|&&failed| failed).count();
+        if self.outcomes.len() >= self.policy.min_requests
+            && failures as f64 / self.outcomes.len() as f64 >= self.policy.failure_rate
+        {
+            self.trip();
+        }
+    }
+
+    /// Open the breaker now, restarting the cooldown
+    pub(super) fn trip(&mut self) {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(Instant::now());
+        self.trial_started = None;
+    }
+
+    pub(super) fn close(&mut self) {
+        self.state = CircuitState::Closed;
+        self.opened_at = None;
+        self.outcomes.clear();
+        self.trial_started = None;
+    }
+
+    fn push(&mut self, failed: bool) {
+        if self.outcomes.len() >= self.policy.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_on_error_rate_and_recovers() {
+        let mut breaker = CircuitBreaker::new(
+            BreakerPolicy::default().with_window(4, 4).with_cooldown(Duration::ZERO),
+        );
+
+        // Two failures in four requests reach the 50% threshold
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        // A failed trial reopens the breaker; a successful one closes it
+        assert!(breaker.cooldown_elapsed());
+        breaker.half_open();
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        breaker.half_open();
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn test_half_open_only_follows_open() {
+        let mut breaker = CircuitBreaker::new(BreakerPolicy::default());
+        breaker.half_open();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // Exhaustion trips at once; the cooldown has not passed yet
+        breaker.trip();
+        assert!(!breaker.cooldown_elapsed());
+        breaker.half_open();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // A failure outside the trial also reopens a half-open breaker
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn test_cancelled_trial_expires() {
+        let timeout = Duration::from_millis(20);
+        let mut breaker =
+            CircuitBreaker::new(BreakerPolicy::default().with_trial_timeout(timeout));
+        breaker.trip();
+        breaker.half_open();
+
+        // The trial's request is dropped and never reports back
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        std::thread::sleep(timeout);
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
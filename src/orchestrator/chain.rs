@@ -0,0 +1,184 @@
+//! Links of the orchestrator's fallback chain
+//!
+//! Each link pairs a provider with the policy deciding when a request moves
+//! on to the next link: which errors fall through, how low its balance may
+//! get, how often it is retried first, and when its circuit breaker opens.
+
+use super::{BreakerPolicy, CircuitState, FallbackProvider};
+use crate::api::{ApiError, RetryPolicy};
+use std::sync::Arc;
+
+/// Broad class of a provider error, for fallback policies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Credits or quota used up
+    Quota,
+    RateLimited,
+    Overloaded,
+    /// 5xx responses
+    Server,
+    /// Timeouts and failed connections
+    Network,
+    Auth,
+    ContextTooLong,
+    /// Invalid requests, filtered content and malformed responses
+    Other,
+}
+
+impl ErrorClass {
+    pub fn of(error: &ApiError) -> Self {
+        match error {
+            ApiError::QuotaExceeded(_) => Self::Quota,
+            ApiError::RateLimited { .. } => Self::RateLimited,
+            ApiError::Overloaded { .. } => Self::Overloaded,
+            ApiError::ServerError { .. } => Self::Server,
+            ApiError::Http(e) if e.is_timeout() || e.is_connect() => Self::Network,
+            ApiError::Auth(_) => Self::Auth,
+            ApiError::ContextTooLong { .. } => Self::ContextTooLong,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// When a link hands a request to the next one
+#[derive(Debug, Clone)]
+pub struct LinkPolicy {
+    /// Errors that move the request on to the next link; others are returned
+    pub fallback_on: Vec<ErrorClass>,
+    /// Errors that open the link's circuit at once, rather than counting
+    /// toward its error rate
+    pub exhaust_on: Vec<ErrorClass>,
+    /// Balance below which the link is only tried once the links after it
+    /// have failed
+    pub min_balance: Option<f64>,
+    /// Retries by the orchestrator, on top of any the provider does itself
+    pub retry: RetryPolicy,
+    pub breaker: BreakerPolicy,
+    /// Provider name the link's spending counts against; defaults to the
+    /// lowercased link name
+    pub budget_key: Option<String>,
+}
+
+impl Default for LinkPolicy {
+    /// Fall back on quota, rate-limit, overload, server and network errors;
+    /// a used-up quota exhausts the link
+    fn default() -> Self {
+        Self {
+            fallback_on: vec![
+                ErrorClass::Quota,
+                ErrorClass::RateLimited,
+                ErrorClass::Overloaded,
+                ErrorClass::Server,
+                ErrorClass::Network,
+            ],
+            exhaust_on: vec![ErrorClass::Quota],
+            min_balance: None,
+            retry: RetryPolicy::none(),
+            breaker: BreakerPolicy::default(),
+            budget_key: None,
+        }
+    }
+}
+
+impl LinkPolicy {
+    pub fn with_fallback_on(mut self, classes: Vec<ErrorClass>) -> Self {
+        self.fallback_on = classes;
+        self
+    }
+
+    pub fn with_exhaust_on(mut self, classes: Vec<ErrorClass>) -> Self {
+        self.exhaust_on = classes;
+        self
+    }
+
+    pub fn with_min_balance(mut self, min_balance: f64) -> Self {
+        self.min_balance = Some(min_balance);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_breaker(mut self, breaker: BreakerPolicy) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    pub fn with_budget_key(mut self, key: impl Into<String>) -> Self {
+        self.budget_key = Some(key.into());
+        self
+    }
+}
+
+/// A provider in the fallback chain, with its policy
+#[derive(Clone)]
+pub struct FallbackLink {
+    pub(super) provider: Arc<dyn FallbackProvider>,
+    pub(super) policy: LinkPolicy,
+}
+
+impl FallbackLink {
+    pub fn new(provider: impl FallbackProvider + 'static) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            policy: LinkPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: LinkPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.provider.name()
+    }
+
+    /// Provider name the link's spending counts against
+    pub fn budget_key(&self) -> String {
+        match &self.policy.budget_key {
+            Some(key) => key.clone(),
+            None => self.name().to_lowercase(),
+        }
+    }
+}
+
+/// Health of one link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// Tried in chain order
+    Ready,
+    /// Balance under the link's minimum; tried only after the later links
+    /// fail, until a re-check finds it topped up
+    Low,
+    /// Skipped until a health probe passes, e.g. credits used up
+    Exhausted,
+    /// Reported unavailable when last tried; checked again next request
+    Unavailable,
+}
+
+/// State of one link, as seen by the orchestrator
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkStatus {
+    pub name: String,
+    pub state: LinkState,
+    pub circuit: CircuitState,
+    /// Last balance the provider reported, if it reports one
+    pub balance: Option<f64>,
+    /// Error of the last failed attempt, cleared by a success
+    pub last_error: Option<String>,
+}
+
+impl LinkStatus {
+    pub(super) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state: LinkState::Ready,
+            circuit: CircuitState::Closed,
+            balance: None,
+            last_error: None,
+        }
+    }
+}
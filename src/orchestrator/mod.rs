@@ -1,34 +1,81 @@
 //! Agent orchestration with automatic fallback
 //!
 //! This module coordinates between multiple API providers, handling:
-//! - Primary provider (Venice.ai) with credit tracking
-//! - Automatic fallback to secondary provider (Claude) when credits exhausted
+//! - An ordered chain of providers, e.g. Venice.ai -> DeepSeek -> Claude API
+//!   -> Claude Code CLI, each with its own [`LinkPolicy`]
+//! - Automatic fallback down the chain on errors or a low balance
+//! - A circuit breaker per link that returns traffic to it once it recovers
+//! - A [`ComplexityRouter`] that picks the cheapest model capable of a task,
+//!   for callers that choose the provider per request (`send --provider auto`)
+//! - Daily, weekly and monthly spending caps, overall and per link
 //! - Session handoff with context preservation
 
+mod breaker;
+mod chain;
+mod routing;
 mod session;
 
+pub use breaker::{BreakerPolicy, CircuitState};
+pub use chain::{ErrorClass, FallbackLink, LinkPolicy, LinkState, LinkStatus};
+pub use routing::{classify_heuristic, Classification, ComplexityRouter, RouteDecision};
 pub use session::{Session, SessionConfig, SessionState};
 
-use crate::api::{ApiError, ApiProvider, ApiRequest, ApiResponse, VeniceProvider};
+use crate::api::streaming::{chunks_from_response, replay_chunks};
+use crate::api::{
+    ApiAgent, ApiConfig, ApiError, ApiProvider, ApiRequest, ApiResponse, ChatProvider,
+    ProviderRegistry, ProviderType, RetryPolicy, StreamChunk, StreamingProvider, TokenUsage,
+    VeniceProvider,
+};
 use crate::cache::CacheTracker;
-use crate::metrics::MetricsTracker;
-use crate::optimization::{smart_truncate, OptimizationConfig, PromptOptimizer, StrategyType};
+use crate::config::Config;
+use crate::metrics::{Budget, BudgetCheck, MetricsTracker};
+use crate::optimization::{
+    send_with_context_recovery, smart_truncate, OptimizationConfig, PromptOptimizer, StrategyType,
+};
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use breaker::CircuitBreaker;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
 use tracing::{info, warn};
 
-/// Fallback provider trait for Claude Code integration
+/// A provider that can serve as a link of the fallback chain
 #[async_trait]
 pub trait FallbackProvider: Send + Sync {
     /// Execute a request through the fallback provider
     async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError>;
 
+    /// Execute a request, streaming the response. Providers that cannot
+    /// stream send the whole response at once.
+    async fn execute_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+        let response = self.execute(request).await?;
+        Ok(replay_chunks(chunks_from_response(&response)))
+    }
+
     /// Check if the fallback provider is available
     async fn is_available(&self) -> bool;
 
     /// Get provider name for logging
     fn name(&self) -> &str;
+
+    /// Remaining credit, for providers that track it
+    async fn balance(&self) -> Option<f64> {
+        None
+    }
+
+    /// Check health before an open circuit lets traffic through again
+    async fn probe(&self) -> bool {
+        self.is_available().await
+    }
+
+    /// Model requests are sent to, for pricing streamed responses
+    fn model(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Orchestrator configuration
@@ -36,9 +83,13 @@ pub trait FallbackProvider: Send + Sync {
 pub struct OrchestratorConfig {
     /// Minimum Venice balance before preemptive fallback
     pub venice_min_balance: f64,
-    /// Whether to continue Venice requests after fallback starts
-    pub allow_venice_after_fallback: bool,
-    /// Maximum retries before fallback
+    /// Whether a link taken out of the chain returns automatically once a
+    /// health probe passes; otherwise it waits for [`Orchestrator::reset`]
+    pub allow_primary_after_fallback: bool,
+    /// Time a failing link is skipped before it is probed again
+    pub recovery_cooldown: Duration,
+    /// Maximum retries of rate-limited or transiently failing Venice
+    /// requests before falling back
     pub max_retries: u32,
     /// Whether to preserve context during handoff
     pub preserve_context: bool,
@@ -48,166 +99,445 @@ impl Default for OrchestratorConfig {
     fn default() -> Self {
         Self {
             venice_min_balance: 0.10,
-            allow_venice_after_fallback: false,
+            allow_primary_after_fallback: true,
+            recovery_cooldown: Duration::from_secs(60),
             max_retries: 2,
             preserve_context: true,
         }
     }
 }
 
+impl OrchestratorConfig {
+    /// Build an OrchestratorConfig from the config-file OrchestratorSettings
+    pub fn from_settings(settings: &crate::config::OrchestratorSettings) -> Self {
+        Self {
+            allow_primary_after_fallback: settings.allow_primary_after_fallback,
+            recovery_cooldown: Duration::from_secs(settings.recovery_cooldown_secs),
+            max_retries: settings.max_retries,
+            preserve_context: settings.preserve_context,
+            ..Self::default()
+        }
+    }
+}
+
+/// Optimization used for fallback handoff and for shrinking requests that
+/// were rejected as too long
+fn compact_optimization_config() -> OptimizationConfig {
+    OptimizationConfig {
+        strategies: vec![
+            StrategyType::StripWhitespace,
+            StrategyType::RemoveComments,
+            StrategyType::TruncateContext,
+            StrategyType::Deduplicate,
+        ],
+        ..OptimizationConfig::default()
+    }
+}
+
 /// Current orchestrator state
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum OrchestratorState {
-    /// Using Venice as primary provider
-    UsingVenice,
-    /// Venice credits low, preparing for fallback
-    VeniceLow,
-    /// Transitioned to fallback provider
-    UsingFallback,
-    /// Both providers unavailable
-    Unavailable,
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrchestratorState {
+    /// Every link of the chain, in order
+    pub links: Vec<LinkStatus>,
+    /// Link that served the last successful request
+    pub active: Option<usize>,
+}
+
+impl OrchestratorState {
+    /// Status of the link that served the last successful request
+    pub fn active_link(&self) -> Option<&LinkStatus> {
+        self.active.and_then(|idx| self.links.get(idx))
+    }
+
+    /// Whether every link's circuit is open
+    pub fn is_unavailable(&self) -> bool {
+        self.links.iter().all(|link| link.circuit == CircuitState::Open)
+    }
 }
 
-/// Orchestrates requests between Venice.ai and Claude Code
-pub struct Orchestrator<F: FallbackProvider> {
+/// Orchestrates requests down a chain of providers
+pub struct Orchestrator {
     config: OrchestratorConfig,
-    venice: Arc<VeniceProvider>,
-    fallback: Arc<F>,
+    chain: Vec<FallbackLink>,
+    /// One per link; `LinkStatus::circuit` mirrors their state
+    breakers: Vec<Mutex<CircuitBreaker>>,
     state: Arc<RwLock<OrchestratorState>>,
     metrics: Arc<MetricsTracker>,
     cache_tracker: Arc<CacheTracker>,
     /// Accumulated context for session handoff
     session_context: Arc<RwLock<Vec<String>>>,
+    /// Shrinks requests that a provider rejects as too long
+    optimizer: PromptOptimizer,
+    /// Spending caps; links over budget are skipped
+    budget: Option<Arc<Budget>>,
+}
+
+/// Records a finished response: metrics, cache hits, spend and the session
+/// context kept for handoff. Moved into the task forwarding a stream.
+struct ResponseRecorder {
+    metrics: Arc<MetricsTracker>,
+    cache_tracker: Arc<CacheTracker>,
+    /// Budget and the key the response's cost counts against
+    budget: Option<(Arc<Budget>, String)>,
+    session_context: Option<Arc<RwLock<Vec<String>>>>,
+}
+
+impl ResponseRecorder {
+    async fn record(&self, model: &str, content: &str, usage: &TokenUsage) {
+        self.metrics.record_request(
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            0,
+            usage.estimated_cost_usd,
+        );
+        self.cache_tracker.record_usage(usage);
+        if let Some((budget, key)) = &self.budget {
+            budget.record(key, budget.cost(key, usage, model));
+        }
+
+        // Store response in session context for potential handoff
+        if let Some(session_context) = &self.session_context {
+            session_context.write().await.push(content.to_string());
+        }
+    }
 }
 
-impl<F: FallbackProvider> Orchestrator<F> {
-    pub fn new(
+impl Orchestrator {
+    /// Venice as primary, with a single fallback
+    pub fn new<F: FallbackProvider + 'static>(
         config: OrchestratorConfig,
         venice: VeniceProvider,
         fallback: F,
         metrics: MetricsTracker,
     ) -> Self {
+        let breaker = BreakerPolicy::default().with_cooldown(config.recovery_cooldown);
+        // Retries happen in the chain, so the provider must not retry too
+        let venice = FallbackLink::new(venice.with_retry_policy(RetryPolicy::none())).with_policy(
+            LinkPolicy::default()
+                .with_min_balance(config.venice_min_balance)
+                .with_retry(RetryPolicy::default().with_max_retries(config.max_retries))
+                .with_breaker(breaker.clone()),
+        );
+        let fallback =
+            FallbackLink::new(fallback).with_policy(LinkPolicy::default().with_breaker(breaker));
+        Self::with_chain(config, vec![venice, fallback], metrics)
+    }
+
+    /// Providers tried in order, each falling back to the next according to
+    /// its policy
+    pub fn with_chain(
+        config: OrchestratorConfig,
+        chain: Vec<FallbackLink>,
+        metrics: MetricsTracker,
+    ) -> Self {
+        let state = OrchestratorState {
+            links: chain.iter().map(|link| LinkStatus::new(link.name())).collect(),
+            active: None,
+        };
+        let breakers = chain
+            .iter()
+            .map(|link| Mutex::new(CircuitBreaker::new(link.policy.breaker.clone())))
+            .collect();
         Self {
             config,
-            venice: Arc::new(venice),
-            fallback: Arc::new(fallback),
-            state: Arc::new(RwLock::new(OrchestratorState::UsingVenice)),
+            chain,
+            breakers,
+            state: Arc::new(RwLock::new(state)),
             metrics: Arc::new(metrics),
             cache_tracker: Arc::new(CacheTracker::default()),
             session_context: Arc::new(RwLock::new(Vec::new())),
+            optimizer: PromptOptimizer::new(compact_optimization_config(), None),
+            budget: None,
         }
     }
 
+    /// Enforce `budget`, recording the cost of every response in it. Links
+    /// over a cap are skipped; the budget's downgrade provider, if it is in
+    /// the chain, serves only requests a cap kept from the links before it.
+    pub fn with_budget(mut self, budget: impl Into<Arc<Budget>>) -> Self {
+        let budget = budget.into();
+        for link in &self.chain {
+            if let Some(reason) = budget.unpriced(&link.budget_key(), link.provider.model()) {
+                warn!("{}", reason);
+            }
+        }
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Shrink handoffs and requests rejected as too long with `optimizer`
+    /// rather than the default compacting one, e.g. to keep the user's
+    /// optimization settings and the model's tokenizer
+    pub fn with_optimizer(mut self, optimizer: PromptOptimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// Record cache hits in `tracker` rather than a tracker of its own
+    pub fn with_cache_tracker(mut self, tracker: Arc<CacheTracker>) -> Self {
+        self.cache_tracker = tracker;
+        self
+    }
+
     /// Get current orchestrator state
     pub async fn state(&self) -> OrchestratorState {
         self.state.read().await.clone()
     }
 
-    /// Get Venice balance information
-    pub async fn venice_balance(&self) -> crate::api::VeniceBalance {
-        self.venice.get_balance().await
+    /// Execute a request with automatic fallback.
+    ///
+    /// A request rejected as too long is shrunk and resent automatically.
+    pub async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+        let send =
+            |req| self.send_down_chain(req, |link, req| async move { link.execute(req).await });
+        let (idx, response) = send_with_context_recovery(&self.optimizer, request, send).await?;
+        self.record_success(idx).await;
+        let recorder = self.recorder(idx);
+        recorder.record(&response.model, &response.content, &response.usage).await;
+        Ok(response)
     }
 
-    /// Execute a request with automatic fallback
-    pub async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
-        let current_state = self.state.read().await.clone();
+    /// Execute a request with automatic fallback, streaming the response.
+    ///
+    /// Fallback happens before the first chunk: once a link accepts the
+    /// request its stream is passed through as is. Usage, spend and session
+    /// context are recorded when the stream ends, including the usage seen
+    /// so far when it fails partway.
+    pub async fn execute_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+        let send = |req| {
+            self.send_down_chain(req, |link, req| async move { link.execute_streaming(req).await })
+        };
+        let (idx, mut upstream) = send_with_context_recovery(&self.optimizer, request, send).await?;
+        self.record_success(idx).await;
+        let recorder = self.recorder(idx);
+        let model = self.chain[idx].provider.model().unwrap_or_default().to_string();
+
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            let mut content = String::new();
+            let mut usage = TokenUsage::default();
+            while let Some(chunk) = upstream.recv().await {
+                match &chunk {
+                    StreamChunk::TextDelta(text) => content.push_str(text),
+                    StreamChunk::Usage(u) | StreamChunk::Done(u) => usage.merge(u),
+                    _ => {}
+                }
+                let is_final = matches!(chunk, StreamChunk::Done(_) | StreamChunk::Error(_));
+                // Keep reading after the receiver is dropped, so the
+                // response is still recorded
+                let _ = tx.send(chunk).await;
+                if is_final {
+                    break;
+                }
+            }
+            recorder.record(&model, &content, &usage).await;
+        });
+        Ok(rx)
+    }
 
-        match current_state {
-            OrchestratorState::UsingVenice | OrchestratorState::VeniceLow => {
-                self.try_venice_with_fallback(request).await
+    /// Send `request` down the chain with `send`, skipping links whose
+    /// circuit is open or that are over budget. Links low on balance are
+    /// tried last, once every other link has failed. Returns the index of
+    /// the link that accepted it.
+    async fn send_down_chain<T, F, Fut>(
+        &self,
+        mut request: ApiRequest,
+        send: F,
+    ) -> Result<(usize, T), ApiError>
+    where
+        F: Fn(Arc<dyn FallbackProvider>, ApiRequest) -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let mut last_error = None;
+        let mut over_budget = None;
+        let mut order: Vec<usize> = (0..self.chain.len()).collect();
+        let mut next = 0;
+        while let Some(&idx) = order.get(next) {
+            next += 1;
+            let link = &self.chain[idx];
+            if idx > 0 && self.is_downgrade(link) && over_budget.is_none() {
+                continue;
+            }
+            if let Err(reason) = self.within_budget(link) {
+                over_budget = Some(reason);
+                continue;
             }
-            OrchestratorState::UsingFallback => {
-                self.execute_fallback(request).await
+            if next <= self.chain.len() && self.still_low(idx).await {
+                order.push(idx);
+                continue;
             }
-            OrchestratorState::Unavailable => {
-                Err(ApiError::Provider("No providers available".to_string()))
+            if !self.admit(idx).await {
+                continue;
+            }
+            if !link.provider.is_available().await {
+                self.set_link_state(idx, LinkState::Unavailable).await;
+                self.update_breaker(idx, CircuitBreaker::record_failure).await;
+                continue;
+            }
+
+            let result =
+                link.policy.retry.run(|| send(link.provider.clone(), request.clone())).await;
+            let error = match result {
+                Ok(value) => return Ok((idx, value)),
+                Err(e) => e,
+            };
+
+            let class = ErrorClass::of(&error);
+            self.state.write().await.links[idx].last_error = Some(error.to_string());
+            if !link.policy.fallback_on.contains(&class) {
+                // The provider answered; the request itself was at fault
+                self.update_breaker(idx, CircuitBreaker::record_success).await;
+                return Err(error);
             }
+            if link.policy.exhaust_on.contains(&class) {
+                warn!("{} exhausted ({}), handing off to the next provider", link.name(), error);
+                self.set_link_state(idx, LinkState::Exhausted).await;
+                self.update_breaker(idx, CircuitBreaker::trip).await;
+                request = self.with_handoff(request, link.name()).await;
+            } else {
+                warn!("{} failing ({}), trying the next provider", link.name(), error);
+                self.update_breaker(idx, CircuitBreaker::record_failure).await;
+            }
+            last_error = Some(error);
         }
+
+        Err(last_error
+            .or(over_budget.map(ApiError::BudgetExceeded))
+            .unwrap_or_else(|| ApiError::Provider("No providers available".to_string())))
     }
 
-    async fn try_venice_with_fallback(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
-        let mut retries = 0;
-
-        loop {
-            match self.venice.send_request(request.clone()).await {
-                Ok(response) => {
-                    // Check balance after successful request
-                    let balance = self.venice.get_balance().await;
-                    if balance.balance_usd < self.config.venice_min_balance
-                        && balance.balance_diem < self.config.venice_min_balance
-                    {
-                        info!(
-                            "Venice balance low (${:.2} USD, {:.2} Diem), preparing for fallback",
-                            balance.balance_usd, balance.balance_diem
-                        );
-                        *self.state.write().await = OrchestratorState::VeniceLow;
-                    }
-
-                    // Track metrics
-                    self.metrics.record_request(
-                        response.usage.prompt_tokens,
-                        response.usage.completion_tokens,
-                        0,
-                        response.usage.estimated_cost_usd,
-                    );
-
-                    // Store response in session context for potential handoff
-                    if self.config.preserve_context {
-                        self.session_context
-                            .write()
-                            .await
-                            .push(response.content.clone());
-                    }
-
-                    return Ok(response);
-                }
-                Err(ApiError::Provider(msg)) if msg.contains("exhausted") => {
-                    warn!("Venice credits exhausted, switching to fallback");
-                    *self.state.write().await = OrchestratorState::UsingFallback;
-                    return self.execute_fallback_with_handoff(request).await;
-                }
-                Err(ApiError::RateLimited { retry_after_secs }) => {
-                    if retries < self.config.max_retries {
-                        retries += 1;
-                        info!(
-                            "Venice rate limited, retry {}/{} after {}s",
-                            retries, self.config.max_retries, retry_after_secs
-                        );
-                        tokio::time::sleep(tokio::time::Duration::from_secs(retry_after_secs)).await;
-                        continue;
-                    } else {
-                        warn!("Venice rate limit retries exhausted, switching to fallback");
-                        return self.execute_fallback(request).await;
-                    }
-                }
-                Err(e) => {
-                    if retries < self.config.max_retries {
-                        retries += 1;
-                        warn!("Venice error: {}, retry {}/{}", e, retries, self.config.max_retries);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                        continue;
-                    } else {
-                        return Err(e);
-                    }
-                }
+    /// Whether `link` is the budget's downgrade provider, reserved for
+    /// requests a spending cap kept from the links before it unless it
+    /// heads the chain
+    fn is_downgrade(&self, link: &FallbackLink) -> bool {
+        self.budget.as_ref().is_some_and(|budget| budget.is_downgrade(&link.budget_key()))
+    }
+
+    /// Whether `link` may spend more; warns once a soft threshold is crossed
+    fn within_budget(&self, link: &FallbackLink) -> Result<(), String> {
+        let Some(budget) = &self.budget else {
+            return Ok(());
+        };
+        match budget.check(&link.budget_key()) {
+            BudgetCheck::Ok => Ok(()),
+            BudgetCheck::Warn(reason) => {
+                warn!("{} nearing its budget: {}", link.name(), reason);
+                Ok(())
+            }
+            BudgetCheck::Exceeded(reason) => {
+                info!("{} over budget ({}), skipping it", link.name(), reason);
+                Err(reason)
+            }
+        }
+    }
+
+    /// Whether link `idx` is still below its minimum balance, re-read from
+    /// the provider. A topped-up link is ready again.
+    async fn still_low(&self, idx: usize) -> bool {
+        if self.state.read().await.links[idx].state != LinkState::Low {
+            return false;
+        }
+        let link = &self.chain[idx];
+        let balance = link.provider.balance().await;
+        let low = matches!(
+            (balance, link.policy.min_balance),
+            (Some(balance), Some(min)) if balance < min
+        );
+        let mut state = self.state.write().await;
+        state.links[idx].balance = balance;
+        if !low {
+            info!("{} topped up, returning it to the chain", link.name());
+            state.links[idx].state = LinkState::Ready;
+        }
+        low
+    }
+
+    /// Whether link `idx` may take a request. An open circuit whose cooldown
+    /// has passed is probed first, and half-opens for one trial request if
+    /// the provider is healthy and its balance has recovered.
+    async fn admit(&self, idx: usize) -> bool {
+        let probe_due = self.breakers[idx].lock().unwrap().cooldown_elapsed();
+        if probe_due && self.config.allow_primary_after_fallback {
+            let link = &self.chain[idx];
+            let healthy = link.provider.probe().await;
+            let balance = link.provider.balance().await;
+            let funded = match (balance, link.policy.min_balance) {
+                (Some(balance), Some(min)) => balance >= min,
+                _ => true,
+            };
+            self.state.write().await.links[idx].balance = balance;
+            if healthy && funded {
+                info!("{} passed its health check, sending a trial request", link.name());
+                self.update_breaker(idx, CircuitBreaker::half_open).await;
+            } else {
+                self.update_breaker(idx, CircuitBreaker::trip).await;
             }
         }
+
+        self.breakers[idx].lock().unwrap().allow()
     }
 
-    async fn execute_fallback(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
-        if !self.fallback.is_available().await {
-            *self.state.write().await = OrchestratorState::Unavailable;
-            return Err(ApiError::Provider(format!(
-                "Fallback provider '{}' unavailable",
-                self.fallback.name()
-            )));
+    /// Mark link `idx` as serving and check its balance
+    async fn record_success(&self, idx: usize) {
+        let link = &self.chain[idx];
+        let balance = link.provider.balance().await;
+        let low = matches!(
+            (balance, link.policy.min_balance),
+            (Some(balance), Some(min)) if balance < min
+        );
+        if low {
+            let balance = balance.unwrap();
+            info!("{} balance low ({:.2}), keeping it as a last resort", link.name(), balance);
         }
 
-        info!("Executing request via fallback provider: {}", self.fallback.name());
-        self.fallback.execute(request).await
+        {
+            let mut state = self.state.write().await;
+            let status = &mut state.links[idx];
+            status.state = if low { LinkState::Low } else { LinkState::Ready };
+            status.balance = balance;
+            status.last_error = None;
+            state.active = Some(idx);
+        }
+        let recovered = self.breakers[idx].lock().unwrap().state() == CircuitState::HalfOpen;
+        if recovered {
+            info!("{} recovered, returning traffic to it", link.name());
+        }
+        self.update_breaker(idx, CircuitBreaker::record_success).await;
     }
 
-    async fn execute_fallback_with_handoff(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+    /// Books responses of link `idx`
+    fn recorder(&self, idx: usize) -> ResponseRecorder {
+        ResponseRecorder {
+            metrics: self.metrics.clone(),
+            cache_tracker: self.cache_tracker.clone(),
+            budget: self.budget.clone().map(|budget| (budget, self.chain[idx].budget_key())),
+            session_context: self
+                .config
+                .preserve_context
+                .then(|| self.session_context.clone()),
+        }
+    }
+
+    async fn set_link_state(&self, idx: usize, link_state: LinkState) {
+        self.state.write().await.links[idx].state = link_state;
+    }
+
+    /// Apply `update` to the breaker of link `idx` and mirror its state
+    async fn update_breaker(&self, idx: usize, update: fn(&mut CircuitBreaker)) {
+        let circuit = {
+            let mut breaker = self.breakers[idx].lock().unwrap();
+            update(&mut breaker);
+            breaker.state()
+        };
+        self.state.write().await.links[idx].circuit = circuit;
+    }
+
+    /// Prefix `request` with a summary of the session so far, for the next
+    /// provider to pick up where `from` left off
+    async fn with_handoff(&self, request: ApiRequest, from: &str) -> ApiRequest {
         // Build handoff context with compressed session summary
         let session_history = self.session_context.read().await.clone();
 
@@ -216,7 +546,8 @@ impl<F: FallbackProvider> Orchestrator<F> {
             // Compress to ~2000 chars to avoid blowing up the fallback budget
             let summary = smart_truncate(&combined, 2000);
             format!(
-                "\n\n[Session handoff from Venice.ai - {} previous responses]\nSummary:\n{}\n",
+                "\n\n[Session handoff from {} - {} previous responses]\nSummary:\n{}\n",
+                from,
                 session_history.len(),
                 summary
             )
@@ -231,17 +562,7 @@ impl<F: FallbackProvider> Orchestrator<F> {
         }
 
         // Optimize the request before sending to fallback
-        let fallback_config = OptimizationConfig {
-            strategies: vec![
-                StrategyType::StripWhitespace,
-                StrategyType::RemoveComments,
-                StrategyType::TruncateContext,
-                StrategyType::Deduplicate,
-            ],
-            ..OptimizationConfig::default()
-        };
-        let optimizer = PromptOptimizer::new(fallback_config, None);
-        if let Ok((optimized, stats)) = optimizer.optimize(handoff_request.clone()).await {
+        if let Ok((optimized, stats)) = self.optimizer.optimize(handoff_request.clone()).await {
             if stats.tokens_saved > 0 {
                 info!(
                     "Fallback handoff optimized: {} -> {} tokens (saved {})",
@@ -251,18 +572,27 @@ impl<F: FallbackProvider> Orchestrator<F> {
             }
         }
 
-        self.execute_fallback(handoff_request).await
+        handoff_request
     }
 
-    /// Force switch to fallback provider
+    /// Open the circuit of the first link still in the chain
     pub async fn force_fallback(&self) {
-        *self.state.write().await = OrchestratorState::UsingFallback;
+        let idx = {
+            let state = self.state.read().await;
+            state.links.iter().position(|l| l.circuit != CircuitState::Open)
+        };
+        if let Some(idx) = idx {
+            self.set_link_state(idx, LinkState::Exhausted).await;
+            self.update_breaker(idx, CircuitBreaker::trip).await;
+        }
     }
 
-    /// Reset to Venice (if credits become available again)
-    pub async fn reset_to_venice(&self) {
-        if !self.venice.is_exhausted() {
-            *self.state.write().await = OrchestratorState::UsingVenice;
+    /// Close every circuit (if credits become available again);
+    /// unavailable providers are skipped when next tried
+    pub async fn reset(&self) {
+        for idx in 0..self.chain.len() {
+            self.set_link_state(idx, LinkState::Ready).await;
+            self.update_breaker(idx, CircuitBreaker::close).await;
         }
     }
 
@@ -277,6 +607,156 @@ impl<F: FallbackProvider> Orchestrator<F> {
     }
 }
 
+#[async_trait]
+impl FallbackProvider for VeniceProvider {
+    async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+        self.send_request(request).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+        self.send_streaming(request).await
+    }
+
+    async fn is_available(&self) -> bool {
+        !self.is_exhausted()
+    }
+
+    fn name(&self) -> &str {
+        "Venice"
+    }
+
+    /// The larger of the USD and Diem balances
+    async fn balance(&self) -> Option<f64> {
+        let balance = self.get_balance().await;
+        balance.last_updated.map(|_| balance.balance_usd.max(balance.balance_diem))
+    }
+
+    /// Re-check the balance, which also clears exhaustion once topped up
+    async fn probe(&self) -> bool {
+        self.fetch_balance().await.is_ok() && !self.is_exhausted()
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(VeniceProvider::model(self))
+    }
+}
+
+/// Link backed by any [`ApiProvider`], e.g. an [`ApiAgent`](crate::api::ApiAgent)
+/// pointed at DeepSeek
+pub struct ProviderFallback<P: ApiProvider> {
+    name: String,
+    provider: P,
+}
+
+impl<P: ApiProvider> ProviderFallback<P> {
+    pub fn new(name: impl Into<String>, provider: P) -> Self {
+        Self {
+            name: name.into(),
+            provider,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: ApiProvider> FallbackProvider for ProviderFallback<P> {
+    async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+        self.provider.send_request(request).await
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Fallback backed by any provider the [`ProviderRegistry`] can build
+pub struct RegistryFallback {
+    name: String,
+    provider: Arc<dyn ChatProvider>,
+    model: Option<String>,
+}
+
+impl RegistryFallback {
+    pub fn new(name: impl Into<String>, provider: impl Into<Arc<dyn ChatProvider>>) -> Self {
+        Self {
+            name: name.into(),
+            provider: provider.into(),
+            model: None,
+        }
+    }
+
+    /// Price streamed responses as coming from `model`
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// The provider, if it is Venice, for its balance
+    fn venice(&self) -> Option<&VeniceProvider> {
+        self.provider.as_any().downcast_ref::<VeniceProvider>()
+    }
+
+    /// Build the provider called `name` as configured in `config`
+    pub fn from_config(
+        registry: &ProviderRegistry,
+        config: &Config,
+        name: &str,
+    ) -> Result<Self, ApiError> {
+        let (spec, provider) = registry.build_from_config(config, name)?;
+        let fallback = Self::new(name, provider);
+        Ok(match spec.model {
+            Some(model) => fallback.with_model(model),
+            None => fallback,
+        })
+    }
+}
+
+#[async_trait]
+impl FallbackProvider for RegistryFallback {
+    async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
+        self.provider.send_request(request).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+        self.provider.send_streaming(request).await
+    }
+
+    async fn is_available(&self) -> bool {
+        self.venice().map_or(true, |venice| !venice.is_exhausted())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn balance(&self) -> Option<f64> {
+        match self.venice() {
+            Some(venice) => FallbackProvider::balance(venice).await,
+            None => None,
+        }
+    }
+
+    async fn probe(&self) -> bool {
+        match self.venice() {
+            Some(venice) => FallbackProvider::probe(venice).await,
+            None => true,
+        }
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+}
+
 /// Claude Code fallback provider implementation
 pub struct ClaudeCodeFallback {
     /// Command to invoke Claude Code CLI
@@ -351,6 +831,8 @@ impl FallbackProvider for ClaudeCodeFallback {
                 model: "claude-code-cli".to_string(),
                 truncated: false,
                 stop_reason: None,
+                stop_sequence: None,
+                tool_calls: Vec::new(),
             })
         } else {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -376,103 +858,345 @@ impl FallbackProvider for ClaudeCodeFallback {
 
 /// API-based Claude fallback (for when CLI isn't available)
 pub struct ClaudeApiFallback {
-    client: reqwest::Client,
     api_key: String,
     model: String,
+    /// Anthropic client; retries happen in the chain
+    agent: ApiAgent,
 }
 
 impl ClaudeApiFallback {
     pub fn new(api_key: String) -> Self {
+        let model = "claude-sonnet-4-20250514".to_string();
         Self {
-            client: reqwest::Client::new(),
+            agent: Self::agent(&api_key, &model),
             api_key,
-            model: "claude-sonnet-4-20250514".to_string(),
+            model,
         }
     }
 
     pub fn with_model(mut self, model: String) -> Self {
+        self.agent = Self::agent(&self.api_key, &model);
         self.model = model;
         self
     }
+
+    fn agent(api_key: &str, model: &str) -> ApiAgent {
+        ApiAgent::new(ApiConfig {
+            provider: ProviderType::Claude,
+            api_key: api_key.to_string(),
+            base_url: None,
+            model: model.to_string(),
+            max_tokens: Some(4096),
+            temperature: None,
+            num_ctx: None,
+        })
+        .with_retry_policy(RetryPolicy::none())
+    }
 }
 
 #[async_trait]
 impl FallbackProvider for ClaudeApiFallback {
     async fn execute(&self, request: ApiRequest) -> Result<ApiResponse, ApiError> {
-        let mut messages = Vec::new();
+        self.agent.send_request(request).await
+    }
 
-        // Add context
-        if !request.context.is_empty() {
-            let context_text: String = request
-                .context
-                .iter()
-                .map(|c| format!("### {}\n```\n{}\n```", c.name, c.content))
-                .collect::<Vec<_>>()
-                .join("\n\n");
-
-            messages.push(serde_json::json!({
-                "role": "user",
-                "content": format!("Context:\n{}", context_text)
-            }));
-        }
-
-        // Add task
-        messages.push(serde_json::json!({
-            "role": "user",
-            "content": request.task
-        }));
+    async fn execute_streaming(
+        &self,
+        request: ApiRequest,
+    ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+        self.agent.send_streaming(request).await
+    }
 
-        let mut body = serde_json::json!({
-            "model": self.model,
-            "messages": messages,
-            "max_tokens": 4096,
-        });
+    async fn is_available(&self) -> bool {
+        !self.api_key.is_empty()
+    }
 
-        if let Some(system) = &request.system {
-            body["system"] = serde_json::json!(system);
-        }
+    fn name(&self) -> &str {
+        "Claude API"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
 
-        let response = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
 
-        if response.status().is_success() {
-            let json: serde_json::Value = response.json().await?;
+    type Failure = Option<fn() -> ApiError>;
 
-            let content = json["content"][0]["text"]
-                .as_str()
-                .unwrap_or("")
-                .to_string();
+    /// Link that fails with its current failure or answers with its name
+    #[derive(Clone)]
+    struct Scripted {
+        name: &'static str,
+        failure: Arc<Mutex<Failure>>,
+        balance: Option<f64>,
+        calls: Arc<AtomicU32>,
+    }
 
-            let usage = crate::api::TokenUsage::new(
-                json["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32,
-                json["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32,
-            );
+    impl Scripted {
+        fn new(name: &'static str, failure: Failure) -> Self {
+            Self {
+                name,
+                failure: Arc::new(Mutex::new(failure)),
+                balance: None,
+                calls: Arc::new(AtomicU32::new(0)),
+            }
+        }
 
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        fn set_failure(&self, failure: Failure) {
+            *self.failure.lock().unwrap() = failure;
+        }
+    }
+
+    #[async_trait]
+    impl FallbackProvider for Scripted {
+        async fn execute(&self, _request: ApiRequest) -> Result<ApiResponse, ApiError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(failure) = *self.failure.lock().unwrap() {
+                return Err(failure());
+            }
             Ok(ApiResponse {
-                content,
-                usage,
-                model: json["model"].as_str().unwrap_or(&self.model).to_string(),
-                truncated: json["stop_reason"].as_str() == Some("max_tokens"),
+                content: self.name.to_string(),
+                usage: TokenUsage {
+                    estimated_cost_usd: Some(1.0),
+                    ..TokenUsage::default()
+                },
+                model: self.name.to_string(),
+                truncated: false,
                 stop_reason: None,
+                stop_sequence: None,
+                tool_calls: Vec::new(),
             })
-        } else {
-            let error = response.text().await.unwrap_or_default();
-            Err(ApiError::Provider(format!("Claude API error: {}", error)))
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn balance(&self) -> Option<f64> {
+            self.balance
         }
     }
 
-    async fn is_available(&self) -> bool {
-        !self.api_key.is_empty()
+    fn chain(links: Vec<FallbackLink>) -> Orchestrator {
+        Orchestrator::with_chain(OrchestratorConfig::default(), links, MetricsTracker::new())
     }
 
-    fn name(&self) -> &str {
-        "Claude API"
+    async fn send(orchestrator: &Orchestrator) -> Result<ApiResponse, ApiError> {
+        orchestrator.execute(ApiRequest::new("task".to_string())).await
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_through_by_policy() {
+        let venice = Scripted::new("venice", Some(|| ApiError::QuotaExceeded("none".to_string())));
+        let deepseek =
+            Scripted::new("deepseek", Some(|| ApiError::RateLimited { retry_after_secs: None }));
+        let mut claude = Scripted::new("claude", None);
+        claude.balance = Some(0.05);
+        let orchestrator = chain(vec![
+            FallbackLink::new(venice.clone()),
+            FallbackLink::new(deepseek.clone()),
+            FallbackLink::new(claude.clone())
+                .with_policy(LinkPolicy::default().with_min_balance(0.10)),
+        ]);
+
+        assert_eq!(send(&orchestrator).await.unwrap().content, "claude");
+        let state = orchestrator.state().await;
+        let states: Vec<_> = state.links.iter().map(|link| link.state).collect();
+        assert_eq!(states, vec![LinkState::Exhausted, LinkState::Ready, LinkState::Low]);
+        let circuits: Vec<_> = state.links.iter().map(|link| link.circuit).collect();
+        assert_eq!(circuits, vec![CircuitState::Open, CircuitState::Closed, CircuitState::Closed]);
+        assert_eq!(state.active_link().unwrap().balance, Some(0.05));
+        assert!(state.links[1].last_error.is_some());
+
+        // Open circuits are skipped; a single rate limit leaves one closed,
+        // and the low link still answers once it has failed
+        assert_eq!(send(&orchestrator).await.unwrap().content, "claude");
+        assert_eq!((venice.calls(), deepseek.calls(), claude.calls()), (1, 2, 2));
+
+        // Ahead of the low link, a healthy one takes the traffic
+        deepseek.set_failure(None);
+        assert_eq!(send(&orchestrator).await.unwrap().content, "deepseek");
+        assert_eq!(claude.calls(), 2);
+
+        // Errors outside the policy are returned instead of falling through
+        let strict = Scripted::new("strict", Some(|| ApiError::Auth("bad key".to_string())));
+        let never = Scripted::new("never", None);
+        let orchestrator =
+            chain(vec![FallbackLink::new(strict), FallbackLink::new(never.clone())]);
+        assert!(matches!(send(&orchestrator).await, Err(ApiError::Auth(_))));
+        assert_eq!(never.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_traffic_returns_to_recovered_primary() {
+        let out_of_credit = || ApiError::QuotaExceeded("none".to_string());
+        let primary = Scripted::new("primary", Some(out_of_credit));
+        let backup = Scripted::new("backup", None);
+        let no_cooldown = BreakerPolicy::default().with_cooldown(Duration::ZERO);
+        let orchestrator = chain(vec![
+            FallbackLink::new(primary.clone())
+                .with_policy(LinkPolicy::default().with_breaker(no_cooldown)),
+            FallbackLink::new(backup),
+        ]);
+
+        assert_eq!(send(&orchestrator).await.unwrap().content, "backup");
+        // Probed and tried again, still out of credit
+        assert_eq!(send(&orchestrator).await.unwrap().content, "backup");
+        assert_eq!(primary.calls(), 2);
+
+        primary.set_failure(None);
+        assert_eq!(send(&orchestrator).await.unwrap().content, "primary");
+        let state = orchestrator.state().await;
+        assert_eq!(state.active, Some(0));
+        assert_eq!(state.links[0].circuit, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_falls_back_and_records_spend() {
+        use crate::config::{BudgetSettings, SpendingLimits};
+        use crate::metrics::BudgetPeriod;
+        use std::collections::BTreeMap;
+
+        let busy = || ApiError::RateLimited { retry_after_secs: None };
+        let metrics = MetricsTracker::new();
+        let budget = Arc::new(Budget::in_memory(BudgetSettings {
+            providers: BTreeMap::from([(
+                "claude".to_string(),
+                SpendingLimits {
+                    daily: Some(1.5),
+                    ..SpendingLimits::default()
+                },
+            )]),
+            downgrade_to: Some("ollama".to_string()),
+            ..BudgetSettings::default()
+        }));
+        let orchestrator = Orchestrator::with_chain(
+            OrchestratorConfig::default(),
+            vec![
+                FallbackLink::new(Scripted::new("venice", Some(busy))),
+                FallbackLink::new(Scripted::new("claude", None)),
+                FallbackLink::new(Scripted::new("ollama", None)),
+            ],
+            metrics.clone(),
+        )
+        .with_budget(budget.clone());
+
+        let stream = || async {
+            let mut rx = orchestrator
+                .execute_streaming(ApiRequest::new("task".to_string()))
+                .await
+                .unwrap();
+            let mut content = String::new();
+            // The stream closes once the response is recorded
+            while let Some(chunk) = rx.recv().await {
+                if let StreamChunk::TextDelta(text) = chunk {
+                    content.push_str(&text);
+                }
+            }
+            content
+        };
+
+        // The downgrade link is held back while Claude is within its cap
+        assert_eq!(stream().await, "claude");
+        assert_eq!(stream().await, "claude");
+        assert_eq!(budget.spent(Some("claude"), BudgetPeriod::Daily), 2.0);
+        assert_eq!(stream().await, "ollama");
+        assert_eq!(metrics.get_metrics().request_count, 3);
+        assert_eq!(orchestrator.state().await.active, Some(2));
+    }
+
+    /// Link streaming an OpenAI-format body, failing after it if `fail`
+    struct SseLink {
+        body: String,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl FallbackProvider for SseLink {
+        async fn execute(&self, _request: ApiRequest) -> Result<ApiResponse, ApiError> {
+            unreachable!("only streamed")
+        }
+
+        async fn execute_streaming(
+            &self,
+            _request: ApiRequest,
+        ) -> Result<mpsc::Receiver<StreamChunk>, ApiError> {
+            let mut parts = vec![Ok(self.body.clone())];
+            if self.fail {
+                parts.push(Err(std::io::Error::other("connection reset")));
+            }
+            Ok(crate::api::streaming::spawn_stream_reader(
+                futures_util::stream::iter(parts),
+                crate::api::SseFormat::OpenAI,
+            ))
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn name(&self) -> &str {
+            "venice"
+        }
+
+        fn model(&self) -> Option<&str> {
+            Some("llama-3.3-70b")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streamed_usage_is_charged() {
+        use crate::metrics::BudgetPeriod;
+
+        const TEXT: &str = "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n";
+        const STOP: &str = "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n";
+        const USAGE: &str = concat!(
+            "data: {\"choices\":[],",
+            "\"usage\":{\"prompt_tokens\":100000,\"completion_tokens\":20000}}\n\n"
+        );
+        let path = std::env::temp_dir().join(format!("stream-spend-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let budget =
+            Arc::new(Budget::load(crate::config::BudgetSettings::default(), path.clone()));
+        let metrics = MetricsTracker::new();
+
+        let complete = SseLink {
+            body: [TEXT, STOP, USAGE, "data: [DONE]\n\n"].concat(),
+            fail: false,
+        };
+        let broken = SseLink {
+            body: [TEXT, USAGE].concat(),
+            fail: true,
+        };
+        for link in [complete, broken] {
+            let orchestrator = Orchestrator::with_chain(
+                OrchestratorConfig::default(),
+                vec![FallbackLink::new(link)],
+                metrics.clone(),
+            )
+            .with_budget(budget.clone());
+            let request = ApiRequest::new("task".to_string());
+            let mut rx = orchestrator.execute_streaming(request).await.unwrap();
+            while rx.recv().await.is_some() {}
+        }
+
+        // Both streams are charged, the broken one for the usage it reported
+        assert!(budget.spent(Some("venice"), BudgetPeriod::Daily) > 0.0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let totals = metrics.get_metrics();
+        assert_eq!((totals.request_count, totals.total_input_tokens), (2, 200_000));
+        let _ = std::fs::remove_file(&path);
     }
 }
@@ -0,0 +1,228 @@
+//! Routing tasks to the cheapest capable model
+//!
+//! Each request is scored as trivial, routine or hard, either by the local
+//! LLM or by a heuristic over the task text, context size and errors, and
+//! sent to the model the routing table lists for that complexity.
+
+use crate::agents::{LocalTask, LocalTaskResult, PreprocessingAgent, TaskComplexity};
+use crate::api::{ApiRequest, ContextType};
+use crate::config::{RouteTarget, RoutingSettings};
+use crate::metrics::MetricsTracker;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+/// Context above this many estimated tokens makes a task hard
+const HARD_CONTEXT_TOKENS: usize = 16_000;
+
+/// Trivial tasks come with at most this much context
+const TRIVIAL_CONTEXT_TOKENS: usize = 2_000;
+
+/// Wording of small mechanical edits. Hints match whole words; one ending
+/// in `*` also matches the words it starts.
+const TRIVIAL_HINTS: &[&str] = &[
+    "renam*", "typo", "typos", "spelling", "reformat*", "format this", "sort imports",
+    "add a comment", "docstring*", "reword*",
+];
+
+/// Wording of tasks that need reasoning across code
+const HARD_HINTS: &[&str] = &[
+    "architect*", "design", "redesign", "race condition*", "deadlock*", "concurren*",
+    "security", "vulnerab*", "migrat*", "performance", "memory leak*", "root cause", "why does",
+    "why is",
+];
+
+/// Markers of failures pasted into the task, rather than talk of errors
+const ERROR_HINTS: &[&str] = &[
+    "error:", "error[", "panicked", "traceback", "exception:", "failed with", "stack trace",
+];
+
+/// Whether `task` mentions `hint` as whole words, or as the start of a word
+/// for a hint ending in `*`
+fn mentions(task: &str, hint: &str) -> bool {
+    let (hint, stem) = match hint.strip_suffix('*') {
+        Some(stem) => (stem, true),
+        None => (hint, false),
+    };
+    let boundary = |c: Option<char>| !c.is_some_and(char::is_alphanumeric);
+    task.match_indices(hint).any(|(at, _)| {
+        let after = task[at + hint.len()..].chars().next();
+        boundary(task[..at].chars().next_back())
+            && (stem || boundary(after) || !hint.ends_with(char::is_alphanumeric))
+    })
+}
+
+/// A complexity score and what it was based on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub complexity: TaskComplexity,
+    pub reason: String,
+}
+
+/// Score a request from its task text, context size and errors
+pub fn classify_heuristic(request: &ApiRequest) -> Classification {
+    let task = request.task.to_lowercase();
+    let context_chars: usize = request.context.iter().map(|c| c.content.len()).sum::<usize>()
+        + request.messages.iter().map(|m| m.content.len()).sum::<usize>();
+    let context_tokens = context_chars / 4;
+    let has_errors = request.context.iter().any(|c| matches!(c.item_type, ContextType::Error))
+        || ERROR_HINTS.iter().any(|hint| mentions(&task, hint));
+
+    let classification = |complexity, reason: String| Classification { complexity, reason };
+    if let Some(hint) = HARD_HINTS.iter().find(|hint| mentions(&task, hint)) {
+        let hint = hint.trim_end_matches('*');
+        return classification(TaskComplexity::Hard, format!("task mentions \"{}\"", hint));
+    }
+    if context_tokens > HARD_CONTEXT_TOKENS {
+        return classification(
+            TaskComplexity::Hard,
+            format!("~{} tokens of context", context_tokens),
+        );
+    }
+    if has_errors {
+        return classification(TaskComplexity::Routine, "errors present".to_string());
+    }
+    match TRIVIAL_HINTS.iter().find(|hint| mentions(&task, hint)) {
+        Some(hint) if context_tokens <= TRIVIAL_CONTEXT_TOKENS => classification(
+            TaskComplexity::Trivial,
+            format!("task mentions \"{}\"", hint.trim_end_matches('*')),
+        ),
+        _ => classification(TaskComplexity::Routine, "default".to_string()),
+    }
+}
+
+/// Where a request was routed, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteDecision {
+    pub classification: Classification,
+    pub target: RouteTarget,
+}
+
+/// Picks a model per request from a [`RoutingSettings`] table
+pub struct ComplexityRouter {
+    table: RoutingSettings,
+    local_agent: Option<Arc<dyn PreprocessingAgent>>,
+    metrics: Option<MetricsTracker>,
+}
+
+impl ComplexityRouter {
+    pub fn new(table: RoutingSettings) -> Self {
+        Self {
+            table,
+            local_agent: None,
+            metrics: None,
+        }
+    }
+
+    /// Classify with the local LLM, falling back to the heuristic
+    pub fn with_local_agent(mut self, agent: Arc<dyn PreprocessingAgent>) -> Self {
+        self.local_agent = Some(agent);
+        self
+    }
+
+    /// Record each decision in `metrics`
+    pub fn with_metrics(mut self, metrics: MetricsTracker) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn target(&self, complexity: TaskComplexity) -> &RouteTarget {
+        match complexity {
+            TaskComplexity::Trivial => &self.table.trivial,
+            TaskComplexity::Routine => &self.table.routine,
+            TaskComplexity::Hard => &self.table.hard,
+        }
+    }
+
+    /// Classify `request` and look up its route
+    pub async fn route(&self, request: &ApiRequest) -> RouteDecision {
+        let classification = match self.classify_locally(request).await {
+            Some(classification) => classification,
+            None => classify_heuristic(request),
+        };
+        let target = self.target(classification.complexity).clone();
+        info!(
+            "Routing {} task ({}) to {}",
+            classification.complexity.as_str(),
+            classification.reason,
+            target
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.record_route(classification.complexity.as_str(), &target.to_string());
+        }
+        RouteDecision {
+            classification,
+            target,
+        }
+    }
+
+    async fn classify_locally(&self, request: &ApiRequest) -> Option<Classification> {
+        let agent = self.local_agent.as_ref()?;
+        if !agent.is_available().await {
+            return None;
+        }
+        let heuristic = classify_heuristic(request);
+        let task = LocalTask::ClassifyComplexity {
+            task: request.task.clone(),
+            context_summary: heuristic.reason,
+        };
+        match agent.process(task).await {
+            Ok(LocalTaskResult::Complexity(complexity)) => Some(Classification {
+                complexity,
+                reason: "local LLM".to_string(),
+            }),
+            Ok(_) => None,
+            Err(e) => {
+                debug!("Local complexity classification failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ContextItem;
+
+    #[tokio::test]
+    async fn test_tasks_route_by_complexity() {
+        let complexity = |request: &ApiRequest| classify_heuristic(request).complexity;
+        let rename = ApiRequest::new("Rename this variable to `count`".to_string());
+        assert_eq!(complexity(&rename), TaskComplexity::Trivial);
+        let feature = ApiRequest::new("Add a --verbose flag to the CLI".to_string());
+        assert_eq!(complexity(&feature), TaskComplexity::Routine);
+        let race = ApiRequest::new("Find the race condition in the worker pool".to_string());
+        assert_eq!(complexity(&race), TaskComplexity::Hard);
+
+        // Hints match words, not any text that contains them
+        let designate = ApiRequest::new("Designate the first column as the key".to_string());
+        assert_eq!(complexity(&designate), TaskComplexity::Routine);
+        let typography = ApiRequest::new("Fix the typography in the header".to_string());
+        assert_eq!(complexity(&typography), TaskComplexity::Routine);
+        let handling = ApiRequest::new("Rename `e` in the error handling code".to_string());
+        assert_eq!(complexity(&handling), TaskComplexity::Trivial);
+        let pasted = ApiRequest::new("Rename it, build says error: unused `e`".to_string());
+        assert_eq!(complexity(&pasted), TaskComplexity::Routine);
+
+        // A trivial edit next to a compiler error is no longer trivial
+        let failing = rename.clone().with_context(vec![ContextItem {
+            name: "cargo build".to_string(),
+            content: "error[E0425]: cannot find value `cnt`".to_string(),
+            item_type: ContextType::Error,
+            relevance: None,
+            cache_control: None,
+            is_static: false,
+        }]);
+        assert_eq!(complexity(&failing), TaskComplexity::Routine);
+
+        let metrics = MetricsTracker::new();
+        let router =
+            ComplexityRouter::new(RoutingSettings::default()).with_metrics(metrics.clone());
+        let decision = router.route(&rename).await;
+        assert_eq!(decision.target, RouteTarget::new("venice", "venice-small"));
+        assert_eq!(router.route(&race).await.target.provider, "claude");
+        assert_eq!(metrics.get_metrics().routes.get("trivial -> venice/venice-small"), Some(&1));
+        assert_eq!(TaskComplexity::parse("Hard."), Some(TaskComplexity::Hard));
+        assert_eq!(TaskComplexity::parse("Nontrivial"), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Memoized token counts
+//!
+//! The optimizer re-counts a request after every strategy, and most context
+//! items come through a strategy unchanged, so counts are remembered by a
+//! hash of the text.
+
+use super::Tokenizer;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// Number of counts remembered before the memo is cleared
+pub const DEFAULT_MEMO_CAPACITY: usize = 16_384;
+
+/// Texts shorter than this are counted directly; hashing them costs about
+/// as much as encoding them
+const MIN_MEMO_BYTES: usize = 64;
+
+/// Wraps a tokenizer and remembers counts per content hash
+pub struct CachedTokenizer {
+    inner: Arc<dyn Tokenizer>,
+    capacity: usize,
+    memo: Mutex<HashMap<u64, usize>>,
+}
+
+impl CachedTokenizer {
+    pub fn new(inner: Arc<dyn Tokenizer>) -> Self {
+        Self {
+            inner,
+            capacity: DEFAULT_MEMO_CAPACITY,
+            memo: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Number of remembered counts
+    pub fn memoized(&self) -> usize {
+        self.memo.lock().map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+impl Tokenizer for CachedTokenizer {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn count(&self, text: &str) -> usize {
+        if text.len() < MIN_MEMO_BYTES || self.capacity == 0 {
+            return self.inner.count(text);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(count) = self.memo.lock().ok().and_then(|m| m.get(&key).copied()) {
+            return count;
+        }
+
+        // Encode without holding the lock so other threads are not blocked
+        let count = self.inner.count(text);
+        if let Ok(mut memo) = self.memo.lock() {
+            if memo.len() >= self.capacity {
+                memo.clear();
+            }
+            memo.insert(key, count);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting(AtomicUsize);
+
+    impl Tokenizer for Counting {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn count(&self, text: &str) -> usize {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            text.len() / 4
+        }
+    }
+
+    #[test]
+    fn test_counts_are_memoized_by_content() {
+        let inner = Arc::new(Counting(AtomicUsize::new(0)));
+        let cached = CachedTokenizer::new(inner.clone()).with_capacity(2);
+        let a = "a".repeat(100);
+        let b = "b".repeat(200);
+
+        assert_eq!(cached.count(&a), 25);
+        assert_eq!(cached.count(&a.clone()), 25);
+        assert_eq!(cached.count(&b), 50);
+        assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+        assert_eq!(cached.name(), "counting");
+
+        // Short texts bypass the memo
+        cached.count("short");
+        cached.count("short");
+        assert_eq!(inner.0.load(Ordering::SeqCst), 4);
+
+        // A full memo is cleared rather than growing without bound
+        cached.count(&"c".repeat(100));
+        assert_eq!(cached.memoized(), 1);
+    }
+}
@@ -0,0 +1,234 @@
+//! HuggingFace `tokenizer.json` loading
+//!
+//! Llama 3, Qwen 2 and DeepSeek ship byte-level BPE tokenizers, which are
+//! the same algorithm tiktoken implements. The vocabulary and merges are
+//! converted into a tiktoken [`CoreBPE`]: token strings are mapped back to
+//! raw bytes and ranked by merge priority. SentencePiece-style vocabularies
+//! (`▁` for spaces, `<0xNN>` byte fallback) are converted the same way.
+
+use super::Tokenizer;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tiktoken_rs::CoreBPE;
+
+/// Pre-tokenizer split used when the file does not define one (GPT-4's)
+const DEFAULT_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// A BPE tokenizer read from a HuggingFace `tokenizer.json`
+pub struct HuggingFaceTokenizer {
+    name: String,
+    bpe: CoreBPE,
+}
+
+impl HuggingFaceTokenizer {
+    /// Load `path`; the tokenizer is named after the file stem
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "huggingface".to_string());
+        Self::from_json(name, &json)
+    }
+
+    pub fn from_json(name: impl Into<String>, json: &Value) -> anyhow::Result<Self> {
+        let model = &json["model"];
+        if let Some(kind) = model["type"].as_str() {
+            anyhow::ensure!(kind == "BPE", "unsupported tokenizer model type {}", kind);
+        }
+        let vocab = model["vocab"]
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("tokenizer.json has no model.vocab"))?;
+
+        let byte_level = contains_type(json, "ByteLevel");
+        let unicode_to_byte: HashMap<char, u8> =
+            byte_to_unicode().into_iter().map(|(b, c)| (c, b)).collect();
+        let to_bytes = |token: &str| -> Vec<u8> {
+            if byte_level {
+                token
+                    .chars()
+                    .flat_map(|c| match unicode_to_byte.get(&c) {
+                        Some(b) => vec![*b],
+                        None => c.to_string().into_bytes(),
+                    })
+                    .collect()
+            } else if let Some(byte) = parse_byte_fallback(token) {
+                vec![byte]
+            } else {
+                token.replace('\u{2581}', " ").into_bytes()
+            }
+        };
+
+        // Rank = merge priority: unmerged tokens first (by id), then every
+        // merge result in the order the merges are listed
+        let mut base: Vec<(&str, u64)> = vocab
+            .iter()
+            .map(|(token, id)| (token.as_str(), id.as_u64().unwrap_or(u64::MAX)))
+            .collect();
+        let merged: Vec<String> = model["merges"]
+            .as_array()
+            .map(|merges| merges.iter().filter_map(merge_result).collect())
+            .unwrap_or_default();
+        let merged_set: HashSet<&str> = merged.iter().map(String::as_str).collect();
+        base.retain(|(token, _)| !merged_set.contains(token));
+        base.sort_by_key(|(_, id)| *id);
+
+        let mut encoder: HashMap<Vec<u8>, usize> = HashMap::new();
+        let ordered = base
+            .into_iter()
+            .map(|(token, _)| token)
+            .chain(merged.iter().map(String::as_str));
+        for token in ordered {
+            let rank = encoder.len();
+            encoder.entry(to_bytes(token)).or_insert(rank);
+        }
+        // Every single byte must be encodable
+        for byte in 0..=255u8 {
+            let rank = encoder.len();
+            encoder.entry(vec![byte]).or_insert(rank);
+        }
+
+        let mut special_tokens = HashMap::new();
+        for added in json["added_tokens"].as_array().into_iter().flatten() {
+            if added["special"].as_bool() == Some(true) {
+                if let Some(content) = added["content"].as_str() {
+                    let rank = encoder.len() + special_tokens.len();
+                    special_tokens.insert(content.to_string(), rank);
+                }
+            }
+        }
+
+        let pattern = split_pattern(json).unwrap_or(DEFAULT_PATTERN);
+        // `collect` converts to the hasher tiktoken's maps use
+        let bpe = CoreBPE::new(
+            encoder.into_iter().collect(),
+            special_tokens.into_iter().collect(),
+            pattern,
+        )?;
+        Ok(Self {
+            name: name.into(),
+            bpe,
+        })
+    }
+}
+
+impl Tokenizer for HuggingFaceTokenizer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// The token a merge rule produces; merges are "a b" strings or ["a", "b"]
+fn merge_result(merge: &Value) -> Option<String> {
+    match merge {
+        Value::String(s) => s.split_once(' ').map(|(a, b)| format!("{}{}", a, b)),
+        Value::Array(pair) => {
+            Some(format!("{}{}", pair.first()?.as_str()?, pair.get(1)?.as_str()?))
+        }
+        _ => None,
+    }
+}
+
+/// `<0x0A>` → 0x0A
+fn parse_byte_fallback(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Whether any component of the pipeline has `"type": kind`
+fn contains_type(value: &Value, kind: &str) -> bool {
+    match value {
+        Value::Object(map) => {
+            map.get("type").and_then(Value::as_str) == Some(kind)
+                || map.values().any(|v| contains_type(v, kind))
+        }
+        Value::Array(items) => items.iter().any(|v| contains_type(v, kind)),
+        _ => false,
+    }
+}
+
+/// Regex of the first `Split` pre-tokenizer
+fn split_pattern(json: &Value) -> Option<&str> {
+    fn find(value: &Value) -> Option<&str> {
+        match value {
+            Value::Object(map) => {
+                if map.get("type").and_then(Value::as_str) == Some("Split") {
+                    if let Some(regex) = map["pattern"]["Regex"].as_str() {
+                        return Some(regex);
+                    }
+                }
+                map.values().find_map(find)
+            }
+            Value::Array(items) => items.iter().find_map(find),
+            _ => None,
+        }
+    }
+    find(&json["pre_tokenizer"])
+}
+
+/// GPT-2's reversible byte → printable character mapping used by
+/// byte-level BPE vocabularies (e.g. space is stored as `Ġ`)
+fn byte_to_unicode() -> Vec<(u8, char)> {
+    let printable = |b: u8| matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+    let mut shifted = 0u32;
+    (0..=255u8)
+        .map(|b| {
+            if printable(b) {
+                (b, b as char)
+            } else {
+                let c = char::from_u32(256 + shifted).expect("valid code point");
+                shifted += 1;
+                (b, c)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_byte_level_bpe_from_json() {
+        let json = json!({
+            "added_tokens": [{ "id": 100, "content": "<|eot|>", "special": true }],
+            "pre_tokenizer": { "type": "ByteLevel" },
+            "model": {
+                "type": "BPE",
+                "vocab": {
+                    "h": 0, "e": 1, "l": 2, "o": 3, "w": 4, "r": 5, "d": 6, "Ġ": 7,
+                    "he": 8, "ll": 9, "hell": 10, "hello": 11, "Ġw": 12
+                },
+                "merges": ["h e", "l l", "he ll", ["hell", "o"], "Ġ w"]
+            }
+        });
+        let tokenizer = HuggingFaceTokenizer::from_json("tiny", &json).unwrap();
+
+        assert_eq!(tokenizer.count("hello"), 1);
+        // "hello" + "Ġw" "o" "r" "l" "d"
+        assert_eq!(tokenizer.count("hello world"), 6);
+        assert_eq!(tokenizer.count("hello<|eot|>"), 2);
+        // Bytes missing from the vocab still encode
+        assert_eq!(tokenizer.count("\u{e9}"), 2);
+    }
+
+    #[test]
+    fn test_sentencepiece_vocab_without_special_tokens() {
+        let json = json!({
+            "model": {
+                "type": "BPE",
+                "vocab": { "<0x0A>": 0, "a": 1, "b": 2, "\u{2581}": 3, "\u{2581}a": 4 },
+                "merges": ["\u{2581} a"]
+            }
+        });
+        let tokenizer = HuggingFaceTokenizer::from_json("sp", &json).unwrap();
+        assert_eq!(tokenizer.count("b a\n"), 3);
+    }
+}
@@ -0,0 +1,290 @@
+//! Model-specific token counting
+//!
+//! Budgets, cache minimums and cost estimates are only as good as the token
+//! counts behind them, so each model gets the tokenizer closest to the one
+//! its provider bills with:
+//!
+//! - OpenAI models: `o200k_base` (GPT-4o, o-series, GPT-4.1+) or `cl100k_base`
+//! - Llama, Qwen and DeepSeek: their HuggingFace `tokenizer.json`, when one is
+//!   installed in [`tokenizer_dir`]; otherwise `cl100k_base`, which their
+//!   vocabularies largely extend
+//! - Claude: `cl100k_base` scaled by a calibrated ratio, since Anthropic does
+//!   not publish its tokenizer
+//! - Anything else: `cl100k_base`
+//!
+//! Building a BPE takes far longer than encoding with it, so each encoding
+//! is built once per process and shared, and the tokenizers handed out by
+//! [`default_tokenizer`] and [`tokenizer_for_model`] memoize their counts.
+
+mod cached;
+mod huggingface;
+
+pub use cached::{CachedTokenizer, DEFAULT_MEMO_CAPACITY};
+pub use huggingface::HuggingFaceTokenizer;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tiktoken_rs::CoreBPE;
+use tracing::debug;
+
+/// Counts tokens the way a particular model does
+pub trait Tokenizer: Send + Sync {
+    /// Short identifier, e.g. "cl100k_base"
+    fn name(&self) -> &str;
+
+    /// Number of tokens `text` encodes to
+    fn count(&self, text: &str) -> usize;
+}
+
+/// A tiktoken BPE (cl100k_base, o200k_base)
+pub struct TiktokenTokenizer {
+    name: &'static str,
+    bpe: CoreBPE,
+}
+
+impl TiktokenTokenizer {
+    /// GPT-4 / GPT-3.5 encoding
+    pub fn cl100k() -> Option<Self> {
+        tiktoken_rs::cl100k_base().ok().map(|bpe| Self {
+            name: "cl100k_base",
+            bpe,
+        })
+    }
+
+    /// GPT-4o / o-series encoding
+    pub fn o200k() -> Option<Self> {
+        tiktoken_rs::o200k_base().ok().map(|bpe| Self {
+            name: "o200k_base",
+            bpe,
+        })
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Ratio of Claude tokens to cl100k tokens. Claude 3 and later split English
+/// prose and source code into roughly 10-20% more tokens than cl100k.
+pub const CLAUDE_CL100K_RATIO: f64 = 1.15;
+
+/// Approximates Claude's tokenizer by scaling another tokenizer's count
+pub struct ClaudeTokenizer {
+    base: Arc<dyn Tokenizer>,
+    ratio: f64,
+}
+
+impl ClaudeTokenizer {
+    pub fn new(base: Arc<dyn Tokenizer>) -> Self {
+        Self {
+            base,
+            ratio: CLAUDE_CL100K_RATIO,
+        }
+    }
+
+    pub fn with_ratio(mut self, ratio: f64) -> Self {
+        self.ratio = ratio;
+        self
+    }
+
+    /// Fit the ratio to real counts, e.g. `input_tokens` reported by the
+    /// Messages API for known texts
+    pub fn calibrate(mut self, samples: &[(&str, usize)]) -> Self {
+        let base: usize = samples.iter().map(|(text, _)| self.base.count(text)).sum();
+        let actual: usize = samples.iter().map(|(_, count)| count).sum();
+        if base > 0 && actual > 0 {
+            self.ratio = actual as f64 / base as f64;
+        }
+        self
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+}
+
+impl Tokenizer for ClaudeTokenizer {
+    fn name(&self) -> &str {
+        "claude-approx"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        (self.base.count(text) as f64 * self.ratio).ceil() as usize
+    }
+}
+
+/// Fixed characters-per-token estimate, used when no BPE is available
+pub struct HeuristicTokenizer {
+    chars_per_token: f32,
+}
+
+impl HeuristicTokenizer {
+    pub fn new(chars_per_token: f32) -> Self {
+        Self { chars_per_token }
+    }
+}
+
+impl Default for HeuristicTokenizer {
+    fn default() -> Self {
+        Self::new(4.0)
+    }
+}
+
+impl Tokenizer for HeuristicTokenizer {
+    fn name(&self) -> &str {
+        "heuristic"
+    }
+
+    fn count(&self, text: &str) -> usize {
+        (text.len() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+/// Shared, uncached cl100k_base encoder
+fn shared_cl100k() -> Arc<dyn Tokenizer> {
+    static CL100K: OnceLock<Arc<dyn Tokenizer>> = OnceLock::new();
+    CL100K
+        .get_or_init(|| match TiktokenTokenizer::cl100k() {
+            Some(t) => Arc::new(t),
+            None => Arc::new(HeuristicTokenizer::default()),
+        })
+        .clone()
+}
+
+/// Shared, uncached o200k_base encoder
+fn shared_o200k() -> Option<Arc<dyn Tokenizer>> {
+    static O200K: OnceLock<Option<Arc<dyn Tokenizer>>> = OnceLock::new();
+    O200K
+        .get_or_init(|| {
+            TiktokenTokenizer::o200k().map(|t| Arc::new(t) as Arc<dyn Tokenizer>)
+        })
+        .clone()
+}
+
+/// The tokenizer used when the model is unknown (cl100k_base)
+pub fn default_tokenizer() -> Arc<dyn Tokenizer> {
+    static DEFAULT: OnceLock<Arc<dyn Tokenizer>> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| Arc::new(CachedTokenizer::new(shared_cl100k())))
+        .clone()
+}
+
+/// Directory searched for HuggingFace `tokenizer.json` files: the
+/// `TOKEN_OPTIMIZER_TOKENIZERS` env var, else
+/// `~/.config/token-optimizer/tokenizers`
+pub fn tokenizer_dir() -> PathBuf {
+    std::env::var("TOKEN_OPTIMIZER_TOKENIZERS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            dirs::config_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("token-optimizer")
+                .join("tokenizers")
+        })
+}
+
+/// Open-weight model family whose tokenizer.json may be installed
+fn hf_family(model: &str) -> Option<&'static str> {
+    ["llama", "qwen", "deepseek"]
+        .into_iter()
+        .find(|family| model.contains(family))
+}
+
+/// Whether an OpenAI model uses o200k_base
+fn uses_o200k(model: &str) -> bool {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    model.starts_with("gpt-4o")
+        || model.starts_with("gpt-4.1")
+        || model.starts_with("gpt-5")
+        || model.starts_with("chatgpt-4o")
+        || ["o1", "o3", "o4"]
+            .iter()
+            .any(|p| model == *p || model.starts_with(&format!("{}-", p)))
+}
+
+/// The tokenizer that best matches `model`'s billing. Resolved once per
+/// model name; later calls share the same instance and its memo.
+pub fn tokenizer_for_model(model: &str) -> Arc<dyn Tokenizer> {
+    static BY_MODEL: OnceLock<Mutex<HashMap<String, Arc<dyn Tokenizer>>>> = OnceLock::new();
+    let model = model.to_lowercase();
+    let by_model = BY_MODEL.get_or_init(Default::default);
+
+    if let Some(tokenizer) = by_model.lock().ok().and_then(|m| m.get(&model).cloned()) {
+        return tokenizer;
+    }
+    let tokenizer = match resolve_tokenizer(&model, &tokenizer_dir()) {
+        Some(base) => Arc::new(CachedTokenizer::new(base)) as Arc<dyn Tokenizer>,
+        None => default_tokenizer(),
+    };
+    if let Ok(mut by_model) = by_model.lock() {
+        by_model.insert(model, tokenizer.clone());
+    }
+    tokenizer
+}
+
+/// Model-specific encoder, looking for tokenizer.json files in `dir`, or
+/// `None` for the default
+fn resolve_tokenizer(model: &str, dir: &Path) -> Option<Arc<dyn Tokenizer>> {
+    if model.contains("claude") {
+        return Some(Arc::new(ClaudeTokenizer::new(shared_cl100k())));
+    }
+    if uses_o200k(model) {
+        if let Some(t) = shared_o200k() {
+            return Some(t);
+        }
+    }
+    if let Some(family) = hf_family(model) {
+        // An exact per-model file wins over the family file
+        for file in [format!("{}.json", model), format!("{}.json", family)] {
+            let path = dir.join(file);
+            if !path.exists() {
+                continue;
+            }
+            match HuggingFaceTokenizer::from_file(&path) {
+                Ok(t) => return Some(Arc::new(t)),
+                Err(e) => debug!("Ignoring tokenizer {}: {}", path.display(), e),
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_selection() {
+        assert_eq!(tokenizer_for_model("gpt-4o-mini").name(), "o200k_base");
+        assert_eq!(tokenizer_for_model("o3-mini").name(), "o200k_base");
+        assert_eq!(tokenizer_for_model("gpt-4").name(), "cl100k_base");
+        assert_eq!(tokenizer_for_model("claude-sonnet-4-20250514").name(), "claude-approx");
+        // No tokenizer.json installed: fall back to cl100k
+        assert!(resolve_tokenizer("llama-3.3-70b", Path::new("/nonexistent")).is_none());
+    }
+
+    #[test]
+    fn test_tokenizers_are_shared() {
+        assert!(Arc::ptr_eq(&default_tokenizer(), &default_tokenizer()));
+        assert!(Arc::ptr_eq(
+            &tokenizer_for_model("gpt-4o"),
+            &tokenizer_for_model("GPT-4o")
+        ));
+        assert!(Arc::ptr_eq(&tokenizer_for_model("gpt-4"), &default_tokenizer()));
+    }
+
+    #[test]
+    fn test_claude_calibration() {
+        let base: Arc<dyn Tokenizer> = Arc::new(HeuristicTokenizer::new(4.0));
+        let claude = ClaudeTokenizer::new(base).calibrate(&[("abcdefgh", 3), ("abcd", 3)]);
+        assert!((claude.ratio() - 2.0).abs() < 1e-9);
+        assert_eq!(claude.count("abcdefgh"), 4);
+    }
+}
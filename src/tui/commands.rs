@@ -92,7 +92,7 @@ pub fn render_help(
         ("/quit", "Exit interactive mode"),
         ("/clear", "Clear the conversation history"),
         ("/model [name]", "Show or change the current model"),
-        ("/provider [name]", "Show or change the provider"),
+        ("/provider [name]", "Show or change the provider (auto: route by task)"),
         ("/stats", "Show token usage statistics"),
         ("/status", "Show current provider and session status"),
         ("/compact", "Compact conversation history to save tokens"),
@@ -11,13 +11,18 @@ pub mod theme;
 
 use crate::agents::{LocalAgent, LocalAgentConfig, PreprocessingAgent};
 use crate::api::{
-    ApiConfig, ApiAgent, ApiProvider, ApiRequest, ContextItem, ContextType, Message,
-    ProviderType, Role, StreamChunk, StreamingProvider, TokenUsage,
-    VeniceConfig, VeniceProvider,
+    ApiError, ApiRequest, ChatProvider, ContextItem, ContextType, Message, ProviderRegistry,
+    ProviderSpec, Role, StreamChunk, TokenUsage, VeniceProvider,
 };
-use crate::config::Config;
-use crate::metrics::MetricsTracker;
-use crate::optimization::{count_tokens, OptimizationConfig, PromptOptimizer, StrategyType};
+use crate::cache::{cache_strategy_for, CacheTracker, PrefixFingerprint};
+use crate::config::{Config, RouteTarget};
+use crate::metrics::{Budget, BudgetCheck, MetricsTracker};
+use crate::optimization::{OptimizationConfig, PromptOptimizer, StrategyType};
+use crate::orchestrator::{
+    BreakerPolicy, ComplexityRouter, FallbackLink, LinkPolicy, Orchestrator, OrchestratorConfig,
+    RegistryFallback,
+};
+use crate::tokenizer::{tokenizer_for_model, Tokenizer};
 
 use commands::{parse_command, render_help, ContextAction, SlashCommand};
 use prompt::PromptHandler;
@@ -28,35 +33,69 @@ use anyhow::Result;
 use crossterm::style::Stylize;
 use std::sync::Arc;
 
-/// The active provider used for requests
-enum ActiveProvider {
-    Venice(Arc<VeniceProvider>),
-    Api(Arc<ApiAgent>),
+/// The active provider used for requests, built through the provider registry
+struct ActiveProvider {
+    /// Display name (e.g. "Venice.ai", or a `[providers.<name>]` key)
+    name: String,
+    spec: ProviderSpec,
+    inner: Arc<dyn ChatProvider>,
 }
 
 impl ActiveProvider {
+    /// Build the provider described by `spec`, shown under `name`
+    fn build(
+        registry: &ProviderRegistry,
+        name: &str,
+        spec: ProviderSpec,
+    ) -> Result<Self, ApiError> {
+        let inner = registry.build(&spec)?;
+        Ok(Self {
+            name: display_name(name),
+            spec,
+            inner: Arc::from(inner),
+        })
+    }
+
     fn name(&self) -> &str {
-        match self {
-            ActiveProvider::Venice(_) => "Venice.ai",
-            ActiveProvider::Api(agent) => match agent.provider_type() {
-                ProviderType::Claude => "Claude",
-                ProviderType::OpenAI => "OpenAI",
-                ProviderType::Ollama => "Ollama",
-                ProviderType::Custom => "Custom",
-            },
-        }
+        &self.name
+    }
+
+    fn venice(&self) -> Option<&VeniceProvider> {
+        self.inner.as_any().downcast_ref::<VeniceProvider>()
     }
 }
 
+/// Human-readable name for a registry provider name
+fn display_name(name: &str) -> String {
+    match name {
+        "venice" => "Venice.ai",
+        "claude" => "Claude",
+        "openai" => "OpenAI",
+        "ollama" => "Ollama",
+        "gemini" => "Gemini",
+        "custom" => "Custom",
+        other => other,
+    }
+    .to_string()
+}
+
 /// Interactive shell with streaming, markdown, and multi-turn support
 pub struct InteractiveShell {
     config: Config,
+    /// Resolves provider names to providers
+    registry: ProviderRegistry,
     provider: ActiveProvider,
     model: String,
     /// Local LLM agent for preprocessing (Ollama), if available
     local_agent: Option<LocalAgent>,
     /// Fallback provider for when primary fails/exhausted
     fallback: Option<ActiveProvider>,
+    /// Sends requests down the primary -> fallback chain
+    orchestrator: Orchestrator,
+    /// Picks the cheapest model capable of each task
+    router: ComplexityRouter,
+    /// Whether tasks are routed; off once the user picks a provider or model
+    route_tasks: bool,
     renderer: TerminalRenderer,
     prompt_handler: PromptHandler,
     /// Conversation history (user + assistant messages)
@@ -65,8 +104,14 @@ pub struct InteractiveShell {
     context: Vec<ContextItem>,
     /// Prompt optimizer for reducing token usage
     optimizer: PromptOptimizer,
+    /// Tokenizer of the active model
+    tokenizer: Arc<dyn Tokenizer>,
     /// Metrics tracker
     metrics: MetricsTracker,
+    /// Provider-reported prompt cache hits and misses
+    cache_tracker: Arc<CacheTracker>,
+    /// Spending caps, checked against the persisted spend history
+    budget: Arc<Budget>,
     /// Maximum token budget for conversation history
     max_history_tokens: usize,
     /// Total tokens in this session
@@ -78,7 +123,12 @@ pub struct InteractiveShell {
 impl InteractiveShell {
     /// Create a new interactive shell with Local→Primary→Fallback pipeline
     pub async fn new(config: Config) -> Result<Self> {
-        let (provider, model, fallback) = Self::build_providers(&config)?;
+        Self::with_registry(config, ProviderRegistry::with_builtins()).await
+    }
+
+    /// Create a shell whose providers are resolved through `registry`
+    pub async fn with_registry(config: Config, registry: ProviderRegistry) -> Result<Self> {
+        let (provider, model, fallback) = Self::build_providers(&config, &registry)?;
 
         // Build local agent for preprocessing if configured
         let local_agent = if config.local.enabled {
@@ -101,110 +151,178 @@ impl InteractiveShell {
 
         // Build prompt optimizer from config settings
         let opt_config = OptimizationConfig::from_settings(&config.optimization);
-        let optimizer = PromptOptimizer::new(opt_config, local_agent.clone());
+        let tokenizer = tokenizer_for_model(&model);
+        let optimizer =
+            PromptOptimizer::new(opt_config, local_agent.clone()).with_tokenizer(tokenizer.clone());
+        let metrics = MetricsTracker::new();
+        let mut router =
+            ComplexityRouter::new(config.routing.clone()).with_metrics(metrics.clone());
+        if let Some(agent) = local_agent.as_ref().filter(|_| config.routing.use_local_llm) {
+            router = router.with_local_agent(Arc::new(agent.clone()));
+        }
+        let cache_tracker = Arc::new(CacheTracker::default());
+        let budget = Arc::new(Budget::load(config.budget.clone(), Budget::default_path()));
+        let orchestrator = Self::build_orchestrator(
+            &config,
+            &registry,
+            [Some(&provider), fallback.as_ref()].into_iter().flatten(),
+            &metrics,
+            &cache_tracker,
+            &budget,
+            Self::fallback_optimizer(&config, local_agent.clone(), tokenizer.clone()),
+        );
 
         Ok(Self {
             config,
+            registry,
             provider,
             model,
             local_agent,
             fallback,
+            orchestrator,
+            router,
+            route_tasks: true,
             renderer: TerminalRenderer::new(),
             prompt_handler: PromptHandler::new(),
             conversation: Vec::new(),
             context: Vec::new(),
             optimizer,
-            metrics: MetricsTracker::new(),
+            tokenizer,
+            metrics,
+            cache_tracker,
+            budget,
             max_history_tokens: 8000,
             session_tokens: 0,
             turn_count: 0,
         })
     }
 
-    /// Build primary and optional fallback providers based on config
-    fn build_providers(config: &Config) -> Result<(ActiveProvider, String, Option<ActiveProvider>)> {
-        let mut primary: Option<(ActiveProvider, String)> = None;
-        let mut fallback: Option<ActiveProvider> = None;
-
-        // Try Venice as primary
-        if config.primary.enabled {
-            if let Some(api_key) = config.primary_api_key() {
-                let venice_config = VeniceConfig {
-                    api_key,
-                    model: config.primary.model.clone(),
-                    base_url: Some(config.primary.base_url.clone()),
-                    min_balance_usd: config.primary.min_balance_usd,
-                    min_balance_diem: config.primary.min_balance_diem,
-                    max_tokens: Some(config.primary.max_tokens),
-                    temperature: Some(config.primary.temperature),
-                };
-                let model = venice_config.model.clone();
-                primary = Some((
-                    ActiveProvider::Venice(Arc::new(VeniceProvider::new(venice_config))),
-                    model,
-                ));
+    /// Optimizer that re-compacts requests handed to the fallback or
+    /// rejected as too long, keeping the user's settings and tokenizer
+    fn fallback_optimizer(
+        config: &Config,
+        local_agent: Option<LocalAgent>,
+        tokenizer: Arc<dyn Tokenizer>,
+    ) -> PromptOptimizer {
+        let fallback_config = OptimizationConfig {
+            strategies: vec![
+                StrategyType::StripWhitespace,
+                StrategyType::RemoveComments,
+                StrategyType::TruncateContext,
+                StrategyType::Deduplicate,
+            ],
+            ..OptimizationConfig::from_settings(&config.optimization)
+        };
+        PromptOptimizer::new(fallback_config, local_agent).with_tokenizer(tokenizer)
+    }
+
+    /// Chain `providers`, then the budget's downgrade provider, into an
+    /// orchestrator that records into the shell's metrics, cache tracker
+    /// and budget
+    fn build_orchestrator<'a>(
+        config: &Config,
+        registry: &ProviderRegistry,
+        providers: impl IntoIterator<Item = &'a ActiveProvider>,
+        metrics: &MetricsTracker,
+        cache_tracker: &Arc<CacheTracker>,
+        budget: &Arc<Budget>,
+        optimizer: PromptOptimizer,
+    ) -> Orchestrator {
+        let orchestrator_config = OrchestratorConfig::from_settings(&config.orchestrator);
+        let policy = LinkPolicy::default().with_breaker(
+            BreakerPolicy::default().with_cooldown(orchestrator_config.recovery_cooldown),
+        );
+        let link = |name: &str, provider: Arc<dyn ChatProvider>, model: Option<&String>| {
+            let fallback = RegistryFallback::new(name, provider);
+            let fallback = match model {
+                Some(model) => fallback.with_model(model.clone()),
+                None => fallback,
+            };
+            FallbackLink::new(fallback).with_policy(policy.clone())
+        };
+
+        let mut chain: Vec<FallbackLink> = providers
+            .into_iter()
+            .map(|p| link(&p.spec.provider, p.inner.clone(), p.spec.model.as_ref()))
+            .collect();
+        if let Some(name) = budget.downgrade_to() {
+            if !chain.iter().any(|l| budget.is_downgrade(&l.budget_key())) {
+                match registry.build_from_config(config, name) {
+                    Ok((spec, inner)) => chain.push(link(name, inner.into(), spec.model.as_ref())),
+                    Err(e) => tracing::debug!("Downgrade provider '{}' unavailable: {}", name, e),
+                }
             }
         }
 
-        // Build fallback from fallback config (Claude/OpenAI)
-        if config.fallback.enabled {
-            if let Some(api_key) = config.fallback_api_key() {
-                let provider_type = match config.fallback.provider.as_str() {
-                    "claude" => ProviderType::Claude,
-                    "openai" => ProviderType::OpenAI,
-                    _ => ProviderType::Custom,
-                };
-                let base_url = match provider_type {
-                    ProviderType::Claude => {
-                        Some(format!("{}/messages", config.fallback.base_url))
-                    }
-                    _ => Some(format!("{}/chat/completions", config.fallback.base_url)),
-                };
-                let api_config = ApiConfig {
-                    provider: provider_type,
-                    api_key,
-                    base_url,
-                    model: config.fallback.model.clone(),
-                    max_tokens: Some(config.fallback.max_tokens),
-                    temperature: Some(config.fallback.temperature),
-                };
+        Orchestrator::with_chain(orchestrator_config, chain, metrics.clone())
+            .with_budget(budget.clone())
+            .with_cache_tracker(cache_tracker.clone())
+            .with_optimizer(optimizer)
+    }
 
+    /// Rebuild the orchestrator after the active provider changed
+    fn rebuild_orchestrator(&mut self) {
+        let optimizer = Self::fallback_optimizer(
+            &self.config,
+            self.local_agent.clone(),
+            self.tokenizer.clone(),
+        );
+        self.orchestrator = Self::build_orchestrator(
+            &self.config,
+            &self.registry,
+            [Some(&self.provider), self.fallback.as_ref()].into_iter().flatten(),
+            &self.metrics,
+            &self.cache_tracker,
+            &self.budget,
+            optimizer,
+        );
+    }
+
+    /// Build primary and optional fallback providers based on config
+    fn build_providers(
+        config: &Config,
+        registry: &ProviderRegistry,
+    ) -> Result<(ActiveProvider, String, Option<ActiveProvider>)> {
+        let build = |spec: ProviderSpec| {
+            let name = spec.provider.clone();
+            ActiveProvider::build(registry, &name, spec)
+                .map_err(|e| tracing::debug!("Provider '{}' unavailable: {}", name, e))
+                .ok()
+        };
+
+        // Primary provider (Venice by default)
+        let mut primary = if config.primary.enabled {
+            build(ProviderSpec::primary(config))
+        } else {
+            None
+        };
+
+        // Fallback provider (Claude/OpenAI/Gemini/...); promoted to primary
+        // when there is none
+        let mut fallback = None;
+        if config.fallback.enabled {
+            if let Some(provider) = build(ProviderSpec::fallback(config)) {
                 if primary.is_some() {
-                    // We have a primary, so this becomes the fallback
-                    fallback = Some(ActiveProvider::Api(Arc::new(ApiAgent::new(api_config))));
+                    fallback = Some(provider);
                 } else {
-                    // No primary available, promote fallback to primary
-                    let model = api_config.model.clone();
-                    primary = Some((
-                        ActiveProvider::Api(Arc::new(ApiAgent::new(api_config))),
-                        model,
-                    ));
+                    primary = Some(provider);
                 }
             }
         }
 
         // If still no primary, try local Ollama as direct provider
         if primary.is_none() && config.local.enabled {
-            let api_config = ApiConfig {
-                provider: ProviderType::Ollama,
-                api_key: String::new(),
-                base_url: Some(format!("{}/api/chat", config.local.url)),
-                model: config.local.model.clone(),
-                max_tokens: None,
-                temperature: Some(0.7),
-            };
-            let model = api_config.model.clone();
-            primary = Some((
-                ActiveProvider::Api(Arc::new(ApiAgent::new(api_config))),
-                model,
-            ));
+            primary = build(ProviderSpec::local(config));
         }
 
         match primary {
-            Some((provider, model)) => Ok((provider, model, fallback)),
+            Some(provider) => {
+                let model = provider.spec.model.clone().unwrap_or_default();
+                Ok((provider, model, fallback))
+            }
             None => anyhow::bail!(
-                "No provider available. Set VENICE_API_KEY, ANTHROPIC_API_KEY, or OPENAI_API_KEY, \
-                 or ensure Ollama is running locally."
+                "No provider available. Set VENICE_API_KEY, ANTHROPIC_API_KEY, OPENAI_API_KEY, \
+                 or GEMINI_API_KEY, or ensure Ollama is running locally."
             ),
         }
     }
@@ -271,6 +389,7 @@ impl InteractiveShell {
             }
             SlashCommand::Model(name) => {
                 if let Some(name) = name {
+                    self.route_tasks = false;
                     self.model = name.clone();
                     self.renderer
                         .render_success(&format!("Model set to: {}", name));
@@ -287,9 +406,13 @@ impl InteractiveShell {
                 }
             }
             SlashCommand::Provider(name) => {
-                if let Some(name) = name {
+                if name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case("auto")) {
+                    self.route_tasks = true;
+                    self.renderer.render_success("Routing each task by complexity");
+                } else if let Some(name) = name {
                     match self.switch_provider(&name) {
                         Ok(()) => {
+                            self.route_tasks = false;
                             self.renderer
                                 .render_success(&format!("Switched to provider: {}", self.provider.name()));
                         }
@@ -343,6 +466,25 @@ impl InteractiveShell {
         // Add conversation history
         request.messages = self.conversation.clone();
 
+        // Send the task to the cheapest model capable of it
+        if self.route_tasks {
+            let decision = self.router.route(&request).await;
+            match self.switch_route(&decision.target) {
+                Ok(()) => self.renderer.render_info(&format!(
+                    "Routed {} task ({}) to {}",
+                    decision.classification.complexity.as_str(),
+                    decision.classification.reason,
+                    decision.target
+                )),
+                Err(e) => self.renderer.render_info(&format!(
+                    "Route to {} unavailable ({}), using {}",
+                    decision.target,
+                    e,
+                    self.provider.name()
+                )),
+            }
+        }
+
         // Step 1: Preprocess with local agent if available
         if let Some(ref agent) = self.local_agent {
             let mut spinner = ThinkingSpinner::new();
@@ -377,83 +519,40 @@ impl InteractiveShell {
             }
         }
 
-        // Step 3: Start thinking spinner and try primary provider
+        // Step 3: Warn as the active provider nears its spending caps
+        if let BudgetCheck::Warn(reason) = self.budget.check(&self.provider.spec.provider) {
+            self.renderer.render_info(&format!("Budget warning: {}", reason));
+        }
+
+        // Step 4: Send down the provider chain. The orchestrator falls back
+        // on failures and caps, and shrinks requests rejected as too long.
+        let prefix = PrefixFingerprint::of(&request);
         let mut spinner = ThinkingSpinner::new();
         spinner.start("Thinking...");
-
-        let stream_result = match &self.provider {
-            ActiveProvider::Venice(provider) => provider.send_streaming(request.clone()).await,
-            ActiveProvider::Api(agent) => agent.send_streaming(request.clone()).await,
-        };
-
-        // Step 4: If primary fails and fallback exists, try fallback
-        let mut rx = match stream_result {
+        let mut rx = match self.orchestrator.execute_streaming(request).await {
             Ok(rx) => rx,
             Err(e) => {
                 spinner.stop();
-                if self.fallback.is_some() && Self::is_fallback_worthy(&e) {
-                    self.renderer.render_system(&format!(
-                        "Primary ({}) failed: {}. Switching to fallback...",
-                        self.provider.name(),
-                        e
-                    ));
-
-                    // Re-optimize with tighter budget for fallback
-                    let mut fallback_request = request;
-                    let fallback_config = OptimizationConfig {
-                        strategies: vec![
-                            StrategyType::StripWhitespace,
-                            StrategyType::RemoveComments,
-                            StrategyType::TruncateContext,
-                            StrategyType::Deduplicate,
-                        ],
-                        ..OptimizationConfig::from_settings(&self.config.optimization)
-                    };
-                    let fallback_optimizer =
-                        PromptOptimizer::new(fallback_config, self.local_agent.clone());
-                    if let Ok((optimized, stats)) =
-                        fallback_optimizer.optimize(fallback_request.clone()).await
-                    {
-                        if stats.tokens_saved > 0 {
-                            self.renderer.render_info(&format!(
-                                "Fallback re-optimized: saved {} more tokens",
-                                stats.tokens_saved
-                            ));
-                        }
-                        fallback_request = optimized;
-                    }
-
-                    let mut spinner = ThinkingSpinner::new();
-                    spinner.start("Trying fallback...");
-                    let fallback_result = match self.fallback.as_ref().unwrap() {
-                        ActiveProvider::Venice(provider) => {
-                            provider.send_streaming(fallback_request).await
-                        }
-                        ActiveProvider::Api(agent) => {
-                            agent.send_streaming(fallback_request).await
-                        }
-                    };
-                    match fallback_result {
-                        Ok(rx) => {
-                            spinner.stop();
-                            rx
-                        }
-                        Err(e2) => {
-                            spinner.stop();
-                            self.renderer
-                                .render_error(&format!("Fallback also failed: {}", e2));
-                            return;
-                        }
-                    }
-                } else {
-                    self.renderer
-                        .render_error(&format!("Request failed: {}", e));
-                    return;
-                }
+                self.renderer.render_error(&format!("Request failed: {}", e));
+                return;
             }
         };
+        let state = self.orchestrator.state().await;
+        if let Some(link) = state.active_link().filter(|_| state.active != Some(0)) {
+            let over_budget = matches!(
+                self.budget.check(&self.provider.spec.provider),
+                BudgetCheck::Exceeded(_)
+            );
+            let why = if over_budget { "over budget" } else { "unavailable" };
+            self.renderer.render_system(&format!(
+                "{} {}, answered by {}",
+                self.provider.name(),
+                why,
+                display_name(&link.name)
+            ));
+        }
 
-        // Step 4: Stream the response
+        // Step 5: Stream the response
         let mut full_response = String::new();
         let mut final_usage = TokenUsage::default();
         let mut first_token = true;
@@ -463,15 +562,32 @@ impl InteractiveShell {
                 StreamChunk::TextDelta(text) => {
                     if first_token {
                         spinner.stop();
-                        print!("\n");
+                        println!();
                         first_token = false;
                     }
                     full_response.push_str(&text);
                     self.renderer.render_delta(&text);
                 }
+                StreamChunk::ThinkingDelta(text) => {
+                    if first_token {
+                        spinner.stop();
+                        println!();
+                        first_token = false;
+                    }
+                    self.renderer.render_thinking_delta(&text);
+                }
+                StreamChunk::ToolUseStart { name, .. } => {
+                    if first_token {
+                        spinner.stop();
+                        first_token = false;
+                    }
+                    self.renderer.render_tool_call(&name);
+                }
+                StreamChunk::ToolInputDelta { .. } | StreamChunk::Stop(_) => {}
+                StreamChunk::Usage(usage) => final_usage.merge(&usage),
                 StreamChunk::Done(usage) => {
                     spinner.stop();
-                    final_usage = usage;
+                    final_usage.merge(&usage);
                     break;
                 }
                 StreamChunk::Error(msg) => {
@@ -508,35 +624,16 @@ impl InteractiveShell {
         );
 
         // Record in conversation history
-        self.conversation.push(Message {
-            role: Role::User,
-            content: input.to_string(),
-        });
-        self.conversation.push(Message {
-            role: Role::Assistant,
-            content: full_response,
-        });
-
-        // Update metrics
+        self.conversation.push(Message::user(input));
+        self.conversation.push(Message::assistant(full_response));
+
+        // Metrics, cache hits and spend are recorded by the orchestrator
         self.session_tokens += final_usage.total_tokens as u64;
         self.turn_count += 1;
-        self.metrics.record_request(
-            final_usage.prompt_tokens,
-            final_usage.completion_tokens,
-            0,
-            final_usage.estimated_cost_usd,
-        );
-    }
-
-    /// Check if an error warrants falling back to the secondary provider
-    fn is_fallback_worthy(error: &crate::api::ApiError) -> bool {
-        let msg = error.to_string().to_lowercase();
-        matches!(error, crate::api::ApiError::RateLimited { .. })
-            || msg.contains("insufficient")
-            || msg.contains("quota")
-            || msg.contains("balance")
-            || msg.contains("credits")
-            || msg.contains("429")
+        let strategy = cache_strategy_for(&self.provider.spec.provider, &self.model);
+        if let Some(report) = self.cache_tracker.check_prefix(prefix, &final_usage, &*strategy) {
+            self.renderer.render_info(&format!("Cache miss: {}", report));
+        }
     }
 
     /// Handle context management actions
@@ -604,142 +701,65 @@ impl InteractiveShell {
         self.model = model.to_string();
 
         // Rebuild the provider with the new model
-        match &self.provider {
-            ActiveProvider::Venice(_) => {
-                if let Some(api_key) = self.config.primary_api_key() {
-                    let venice_config = VeniceConfig {
-                        api_key,
-                        model: model.to_string(),
-                        base_url: Some(self.config.primary.base_url.clone()),
-                        min_balance_usd: self.config.primary.min_balance_usd,
-                        min_balance_diem: self.config.primary.min_balance_diem,
-                        max_tokens: Some(self.config.primary.max_tokens),
-                        temperature: Some(self.config.primary.temperature),
-                    };
-                    self.provider =
-                        ActiveProvider::Venice(Arc::new(VeniceProvider::new(venice_config)));
-                }
-            }
-            ActiveProvider::Api(agent) => {
-                let provider_type = agent.provider_type();
-                let (api_key, base_url) = match provider_type {
-                    ProviderType::Claude => (
-                        self.config.fallback_api_key().unwrap_or_default(),
-                        Some(format!("{}/messages", self.config.fallback.base_url)),
-                    ),
-                    ProviderType::OpenAI => (
-                        self.config.fallback_api_key().unwrap_or_default(),
-                        Some(format!(
-                            "{}/chat/completions",
-                            self.config.fallback.base_url
-                        )),
-                    ),
-                    ProviderType::Ollama => (
-                        String::new(),
-                        Some(format!("{}/api/chat", self.config.local.url)),
-                    ),
-                    ProviderType::Custom => (String::new(), None),
-                };
-                let api_config = ApiConfig {
-                    provider: provider_type,
-                    api_key,
-                    base_url,
-                    model: model.to_string(),
-                    max_tokens: Some(self.config.fallback.max_tokens),
-                    temperature: Some(self.config.fallback.temperature),
-                };
-                self.provider = ActiveProvider::Api(Arc::new(ApiAgent::new(api_config)));
-            }
-        }
+        let spec = self.provider.spec.clone().with_model(model.to_string());
+        let name = self.provider.name.clone();
+        self.provider = ActiveProvider::build(&self.registry, &name, spec)?;
+        self.set_tokenizer();
+        self.rebuild_orchestrator();
 
         Ok(())
     }
 
     /// Switch to a different provider
     fn switch_provider(&mut self, name: &str) -> Result<()> {
-        match name.to_lowercase().as_str() {
-            "venice" => {
-                let api_key = self
-                    .config
-                    .primary_api_key()
-                    .ok_or_else(|| anyhow::anyhow!("No Venice API key configured"))?;
-                let venice_config = VeniceConfig {
-                    api_key,
-                    model: self.config.primary.model.clone(),
-                    base_url: Some(self.config.primary.base_url.clone()),
-                    min_balance_usd: self.config.primary.min_balance_usd,
-                    min_balance_diem: self.config.primary.min_balance_diem,
-                    max_tokens: Some(self.config.primary.max_tokens),
-                    temperature: Some(self.config.primary.temperature),
-                };
-                self.model = venice_config.model.clone();
-                self.provider =
-                    ActiveProvider::Venice(Arc::new(VeniceProvider::new(venice_config)));
-            }
-            "claude" | "anthropic" => {
-                let api_key = self
-                    .config
-                    .claude_api_key()
-                    .ok_or_else(|| anyhow::anyhow!("No Anthropic API key configured"))?;
-                let api_config = ApiConfig {
-                    provider: ProviderType::Claude,
-                    api_key,
-                    base_url: Some(format!("{}/messages", self.config.fallback.base_url)),
-                    model: self.config.fallback.model.clone(),
-                    max_tokens: Some(self.config.fallback.max_tokens),
-                    temperature: Some(self.config.fallback.temperature),
-                };
-                self.model = api_config.model.clone();
-                self.provider = ActiveProvider::Api(Arc::new(ApiAgent::new(api_config)));
-            }
-            "openai" => {
-                let api_key = std::env::var("OPENAI_API_KEY")
-                    .or_else(|_| {
-                        self.config
-                            .fallback
-                            .api_key
-                            .clone()
-                            .ok_or(std::env::VarError::NotPresent)
-                    })
-                    .map_err(|_| anyhow::anyhow!("No OpenAI API key configured"))?;
-                let api_config = ApiConfig {
-                    provider: ProviderType::OpenAI,
-                    api_key,
-                    base_url: Some("https://api.openai.com/v1/chat/completions".to_string()),
-                    model: "gpt-4".to_string(),
-                    max_tokens: Some(4096),
-                    temperature: Some(0.7),
-                };
-                self.model = api_config.model.clone();
-                self.provider = ActiveProvider::Api(Arc::new(ApiAgent::new(api_config)));
-            }
-            "ollama" | "local" => {
-                let api_config = ApiConfig {
-                    provider: ProviderType::Ollama,
-                    api_key: String::new(),
-                    base_url: Some(format!("{}/api/chat", self.config.local.url)),
-                    model: self.config.local.model.clone(),
-                    max_tokens: None,
-                    temperature: Some(0.7),
-                };
-                self.model = api_config.model.clone();
-                self.provider = ActiveProvider::Api(Arc::new(ApiAgent::new(api_config)));
-            }
-            _ => {
-                anyhow::bail!(
-                    "Unknown provider: {}. Available: venice, claude, openai, ollama",
-                    name
-                );
-            }
+        let name = name.to_lowercase();
+        if !self.config.providers.contains_key(&name) && !self.registry.contains(&name) {
+            let mut available = self.registry.names();
+            available.extend(self.config.providers.keys().cloned());
+            anyhow::bail!(
+                "Unknown provider: {}. Available: {}",
+                name,
+                available.join(", ")
+            );
         }
+        let (spec, inner) = self.registry.build_from_config(&self.config, &name)?;
+        let key = self.registry.resolve(&name).unwrap_or(name);
+        self.model = spec.model.clone().unwrap_or_default();
+        self.provider = ActiveProvider {
+            name: display_name(&key),
+            spec,
+            inner: Arc::from(inner),
+        };
+        self.set_tokenizer();
+        self.rebuild_orchestrator();
         Ok(())
     }
 
+    /// Switch to the provider and model a task was routed to, unless they
+    /// are already active
+    fn switch_route(&mut self, target: &RouteTarget) -> Result<()> {
+        if !self.provider.spec.provider.eq_ignore_ascii_case(&target.provider) {
+            self.switch_provider(&target.provider)?;
+        }
+        match &target.model {
+            Some(model) if *model != self.model => self.switch_model(model),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count tokens with the active model's tokenizer
+    fn set_tokenizer(&mut self) {
+        self.tokenizer = tokenizer_for_model(&self.model);
+        let opt_config = OptimizationConfig::from_settings(&self.config.optimization);
+        self.optimizer = PromptOptimizer::new(opt_config, self.local_agent.clone())
+            .with_tokenizer(self.tokenizer.clone());
+    }
+
     /// Count total tokens in conversation history
     fn history_token_count(&self) -> usize {
         self.conversation
             .iter()
-            .map(|m| count_tokens(&m.content))
+            .map(|m| self.tokenizer.count(&m.content))
             .sum()
     }
 
@@ -810,6 +830,36 @@ impl InteractiveShell {
             "Context files:".with(self.renderer.dim_color()),
             format!("{}", self.context.len()).with(self.renderer.stats_color()),
         );
+        let cache = self.cache_tracker.get_metrics();
+        if cache.cache_hits + cache.cache_misses > 0 {
+            println!(
+                "  {} {}",
+                "Cache hit rate:".with(self.renderer.dim_color()),
+                format!("{:.0}% ({} tokens read)", cache.hit_rate * 100.0, cache.cached_tokens)
+                    .with(self.renderer.stats_color()),
+            );
+        }
+        let summary = self.cache_tracker.summary();
+        if let Some(accuracy) = summary.prediction_accuracy {
+            println!(
+                "  {} {}",
+                "Cache predictions:".with(self.renderer.dim_color()),
+                format!(
+                    "{:.0}% accurate, observed TTL {}s{}",
+                    accuracy * 100.0,
+                    summary.ttl_secs,
+                    if summary.is_drifting() { " (drifting)" } else { "" }
+                )
+                .with(self.renderer.stats_color()),
+            );
+        }
+        for (route, count) in self.metrics.get_metrics().routes {
+            println!(
+                "  {} {}",
+                "Routed:".with(self.renderer.dim_color()),
+                format!("{} ({}x)", route, count).with(self.renderer.stats_color()),
+            );
+        }
         println!();
     }
 
@@ -860,7 +910,7 @@ impl InteractiveShell {
         }
 
         // Show Venice balance if applicable
-        if let ActiveProvider::Venice(venice) = &self.provider {
+        if let Some(venice) = self.provider.venice() {
             let balance = venice.get_balance().await;
             if balance.last_updated.is_some() {
                 println!(
@@ -47,3 +47,9 @@ impl PromptHandler {
         self.history.len()
     }
 }
+
+impl Default for PromptHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -95,6 +95,22 @@ impl TerminalRenderer {
         let _ = std::io::stdout().flush();
     }
 
+    /// Render a streaming thinking/reasoning delta (dimmed)
+    pub fn render_thinking_delta(&self, text: &str) {
+        use std::io::Write;
+        print!("{}", text.with(self.theme.dim));
+        let _ = std::io::stdout().flush();
+    }
+
+    /// Render a notice that the model is calling a tool
+    pub fn render_tool_call(&self, name: &str) {
+        println!(
+            "\n  {} {}",
+            "\u{2699}".with(self.theme.system),
+            format!("tool call: {}", name).with(self.theme.system)
+        );
+    }
+
     /// Render a complete response with markdown formatting
     pub fn render_markdown(&self, content: &str) {
         // Only re-render with markdown if content has markdown elements
@@ -179,6 +195,12 @@ impl TerminalRenderer {
     }
 }
 
+impl Default for TerminalRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Check if content has markdown elements worth re-rendering
 fn has_markdown_elements(content: &str) -> bool {
     content.contains("```")
@@ -45,6 +45,12 @@ impl ThinkingSpinner {
     }
 }
 
+impl Default for ThinkingSpinner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Drop for ThinkingSpinner {
     fn drop(&mut self) {
         self.stop();